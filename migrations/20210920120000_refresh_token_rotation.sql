@@ -0,0 +1,21 @@
+-- Tokens created by rotation share the family of the original token.
+-- Retired tokens have been rotated, and using them again revokes the whole family.
+ALTER TABLE refresh_tokens
+    ADD COLUMN family_id    uuid NOT NULL DEFAULT uuid_generate_v4(),
+    ADD COLUMN rotate       BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN retired      TIMESTAMPTZ;
+
+CREATE INDEX refresh_tokens_family ON refresh_tokens (family_id);
+
+CREATE TABLE IF NOT EXISTS security_events (
+    id                  uuid DEFAULT uuid_generate_v4(),
+    created             TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id             uuid,
+    kind                VARCHAR(50) NOT NULL,
+    client_address      VARCHAR(100),
+    details             TEXT,
+    PRIMARY KEY (id),
+    CONSTRAINT security_event_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,16 @@
+-- Tokens are looked up by a public prefix and checked against a keyed hash.
+-- Existing plaintext tokens are hashed and cleared by the application on startup,
+-- since the hash key is not available here.
+ALTER TABLE refresh_tokens DROP CONSTRAINT refresh_tokens_pk;
+ALTER TABLE refresh_tokens ADD CONSTRAINT refresh_tokens_pk PRIMARY KEY (id);
+ALTER TABLE refresh_tokens ALTER COLUMN token_string DROP NOT NULL;
+
+ALTER TABLE refresh_tokens
+    ADD COLUMN token_prefix VARCHAR(12),
+    ADD COLUMN token_hash   VARCHAR(64);
+
+UPDATE refresh_tokens SET token_prefix = LEFT(token_string, 12);
+
+ALTER TABLE refresh_tokens ALTER COLUMN token_prefix SET NOT NULL;
+
+CREATE INDEX refresh_tokens_prefix ON refresh_tokens (token_prefix);
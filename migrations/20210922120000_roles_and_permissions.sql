@@ -0,0 +1,48 @@
+-- Permissions are defined by the application, roles and assignments by admins.
+CREATE TABLE IF NOT EXISTS permissions (
+    name            VARCHAR(50) NOT NULL,
+    description     TEXT,
+    PRIMARY KEY (name)
+);
+
+INSERT INTO permissions (name, description) VALUES
+    ('roles.manage', 'Create roles and assign them to users.'),
+    ('groups.manage', 'Add users to groups and remove them from groups.'),
+    ('sessions.revoke', 'Revoke the sessions of other users.')
+ON CONFLICT DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS roles (
+    id              uuid DEFAULT uuid_generate_v4(),
+    created         TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    modified        TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    name            VARCHAR(50) NOT NULL UNIQUE,
+    description     TEXT,
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role_id         uuid NOT NULL,
+    permission      VARCHAR(50) NOT NULL,
+    PRIMARY KEY (role_id, permission),
+    CONSTRAINT role_permission_role
+        FOREIGN KEY(role_id)
+            REFERENCES roles(id) ON DELETE CASCADE,
+    CONSTRAINT role_permission_permission
+        FOREIGN KEY(permission)
+            REFERENCES permissions(name) ON DELETE CASCADE
+);
+
+-- Assignments without an expiry time are permanent.
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id         uuid NOT NULL,
+    role_id         uuid NOT NULL,
+    created         TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires         TIMESTAMPTZ,
+    PRIMARY KEY (user_id, role_id),
+    CONSTRAINT user_role_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT user_role_role
+        FOREIGN KEY(role_id)
+            REFERENCES roles(id) ON DELETE CASCADE
+);
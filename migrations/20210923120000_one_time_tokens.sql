@@ -0,0 +1,18 @@
+-- Single-use tokens sent to users, like password reset links.
+-- Only a keyed hash of the token is stored, like with refresh tokens.
+CREATE TABLE IF NOT EXISTS one_time_tokens (
+    id                  uuid DEFAULT uuid_generate_v4(),
+    created             TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires             TIMESTAMPTZ NOT NULL,
+    used                TIMESTAMPTZ,
+    user_id             uuid NOT NULL,
+    kind                VARCHAR(50) NOT NULL,
+    token_prefix        VARCHAR(12) NOT NULL,
+    token_hash          TEXT NOT NULL,
+    PRIMARY KEY (id),
+    CONSTRAINT one_time_token_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX one_time_tokens_prefix ON one_time_tokens (token_prefix);
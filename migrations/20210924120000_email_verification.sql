@@ -0,0 +1,11 @@
+-- A changed address is kept pending until it's confirmed, the old one stays in use.
+ALTER TABLE users
+    ADD COLUMN email_verified   BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN pending_email    VARCHAR(100);
+
+-- Existing addresses were accepted before verification existed,
+-- so requiring verification doesn't lock those users out.
+UPDATE users SET email_verified = TRUE WHERE email IS NOT NULL;
+
+-- The address a verification token confirms.
+ALTER TABLE one_time_tokens ADD COLUMN payload TEXT;
@@ -0,0 +1,28 @@
+-- TOTP secrets. Two-factor authentication is enabled once the secret is confirmed.
+CREATE TABLE IF NOT EXISTS totp_secrets (
+    user_id         uuid NOT NULL,
+    created         TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    secret          BYTEA NOT NULL,
+    confirmed       TIMESTAMPTZ,
+    -- The time step of the last accepted code, a code can't be used twice.
+    last_step       BIGINT,
+    PRIMARY KEY (user_id),
+    CONSTRAINT totp_secret_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Single-use codes for when the authenticator is lost, stored hashed.
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id              uuid DEFAULT uuid_generate_v4(),
+    created         TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id         uuid NOT NULL,
+    code_hash       TEXT NOT NULL,
+    used            TIMESTAMPTZ,
+    PRIMARY KEY (id),
+    CONSTRAINT recovery_code_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX recovery_codes_user ON recovery_codes (user_id);
@@ -0,0 +1,19 @@
+-- WebAuthn credentials. The public key is a DER encoded SubjectPublicKeyInfo.
+CREATE TABLE IF NOT EXISTS passkeys (
+    id              uuid DEFAULT uuid_generate_v4(),
+    created         TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id         uuid NOT NULL,
+    name            VARCHAR(50),
+    credential_id   BYTEA NOT NULL UNIQUE,
+    public_key      BYTEA NOT NULL,
+    -- COSE algorithm identifier
+    algorithm       BIGINT NOT NULL,
+    sign_count      BIGINT NOT NULL DEFAULT 0,
+    last_used       TIMESTAMPTZ,
+    PRIMARY KEY (id),
+    CONSTRAINT passkey_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX passkeys_user ON passkeys (user_id);
@@ -0,0 +1,4 @@
+-- Lockouts themselves are kept in Redis, only the permission to end them is stored here.
+INSERT INTO permissions (name, description) VALUES
+    ('users.unlock', 'End the login lockout of an username.')
+ON CONFLICT DO NOTHING;
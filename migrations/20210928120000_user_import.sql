@@ -0,0 +1,5 @@
+-- Imported users keep their bcrypt or PBKDF2 hash, tagged with the algorithm,
+-- until it's replaced with an argon2id hash on their first login.
+INSERT INTO permissions (name, description) VALUES
+    ('users.import', 'Create users with password hashes from other systems.')
+ON CONFLICT DO NOTHING;
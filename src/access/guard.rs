@@ -0,0 +1,171 @@
+//! Authorization guards for GraphQL resolvers.
+//! Resolvers declare their requirements with the guard attribute,
+//! e.g. `#[graphql(guard(Permission(name = "permission::MANAGE_ROLES")))]`.
+//! Guards can be combined with `and(..)` and `or(..)`.
+//! The generated code calls `Guard::check`, so the trait has to be imported with the guards.
+
+pub use async_graphql::guard::Guard;
+
+use super::{authenticated, current_permissions, current_user};
+use crate::gql::E;
+use async_graphql::{Context, Result};
+
+/// Members can manage other users.
+pub const ADMIN: &str = "admin";
+
+/// The request has to have a valid JWT.
+pub struct RequireAuth;
+
+#[async_trait::async_trait]
+impl Guard for RequireAuth {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        authenticated(ctx)?;
+
+        Ok(())
+    }
+}
+
+/// The authenticated user has to be a member of the group.
+/// Membership is checked from the live user record, not the JWT claims.
+pub struct RequireGroup {
+    pub group: &'static str,
+}
+
+#[async_trait::async_trait]
+impl Guard for RequireGroup {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        let user = current_user(ctx).await?;
+
+        if !user.groups.iter().any(|group| group == self.group) {
+            return Err(
+                E::Forbidden(format!("membership in group '{}' required", self.group)).into(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The authenticated user has to be a member of atleast one of the groups.
+pub struct RequireAnyGroup {
+    pub groups: &'static [&'static str],
+}
+
+#[async_trait::async_trait]
+impl Guard for RequireAnyGroup {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        let user = current_user(ctx).await?;
+
+        if !user
+            .groups
+            .iter()
+            .any(|group| self.groups.contains(&group.as_str()))
+        {
+            return Err(E::Forbidden(format!(
+                "membership in one of the groups '{}' required",
+                self.groups.join("', '")
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// The authenticated user has to have the permission through one of their active roles.
+/// Members of the `admin` group have every permission.
+/// Permission names are listed in `models::role::permission`.
+pub struct Permission {
+    pub name: &'static str,
+}
+
+#[async_trait::async_trait]
+impl Guard for Permission {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if (RequireGroup { group: ADMIN }).check(ctx).await.is_ok() {
+            return Ok(());
+        }
+
+        if !current_permissions(ctx)
+            .await?
+            .iter()
+            .any(|p| p == self.name)
+        {
+            return Err(E::Forbidden(format!("permission '{}' required", self.name)).into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::SqlxConn,
+        macros::{unique_name, TEST_JWT},
+        Config, CONF_FILE,
+    };
+    use async_graphql::{EmptyMutation, EmptySubscription, Object, Request, Response, Schema};
+
+    const STAFF: &[&str] = &[ADMIN, "staff"];
+
+    struct GuardedQuery;
+
+    #[Object]
+    impl GuardedQuery {
+        #[graphql(guard(RequireGroup(group = "ADMIN")))]
+        async fn admin(&self) -> bool {
+            true
+        }
+
+        #[graphql(guard(RequireAnyGroup(groups = "STAFF")))]
+        async fn staff(&self) -> bool {
+            true
+        }
+    }
+
+    /// Run the query as a new user in the groups.
+    async fn as_member(groups: &[&str], query: &str) -> Response {
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+
+        let username = unique_name("guard_user");
+        gql_test_user!(&username);
+        let jwt = gql_test_login!(&username, "password_of_20_characters");
+
+        let groups: Vec<String> = groups.iter().map(|group| group.to_string()).collect();
+        sqlx::query!(
+            "UPDATE users SET groups = $1 WHERE username = $2",
+            &groups,
+            username
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let claims = TEST_JWT
+            .decode(&jwt, TEST_JWT.audience())
+            .await
+            .unwrap()
+            .claims;
+
+        Schema::new(GuardedQuery, EmptyMutation, EmptySubscription)
+            .execute(Request::new(query).data(pool).data(claims))
+            .await
+    }
+
+    #[tokio::test]
+    async fn require_group() {
+        assert!(as_member(&[ADMIN], "{ admin }").await.is_ok());
+        assert!(as_member(&["staff"], "{ admin }").await.is_err());
+        assert!(as_member(&[], "{ admin }").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn require_any_group() {
+        assert!(as_member(&[ADMIN], "{ staff }").await.is_ok());
+        assert!(as_member(&["staff"], "{ staff }").await.is_ok());
+        assert!(as_member(&["other"], "{ staff }").await.is_err());
+    }
+}
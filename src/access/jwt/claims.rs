@@ -0,0 +1,101 @@
+use crate::models::user::User;
+use async_graphql::{ComplexObject, Context, Result, SimpleObject};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Profile fields that can be copied into the claims.
+/// Configured with `profile_claims` in the `[jwt]` section, none are included by default.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileClaim {
+    Username,
+    Email,
+    DisplayName,
+    Groups,
+    /// Effective permissions of the user's active roles at the time of signing.
+    Permissions,
+}
+
+/// JWT token claims that are encoded and decoded.
+/// Only identifies the user, the full record is loaded by `sub` when needed.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+#[graphql(complex)]
+pub struct JwtClaims {
+    /// The user the token was issued to.
+    pub sub: Uuid,
+    /// Who issued the token.
+    pub iss: String,
+    /// Who the token is intended for.
+    pub aud: String,
+    /// Unique identifier of this token.
+    pub jti: Uuid,
+    /// The refresh token this token was signed with.
+    pub parent_token: Uuid,
+    /// The token is not valid before this time in seconds since the Epoch.
+    pub nbf: i64,
+    /// The time of issuing the token in seconds since the Epoch.
+    pub iat: i64,
+    /// The exporation time in seconds since the Epoch.
+    pub exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Vec<String>>,
+}
+
+impl JwtClaims {
+    /// Create new claims for the user, valid from now for `exp_secs`.
+    /// Only the listed profile fields are included.
+    pub fn new(
+        user: &User,
+        issuer: &str,
+        audience: &str,
+        exp_secs: i64,
+        parent_token: Uuid,
+        profile: &[ProfileClaim],
+    ) -> JwtClaims {
+        let now = Utc::now().timestamp();
+        let include = |claim: ProfileClaim| profile.contains(&claim);
+
+        JwtClaims {
+            sub: user.id,
+            iss: issuer.into(),
+            aud: audience.into(),
+            jti: Uuid::new_v4(),
+            parent_token,
+            nbf: now,
+            iat: now,
+            exp: now + exp_secs,
+            username: Some(user.username.clone()).filter(|_| include(ProfileClaim::Username)),
+            email: user.email.clone().filter(|_| include(ProfileClaim::Email)),
+            display_name: user
+                .display_name
+                .clone()
+                .filter(|_| include(ProfileClaim::DisplayName)),
+            groups: Some(user.groups.clone()).filter(|_| include(ProfileClaim::Groups)),
+            // Loaded separately, see `JWT::includes`
+            permissions: None,
+        }
+    }
+
+    /// Load the current state of the user the token was issued to.
+    pub async fn load_user(&self, pool: &PgPool) -> anyhow::Result<User> {
+        User::from_id(pool, self.sub).await
+    }
+}
+
+#[ComplexObject]
+impl JwtClaims {
+    /// The live user record, not the state at the time of signing.
+    async fn user(&self, ctx: &Context<'_>) -> Result<User> {
+        Ok(self.load_user(ctx.data::<PgPool>()?).await?)
+    }
+}
@@ -0,0 +1,156 @@
+use super::JwtClaims;
+use crate::db::RedisConn;
+use anyhow::Result;
+use chrono::Utc;
+use redis::AsyncCommands;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// How long a lookup result is trusted without asking Redis again.
+/// A revocation made by another instance takes at most this long to be noticed.
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Cache size after which expired entries are pruned.
+const CACHE_PRUNE_LEN: usize = 10_000;
+
+/// Revoked JWTs, by their `jti` or by the refresh token they were signed with.
+/// Entries live in Redis only as long as the tokens they revoke could be valid.
+#[derive(Clone)]
+pub struct Denylist {
+    redis_conn: RedisConn,
+    /// Recent lookups by `jti`: whether the token was revoked and when that was checked.
+    cache: Arc<Mutex<HashMap<Uuid, (bool, Instant)>>>,
+}
+
+impl Denylist {
+    pub fn new(redis_conn: RedisConn) -> Self {
+        Denylist {
+            redis_conn,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn jti_key(jti: &Uuid) -> String {
+        format!("JWT_DENY_JTI_{}", jti)
+    }
+
+    fn parent_key(parent_token: &Uuid) -> String {
+        format!("JWT_DENY_PARENT_{}", parent_token)
+    }
+
+    /// Revoke a single token until it expires.
+    pub async fn revoke_jwt(&self, claims: &JwtClaims) -> Result<()> {
+        let ttl = claims.exp - Utc::now().timestamp();
+
+        // Already expired, nothing to revoke
+        if ttl <= 0 {
+            return Ok(());
+        }
+
+        self.deny(Self::jti_key(&claims.jti), ttl as usize).await
+    }
+
+    /// Revoke every token signed with the refresh token.
+    /// The lifetime should be the longest a JWT signed with it can live.
+    pub async fn revoke_parent(&self, parent_token: &Uuid, max_jwt_lifetime: i64) -> Result<()> {
+        if max_jwt_lifetime <= 0 {
+            return Ok(());
+        }
+
+        self.deny(Self::parent_key(parent_token), max_jwt_lifetime as usize)
+            .await
+    }
+
+    async fn deny(&self, key: String, ttl_secs: usize) -> Result<()> {
+        let mut con = self.redis_conn.conn_async().await?;
+
+        con.set_ex::<_, _, ()>(&key, 1, ttl_secs).await?;
+
+        // Cached negative results might now be wrong
+        self.lock_cache()?.clear();
+
+        Ok(())
+    }
+
+    /// Check both the token itself and it's parent refresh token in a single round trip.
+    /// Results are cached for a short while.
+    pub async fn is_revoked(&self, claims: &JwtClaims) -> Result<bool> {
+        if let Some((revoked, checked)) = self.lock_cache()?.get(&claims.jti) {
+            if checked.elapsed() < CACHE_TTL {
+                return Ok(*revoked);
+            }
+        }
+
+        let mut con = self.redis_conn.conn_async().await?;
+
+        let found: u64 = redis::cmd("EXISTS")
+            .arg(Self::jti_key(&claims.jti))
+            .arg(Self::parent_key(&claims.parent_token))
+            .query_async(&mut con)
+            .await?;
+
+        let revoked = found > 0;
+
+        let mut cache = self.lock_cache()?;
+
+        if cache.len() >= CACHE_PRUNE_LEN {
+            cache.retain(|_, (_, checked)| checked.elapsed() < CACHE_TTL);
+        }
+
+        cache.insert(claims.jti, (revoked, Instant::now()));
+
+        Ok(revoked)
+    }
+
+    fn lock_cache(&self) -> Result<std::sync::MutexGuard<'_, HashMap<Uuid, (bool, Instant)>>> {
+        self.cache
+            .lock()
+            .map_err(|_| anyhow!("Denylist cache lock is poisoned."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, CONF_FILE};
+
+    fn test_claims() -> JwtClaims {
+        let now = Utc::now().timestamp();
+
+        JwtClaims {
+            sub: Uuid::new_v4(),
+            iss: "dia".into(),
+            aud: "dia".into(),
+            jti: Uuid::new_v4(),
+            parent_token: Uuid::new_v4(),
+            nbf: now,
+            iat: now,
+            exp: now + 600,
+            username: None,
+            email: None,
+            display_name: None,
+            groups: None,
+            permissions: None,
+        }
+    }
+
+    /// Revoking the parent refresh token should revoke the tokens signed with it.
+    #[tokio::test]
+    async fn revoke_by_parent() {
+        let denylist = Denylist::new(RedisConn::new(&Config::from_file(CONF_FILE)));
+        let claims = test_claims();
+
+        assert!(!denylist.is_revoked(&claims).await.unwrap());
+
+        denylist
+            .revoke_parent(&claims.parent_token, 600)
+            .await
+            .unwrap();
+
+        assert!(denylist.is_revoked(&claims).await.unwrap());
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::Result;
+use async_graphql::SimpleObject;
+use chrono::prelude::*;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use openssl::{
+    bn::{BigNum, BigNumContext, BigNumRef},
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::{Id, PKey, Private},
+    rsa::Rsa,
+};
+use serde::Serialize;
+use std::{
+    fs::{self, read_dir, read_to_string},
+    path::Path,
+};
+use uuid::Uuid;
+
+/// Byte length of a P-256 coordinate and of an Ed25519 public key.
+const COORDINATE_LEN: usize = 32;
+
+/// A single signing key. Tokens signed with it carry its `kid` in the header,
+/// so the right key and algorithm can be picked when decoding.
+#[derive(Clone)]
+pub struct JwtKey {
+    /// Key identifier, also the file stem when the key is stored on disk.
+    pub kid: String,
+    /// When the key was generated. Used to schedule rotation and retirement.
+    pub created: DateTime<Utc>,
+    /// RS256, ES256 or EdDSA, depending on the key type.
+    pub algorithm: Algorithm,
+    /// The private key in PKCS#8 PEM format.
+    private_key: Vec<u8>,
+    /// The public key in PEM format.
+    pub public_key: Vec<u8>,
+    /// The public key as a JWK, computed once when loading.
+    jwk: Jwk,
+    /// Based on `self.private_key` to encode tokens.
+    pub(super) encoding: EncodingKey,
+    /// Based on `self.public_key` to decode tokens.
+    pub(super) decoding: DecodingKey,
+}
+
+impl JwtKey {
+    /// Generate a new key for the algorithm with a random identifier.
+    /// RSA keys are 4096 bits, ES256 uses P-256 and EdDSA Ed25519.
+    /// CPU intensive for RSA, use `spawn_blocking` in an asynchronous context.
+    pub fn generate(algorithm: Algorithm) -> Result<Self> {
+        let pkey = match algorithm {
+            Algorithm::RS256 => PKey::from_rsa(Rsa::generate(4096)?)?,
+            Algorithm::ES256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+
+                PKey::from_ec_key(EcKey::generate(&group)?)?
+            }
+            Algorithm::EdDSA => PKey::generate_ed25519()?,
+            _ => bail!("Unsupported JWT algorithm {:?}.", algorithm),
+        };
+
+        Self::from_pkey(Uuid::new_v4().to_simple().to_string(), Utc::now(), pkey)
+    }
+
+    pub fn from_rsa(kid: String, created: DateTime<Utc>, rsa: Rsa<Private>) -> Result<Self> {
+        Self::from_pkey(kid, created, PKey::from_rsa(rsa)?)
+    }
+
+    /// Build a key from an RSA, P-256 or Ed25519 private key. The algorithm follows the key type.
+    pub fn from_pkey(kid: String, created: DateTime<Utc>, pkey: PKey<Private>) -> Result<Self> {
+        let private = pkey.private_key_to_pem_pkcs8()?;
+        let public = pkey.public_key_to_pem()?;
+
+        let (algorithm, encoding, decoding, params) = match pkey.id() {
+            Id::RSA => {
+                let rsa = pkey.rsa()?;
+
+                (
+                    Algorithm::RS256,
+                    EncodingKey::from_rsa_pem(&private)?,
+                    DecodingKey::from_rsa_pem(&public)?,
+                    JwkParams {
+                        kty: "RSA",
+                        n: Some(base64url(&rsa.n().to_vec())),
+                        e: Some(base64url(&rsa.e().to_vec())),
+                        ..Default::default()
+                    },
+                )
+            }
+            Id::EC => {
+                let ec = pkey.ec_key()?;
+
+                if ec.group().curve_name() != Some(Nid::X9_62_PRIME256V1) {
+                    bail!("Only P-256 elliptic curve keys are supported.");
+                }
+
+                let mut ctx = BigNumContext::new()?;
+                let mut x = BigNum::new()?;
+                let mut y = BigNum::new()?;
+                ec.public_key()
+                    .affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut ctx)?;
+
+                (
+                    Algorithm::ES256,
+                    EncodingKey::from_ec_pem(&private)?,
+                    DecodingKey::from_ec_pem(&public)?,
+                    JwkParams {
+                        kty: "EC",
+                        crv: Some("P-256"),
+                        x: Some(base64url(&padded(&x))),
+                        y: Some(base64url(&padded(&y))),
+                        ..Default::default()
+                    },
+                )
+            }
+            Id::ED25519 => {
+                // The raw key is the end of the DER encoded SubjectPublicKeyInfo
+                let der = pkey.public_key_to_der()?;
+
+                (
+                    Algorithm::EdDSA,
+                    EncodingKey::from_ed_pem(&private)?,
+                    DecodingKey::from_ed_pem(&public)?,
+                    JwkParams {
+                        kty: "OKP",
+                        crv: Some("Ed25519"),
+                        x: Some(base64url(&der[der.len() - COORDINATE_LEN..])),
+                        ..Default::default()
+                    },
+                )
+            }
+            id => bail!("Unsupported JWT key type {:?}.", id),
+        };
+
+        Ok(JwtKey {
+            jwk: params.into_jwk(&kid, algorithm),
+            kid,
+            created,
+            algorithm,
+            encoding,
+            decoding,
+            public_key: public,
+            private_key: private,
+        })
+    }
+
+    /// Load a private key from a PEM file and check it's validity.
+    /// The file stem is used as the key identifier and the modification time as the creation time.
+    pub fn from_pem_file(path: &Path) -> Result<Self> {
+        let contents = read_to_string(path)?.as_bytes().to_vec();
+
+        let kid = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem.to_string(),
+            None => bail!("Invalid JWT key file name: {}.", path.display()),
+        };
+
+        let created: DateTime<Utc> = fs::metadata(path)?.modified()?.into();
+
+        Self::from_pkey(kid, created, PKey::private_key_from_pem(&contents)?)
+    }
+
+    /// Load every `*.pem` file in the directory, creating the directory if it's missing.
+    pub fn load_dir(dir: &Path) -> Result<Vec<Self>> {
+        fs::create_dir_all(dir)?;
+
+        let mut keys = vec![];
+
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|ext| ext.to_str()) == Some("pem") {
+                keys.push(Self::from_pem_file(&path)?);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Write the private key to `<dir>/<kid>.pem`, readable only by the owner.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(format!("{}.pem", self.kid));
+
+        fs::write(&path, &self.private_key)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove `<dir>/<kid>.pem`. Another instance sharing the directory
+    /// might have removed it already, which is not an error.
+    pub fn remove(&self, dir: &Path) -> Result<()> {
+        match fs::remove_file(dir.join(format!("{}.pem", self.kid))) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The public key as a JSON Web Key.
+    pub fn jwk(&self) -> Jwk {
+        self.jwk.clone()
+    }
+
+    /// The public key in both formats.
+    pub fn public(&self) -> Result<PublicKey> {
+        Ok(PublicKey {
+            kid: self.kid.clone(),
+            algorithm: format!("{:?}", self.algorithm),
+            pem: std::str::from_utf8(&self.public_key)?.to_string(),
+            jwk: self.jwk(),
+        })
+    }
+}
+
+/// Key type specific JWK members.
+#[derive(Default)]
+struct JwkParams {
+    kty: &'static str,
+    crv: Option<&'static str>,
+    n: Option<String>,
+    e: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+impl JwkParams {
+    fn into_jwk(self, kid: &str, algorithm: Algorithm) -> Jwk {
+        Jwk {
+            kty: self.kty.into(),
+            key_use: "sig".into(),
+            alg: format!("{:?}", algorithm),
+            kid: kid.into(),
+            crv: self.crv.map(String::from),
+            n: self.n,
+            e: self.e,
+            x: self.x,
+            y: self.y,
+        }
+    }
+}
+
+/// Unpadded base64url, as used in JWKs.
+fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Left pad an elliptic curve coordinate to it's full length.
+fn padded(number: &BigNumRef) -> Vec<u8> {
+    let bytes = number.to_vec();
+    let mut padded = vec![0u8; COORDINATE_LEN.saturating_sub(bytes.len())];
+    padded.extend(bytes);
+
+    padded
+}
+
+/// A public key in the JSON Web Key format (RFC 7517).
+/// Only the members of the key type are set.
+#[derive(Debug, Clone, Serialize, SimpleObject)]
+pub struct Jwk {
+    /// `RSA`, `EC` or `OKP`.
+    pub kty: String,
+    #[serde(rename = "use")]
+    #[graphql(name = "use")]
+    pub key_use: String,
+    pub alg: String,
+    pub kid: String,
+    /// Curve of `EC` and `OKP` keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    /// Base64url encoded RSA modulus.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// Base64url encoded RSA public exponent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// Base64url encoded x coordinate, or the public key of `OKP` keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    /// Base64url encoded y coordinate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+/// A JSON Web Key Set, served from `/.well-known/jwks.json`.
+#[derive(Debug, Serialize, SimpleObject)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// The current signing key for clients that only need one key.
+#[derive(Debug, SimpleObject)]
+pub struct PublicKey {
+    pub kid: String,
+    /// `RS256`, `ES256` or `EdDSA`.
+    pub algorithm: String,
+    /// The public key in PEM format.
+    pub pem: String,
+    pub jwk: Jwk,
+}
@@ -0,0 +1,600 @@
+mod claims;
+mod denylist;
+mod key;
+
+pub use claims::{JwtClaims, ProfileClaim};
+pub use denylist::Denylist;
+pub use key::{Jwks, JwtKey, PublicKey};
+
+use crate::{models::user::User, res::Res, Config};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use anyhow::Result;
+use chrono::{prelude::*, Duration};
+use futures::future::{err, ok, Ready};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, Header, TokenData, Validation};
+use openssl::{pkey::Private, rsa::Rsa};
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tokio::task::spawn_blocking;
+use uuid::Uuid;
+
+/// How often the key directory is reloaded and rotation is checked, in seconds.
+const KEY_CHECK_INTERVAL: u64 = 60;
+
+/// A new key is only published for this long before it's used for signing, in seconds.
+/// Other instances sharing the key directory and JWKS consumers have time to pick it up.
+const KEY_PUBLISH_DELAY: i64 = 2 * KEY_CHECK_INTERVAL as i64;
+
+/// Issuer and audience of in-memory instances, which are not created from a config.
+const DEFAULT_ISSUER: &str = "dia";
+
+/// Manages the signing keys, incoming JWT validation and singing of new JWTs.
+/// Clones share the same key set, so a rotation is visible to every worker.
+/// Keys of different algorithms can be mixed, each token is validated with the algorithm of it's key.
+#[derive(Clone)]
+pub struct JWT {
+    /// Every key that is accepted when decoding, newest first.
+    keys: Arc<RwLock<Vec<JwtKey>>>,
+    /// Where keys are persisted. `None` for an in-memory key set, which is never rotated.
+    key_dir: Option<PathBuf>,
+    /// How old the newest key can get before a new one is generated.
+    rotation_interval: Duration,
+    /// How long a key is still accepted after it has been rotated out.
+    grace_period: Duration,
+    /// Algorithm of newly generated keys.
+    algorithm: Algorithm,
+    /// Set as `iss` of signed tokens, and required from decoded ones.
+    issuer: String,
+    /// The audience of this server's own routes, used when no other audience is given.
+    audience: String,
+    /// Every audience tokens can be signed for, including `audience`.
+    audiences: Vec<String>,
+    /// Profile fields included in signed tokens.
+    profile_claims: Vec<ProfileClaim>,
+    /// Revoked tokens. Without one, tokens are valid until they expire.
+    denylist: Option<Denylist>,
+}
+
+impl JWT {
+    /// Generate a new in-memory instance with a newly generated RSA private key.
+    pub fn generate() -> Result<Self> {
+        Self::generate_with(Algorithm::RS256)
+    }
+
+    /// Generate a new in-memory instance with a key for the algorithm.
+    pub fn generate_with(algorithm: Algorithm) -> Result<Self> {
+        Ok(Self::from_keys(vec![JwtKey::generate(algorithm)?]))
+    }
+
+    /// Retrieve a key from a file and check it's validity.
+    pub fn from_pem_file(path: &str) -> Result<Self> {
+        Ok(Self::from_keys(vec![JwtKey::from_pem_file(Path::new(
+            path,
+        ))?]))
+    }
+
+    pub fn from_pem(pem: Rsa<Private>) -> Result<Self> {
+        Ok(Self::from_keys(vec![JwtKey::from_rsa(
+            Uuid::new_v4().to_simple().to_string(),
+            Utc::now(),
+            pem,
+        )?]))
+    }
+
+    fn from_keys(keys: Vec<JwtKey>) -> Self {
+        JWT {
+            algorithm: keys
+                .first()
+                .map(|key| key.algorithm)
+                .unwrap_or(Algorithm::RS256),
+            keys: Arc::new(RwLock::new(keys)),
+            key_dir: None,
+            rotation_interval: Duration::max_value(),
+            grace_period: Duration::zero(),
+            issuer: DEFAULT_ISSUER.into(),
+            audience: DEFAULT_ISSUER.into(),
+            audiences: vec![DEFAULT_ISSUER.into()],
+            profile_claims: vec![],
+            denylist: None,
+        }
+    }
+
+    /// Load the keys from the configured directory, generating the first one if it's empty.
+    pub fn from_config(conf: &Config) -> Result<Self> {
+        let mut jwt = Self::from_dir(
+            &conf.jwt.key_dir,
+            conf.jwt.algorithm,
+            Duration::seconds(conf.jwt.rotation_interval as i64),
+            Duration::seconds(conf.jwt.grace_period as i64),
+        )?;
+
+        jwt.issuer = conf.jwt.issuer.clone();
+        jwt.audience = conf.jwt.audience.clone();
+        jwt.audiences = conf.jwt.audiences.clone();
+
+        if !jwt.audiences.contains(&jwt.audience) {
+            jwt.audiences.push(jwt.audience.clone());
+        }
+
+        jwt.profile_claims = conf.jwt.profile_claims.clone();
+
+        Ok(jwt)
+    }
+
+    /// Keys of other algorithms in the directory are still accepted until they are retired.
+    pub fn from_dir<P: AsRef<Path>>(
+        dir: P,
+        algorithm: Algorithm,
+        rotation_interval: Duration,
+        grace_period: Duration,
+    ) -> Result<Self> {
+        let jwt = JWT {
+            keys: Arc::new(RwLock::new(vec![])),
+            key_dir: Some(dir.as_ref().to_path_buf()),
+            rotation_interval,
+            grace_period,
+            algorithm,
+            issuer: DEFAULT_ISSUER.into(),
+            audience: DEFAULT_ISSUER.into(),
+            audiences: vec![DEFAULT_ISSUER.into()],
+            profile_claims: vec![],
+            denylist: None,
+        };
+
+        jwt.rotate()?;
+
+        Ok(jwt)
+    }
+
+    /// Consult the denylist when decoding tokens.
+    pub fn with_denylist(mut self, denylist: Denylist) -> Self {
+        self.denylist = Some(denylist);
+
+        self
+    }
+
+    /// The denylist tokens are revoked in, if there is one.
+    pub fn denylist(&self) -> Result<&Denylist> {
+        match &self.denylist {
+            Some(denylist) => Ok(denylist),
+            None => bail!("JWT revocation is not available."),
+        }
+    }
+
+    /// Reload the key directory, generate a new key if the newest one is older than
+    /// the rotation interval or uses another algorithm, and remove keys that are past
+    /// their grace period.
+    /// Blocks on file IO and key generation.
+    pub fn rotate(&self) -> Result<()> {
+        let dir = match &self.key_dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+
+        let now = Utc::now();
+        let mut keys = JwtKey::load_dir(dir)?;
+
+        let rotation_due = match keys.iter().max_by_key(|key| key.created) {
+            Some(newest) => {
+                newest.created + self.rotation_interval <= now || newest.algorithm != self.algorithm
+            }
+            None => true,
+        };
+
+        if rotation_due {
+            info!("Generating a new {:?} JWT signing key", self.algorithm);
+
+            let key = JwtKey::generate(self.algorithm)?;
+            key.save(dir)?;
+            keys.push(key);
+        }
+
+        keys.sort_by_key(|key| Reverse(key.created));
+
+        // Never retire the key currently used for signing
+        let signing_kid = signing_key(&keys).map(|key| key.kid.clone());
+
+        let mut retained = vec![];
+
+        for key in keys {
+            if Some(&key.kid) != signing_kid.as_ref()
+                && key.created + self.rotation_interval + self.grace_period <= now
+            {
+                info!("Retiring JWT signing key {}", key.kid);
+
+                key.remove(dir)?;
+            } else {
+                retained.push(key);
+            }
+        }
+
+        *self
+            .keys
+            .write()
+            .map_err(|_| anyhow!("JWT key set lock is poisoned."))? = retained;
+
+        Ok(())
+    }
+
+    /// Check the key directory periodically in the background.
+    /// Has to be called inside the actix runtime.
+    pub fn schedule_rotation(&self) {
+        let jwt = self.clone();
+
+        actix_web::rt::spawn(async move {
+            let mut interval =
+                actix_web::rt::time::interval(std::time::Duration::from_secs(KEY_CHECK_INTERVAL));
+
+            loop {
+                interval.tick().await;
+
+                let jwt = jwt.clone();
+
+                match spawn_blocking(move || jwt.rotate()).await {
+                    Ok(Ok(())) => (),
+                    Ok(Err(error)) => error!("JWT key rotation failed: {}", error),
+                    Err(error) => error!("JWT key rotation task failed: {}", error),
+                }
+            }
+        });
+    }
+
+    /// The audience of this server's own routes.
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    /// Check that tokens can be signed for and validated against the audience.
+    pub fn check_audience(&self, audience: &str) -> Result<()> {
+        if !self
+            .audiences
+            .iter()
+            .any(|registered| registered == audience)
+        {
+            bail!("Audience '{}' is not registered.", audience);
+        }
+
+        Ok(())
+    }
+
+    /// Claims for a new token issued by this instance, with the configured profile fields.
+    /// The audience has to be registered.
+    pub fn claims(
+        &self,
+        user: &User,
+        audience: &str,
+        exp_secs: i64,
+        parent_token: Uuid,
+    ) -> Result<JwtClaims> {
+        self.check_audience(audience)?;
+
+        Ok(JwtClaims::new(
+            user,
+            &self.issuer,
+            audience,
+            exp_secs,
+            parent_token,
+            &self.profile_claims,
+        ))
+    }
+
+    /// `true` if the optional claim is configured to be included in signed tokens.
+    /// Claims that need a database query, like permissions, are set by the caller.
+    pub fn includes(&self, claim: ProfileClaim) -> bool {
+        self.profile_claims.contains(&claim)
+    }
+
+    /// Encode claim with the current signing key. The key identifier is set in the header.
+    pub fn encode(&self, claims: &JwtClaims) -> Result<String> {
+        let keys = self.read_keys()?;
+
+        let key = match signing_key(&keys) {
+            Some(key) => key,
+            None => bail!("No JWT signing key available."),
+        };
+
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+
+        Ok(encode(&header, claims, &key.encoding)?)
+    }
+
+    /// Decode claim and check that the token has not been revoked.
+    /// The token has to be intended for the given audience.
+    pub async fn decode(&self, token: &str, audience: &str) -> Result<TokenData<JwtClaims>> {
+        let data = self.verify(token, audience)?;
+
+        if let Some(denylist) = &self.denylist {
+            if denylist.is_revoked(&data.claims).await? {
+                bail!("The JWT has been revoked.");
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Decode claim with the key the header refers to, without checking revocation.
+    /// Tokens without a `kid` are tried against the signing key.
+    /// Also checks expiration, not before and issuer.
+    /// The token has to be intended for the given audience.
+    pub fn verify(&self, token: &str, audience: &str) -> Result<TokenData<JwtClaims>> {
+        self.check_audience(audience)?;
+
+        let keys = self.read_keys()?;
+
+        let key = match decode_header(token)?.kid {
+            Some(kid) => keys.iter().find(|key| key.kid == kid),
+            None => signing_key(&keys),
+        };
+
+        let key = match key {
+            Some(key) => key,
+            None => bail!("The JWT was signed with an unknown key."),
+        };
+
+        // The algorithm comes from the key, never from the token header
+        let mut validation = Validation::new(key.algorithm);
+        validation.validate_nbf = true;
+        validation.set_audience(&[audience]);
+
+        let data = decode::<JwtClaims>(token, &key.decoding, &validation)?;
+
+        if data.claims.iss != self.issuer {
+            bail!("The JWT was issued by an unknown issuer.");
+        }
+
+        Ok(data)
+    }
+
+    /// The public key of the current signing key in PEM and JWK format.
+    pub fn public_key(&self) -> Result<PublicKey> {
+        match signing_key(&self.read_keys()?) {
+            Some(key) => key.public(),
+            None => bail!("No JWT signing key available."),
+        }
+    }
+
+    /// Every key accepted for decoding as a JSON Web Key Set.
+    pub fn jwks(&self) -> Result<Jwks> {
+        Ok(Jwks {
+            keys: self.read_keys()?.iter().map(|key| key.jwk()).collect(),
+        })
+    }
+
+    fn read_keys(&self) -> Result<std::sync::RwLockReadGuard<'_, Vec<JwtKey>>> {
+        self.keys
+            .read()
+            .map_err(|_| anyhow!("JWT key set lock is poisoned."))
+    }
+}
+
+/// The newest key that has been published long enough, or the newest key if none has.
+/// Expects the keys to be sorted newest first.
+fn signing_key(keys: &[JwtKey]) -> Option<&JwtKey> {
+    let published_before = Utc::now() - Duration::seconds(KEY_PUBLISH_DELAY);
+
+    keys.iter()
+        .find(|key| key.created <= published_before)
+        .or_else(|| keys.first())
+}
+
+impl FromRequest for JWT {
+    type Error = Res<()>;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.app_data::<Self>() {
+            Some(jwt) => ok(jwt.clone()),
+            _ => {
+                error!("JWT does not exists in app's data!");
+
+                err(Res::<()>::error("No JWT in app's data"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_user() -> User {
+        User {
+            id: Uuid::new_v4(),
+            created: Utc::now(),
+            modified: Utc::now(),
+            username: "jwt_username".into(),
+            email: None,
+            display_name: None,
+            password_hash: "password_hash".into(),
+            groups: vec!["admin".into()],
+            email_verified: false,
+            pending_email: None,
+        }
+    }
+
+    /// New `Claims` with an expiration of 10 minutes.
+    fn test_claims() -> JwtClaims {
+        JwtClaims::new(
+            &test_user(),
+            DEFAULT_ISSUER,
+            DEFAULT_ISSUER,
+            600,
+            Uuid::new_v4(),
+            &[],
+        )
+    }
+
+    /// Test that valid claims pass the validation.
+    #[tokio::test]
+    async fn valid_claims() {
+        let claims = test_claims();
+        let jwt = JWT::generate().unwrap();
+
+        let encoded = jwt.encode(&claims).unwrap();
+        let decoded = jwt.decode(&encoded, DEFAULT_ISSUER).await;
+
+        // Should be valid, since the 10 minute expiration.
+        assert!(decoded.is_ok())
+    }
+
+    /// Modify the expiration to the past.
+    #[tokio::test]
+    async fn expired_claims() {
+        let mut claims = test_claims();
+        // Edit the timestamp to the past
+        claims.exp -= 1000;
+
+        let jwt = JWT::generate().unwrap();
+
+        let encoded = jwt.encode(&claims).unwrap();
+        let decoded = jwt.decode(&encoded, DEFAULT_ISSUER).await;
+
+        assert!(!decoded.is_ok())
+    }
+
+    /// Tokens signed before a rotation should stay valid, and both keys should be published.
+    #[tokio::test]
+    async fn rotated_key_still_validates() {
+        let dir = std::env::temp_dir().join(format!("dia_jwt_{}", Uuid::new_v4()));
+
+        // Rotate on every check, keep old keys for an hour
+        let jwt =
+            JWT::from_dir(&dir, Algorithm::RS256, Duration::zero(), Duration::hours(1)).unwrap();
+
+        let encoded = jwt.encode(&test_claims()).unwrap();
+
+        jwt.rotate().unwrap();
+
+        assert!(jwt.decode(&encoded, DEFAULT_ISSUER).await.is_ok());
+        assert_eq!(jwt.jwks().unwrap().keys.len(), 2);
+
+        // Another instance sharing the directory accepts the same token
+        let replica = JWT::from_dir(
+            &dir,
+            Algorithm::RS256,
+            Duration::days(30),
+            Duration::hours(1),
+        )
+        .unwrap();
+
+        assert!(replica.decode(&encoded, DEFAULT_ISSUER).await.is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A token signed by a key outside of the key set should be rejected.
+    #[tokio::test]
+    async fn unknown_key() {
+        let encoded = JWT::generate().unwrap().encode(&test_claims()).unwrap();
+
+        assert!(JWT::generate()
+            .unwrap()
+            .decode(&encoded, DEFAULT_ISSUER)
+            .await
+            .is_err())
+    }
+
+    /// Only the configured profile fields should end up in the token, never the password hash.
+    #[test]
+    fn profile_claims_opt_in() {
+        let mut jwt = JWT::generate().unwrap();
+        jwt.profile_claims = vec![ProfileClaim::Groups];
+
+        let claims = jwt
+            .claims(&test_user(), DEFAULT_ISSUER, 600, Uuid::new_v4())
+            .unwrap();
+        let encoded = jwt.encode(&claims).unwrap();
+        let payload =
+            base64::decode_config(encoded.split('.').nth(1).unwrap(), base64::URL_SAFE_NO_PAD)
+                .unwrap();
+        let payload = std::str::from_utf8(&payload).unwrap();
+
+        assert!(payload.contains("admin"));
+        assert!(!payload.contains("jwt_username"));
+        assert!(!payload.contains("password_hash"));
+    }
+
+    /// Tokens from another issuer should be rejected even if the signature is valid.
+    #[tokio::test]
+    async fn wrong_issuer() {
+        let mut claims = test_claims();
+        claims.iss = "someone_else".into();
+
+        let jwt = JWT::generate().unwrap();
+
+        assert!(jwt
+            .decode(&jwt.encode(&claims).unwrap(), DEFAULT_ISSUER)
+            .await
+            .is_err())
+    }
+
+    /// A token signed for one audience should not be accepted by another.
+    #[tokio::test]
+    async fn wrong_audience() {
+        let mut jwt = JWT::generate().unwrap();
+        jwt.audiences.push("other_app".into());
+
+        let claims = jwt
+            .claims(&test_user(), "other_app", 600, Uuid::new_v4())
+            .unwrap();
+        let encoded = jwt.encode(&claims).unwrap();
+
+        assert!(jwt.decode(&encoded, "other_app").await.is_ok());
+        assert!(jwt.decode(&encoded, DEFAULT_ISSUER).await.is_err());
+
+        // Unregistered audiences can't be signed for
+        assert!(jwt
+            .claims(&test_user(), "unknown_app", 600, Uuid::new_v4())
+            .is_err());
+    }
+
+    /// Every supported algorithm should sign and validate it's own tokens.
+    #[tokio::test]
+    async fn algorithms() {
+        for algorithm in &[Algorithm::RS256, Algorithm::ES256, Algorithm::EdDSA] {
+            let jwt = JWT::generate_with(*algorithm).unwrap();
+
+            let encoded = jwt.encode(&test_claims()).unwrap();
+
+            assert!(jwt.decode(&encoded, DEFAULT_ISSUER).await.is_ok());
+            assert_eq!(
+                jwt.public_key().unwrap().jwk.alg,
+                format!("{:?}", algorithm)
+            );
+        }
+    }
+
+    /// Switching the algorithm should rotate, while tokens of the old algorithm stay valid.
+    #[tokio::test]
+    async fn algorithm_migration() {
+        let dir = std::env::temp_dir().join(format!("dia_jwt_{}", Uuid::new_v4()));
+
+        let rsa = JWT::from_dir(
+            &dir,
+            Algorithm::RS256,
+            Duration::days(30),
+            Duration::hours(1),
+        )
+        .unwrap();
+        let encoded = rsa.encode(&test_claims()).unwrap();
+
+        let ec = JWT::from_dir(
+            &dir,
+            Algorithm::ES256,
+            Duration::days(30),
+            Duration::hours(1),
+        )
+        .unwrap();
+
+        assert!(ec.decode(&encoded, DEFAULT_ISSUER).await.is_ok());
+        assert_eq!(ec.jwks().unwrap().keys.len(), 2);
+        assert_eq!(ec.public_key().unwrap().algorithm, "ES256");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
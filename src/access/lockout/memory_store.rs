@@ -0,0 +1,169 @@
+use super::{LockoutStore, Reservation, Reserved};
+use anyhow::Result;
+use chrono::Utc;
+use std::{collections::HashMap, sync::Mutex};
+
+/// Expired failures are removed when there are at least this many usernames.
+const PRUNE_AT: usize = 1024;
+
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    /// When the next attempt is allowed.
+    until: i64,
+    /// When the failures are forgotten.
+    expires: i64,
+}
+
+/// Failures of a single process, the same as `RedisStore`.
+/// For a single instance, tests and falling back when Redis can't be reached.
+#[derive(Default)]
+pub struct MemoryStore {
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+#[async_trait::async_trait]
+impl LockoutStore for MemoryStore {
+    async fn reserve(
+        &self,
+        key: &str,
+        now: i64,
+        lockout_seconds: i64,
+        delays: &[i64],
+    ) -> Result<Reserved> {
+        let mut failures = self.failures.lock().unwrap();
+
+        if failures.len() >= PRUNE_AT {
+            failures.retain(|_, failures| failures.expires > now);
+        }
+
+        let entry = failures.entry(key.into()).or_default();
+
+        if entry.expires <= now {
+            *entry = Failures::default();
+        }
+
+        if entry.until > now {
+            return Ok(Reserved::Wait(entry.until - now));
+        }
+
+        let previous_until = entry.until;
+
+        entry.count += 1;
+        entry.until = now
+            + delays
+                .get(entry.count as usize - 1)
+                .or_else(|| delays.last())
+                .copied()
+                .unwrap_or(0);
+        entry.expires = now + lockout_seconds;
+
+        Ok(Reserved::Counted(Reservation {
+            failures: entry.count,
+            previous_until,
+            until: entry.until,
+        }))
+    }
+
+    async fn release(&self, key: &str, reservation: &Reservation) -> Result<()> {
+        let mut failures = self.failures.lock().unwrap();
+
+        if let Some(entry) = failures.get_mut(key) {
+            if entry.until == reservation.until {
+                entry.until = reservation.previous_until;
+            }
+
+            entry.count = entry.count.saturating_sub(1);
+
+            if entry.count == 0 {
+                failures.remove(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn until(&self, key: &str) -> Result<Option<i64>> {
+        let now = Utc::now().timestamp();
+
+        Ok(self
+            .failures
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|failures| failures.expires > now)
+            .map(|failures| failures.until))
+    }
+
+    async fn clear(&self, key: &str) -> Result<bool> {
+        let now = Utc::now().timestamp();
+
+        Ok(matches!(
+            self.failures.lock().unwrap().remove(key),
+            Some(failures) if failures.expires > now
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAYS: [i64; 3] = [0, 2, 60];
+
+    /// Each failure delays the next attempt more, up to the last delay.
+    #[tokio::test]
+    async fn delays() {
+        let store = MemoryStore::default();
+        let now = Utc::now().timestamp();
+
+        assert!(matches!(
+            store.reserve("key", now, 60, &DELAYS).await.unwrap(),
+            Reserved::Counted(Reservation { failures: 1, until, .. }) if until == now
+        ));
+        assert!(matches!(
+            store.reserve("key", now, 60, &DELAYS).await.unwrap(),
+            Reserved::Counted(Reservation { failures: 2, until, .. }) if until == now + 2
+        ));
+        assert!(matches!(
+            store.reserve("key", now + 1, 60, &DELAYS).await.unwrap(),
+            Reserved::Wait(1)
+        ));
+        assert!(matches!(
+            store.reserve("key", now + 2, 60, &DELAYS).await.unwrap(),
+            Reserved::Counted(Reservation { failures: 3, .. })
+        ));
+        assert!(matches!(
+            store.reserve("key", now + 61, 60, &DELAYS).await.unwrap(),
+            Reserved::Wait(1)
+        ));
+
+        // Forgotten after the lockout
+        assert!(matches!(
+            store.reserve("key", now + 62, 60, &DELAYS).await.unwrap(),
+            Reserved::Counted(Reservation { failures: 1, .. })
+        ));
+    }
+
+    /// A released failure restores the previous wait, and the last one removes the username.
+    #[tokio::test]
+    async fn release() {
+        let store = MemoryStore::default();
+        let now = Utc::now().timestamp();
+
+        store.reserve("key", now, 60, &DELAYS).await.unwrap();
+
+        let reservation = match store.reserve("key", now, 60, &DELAYS).await.unwrap() {
+            Reserved::Counted(reservation) => reservation,
+            Reserved::Wait(_) => panic!("the second failure has no delay"),
+        };
+        assert_eq!(store.until("key").await.unwrap(), Some(now + 2));
+
+        store.release("key", &reservation).await.unwrap();
+        assert_eq!(store.until("key").await.unwrap(), Some(now));
+
+        assert!(store.clear("key").await.unwrap());
+        assert!(!store.clear("key").await.unwrap());
+        assert_eq!(store.until("key").await.unwrap(), None);
+    }
+}
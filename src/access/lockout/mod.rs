@@ -0,0 +1,540 @@
+//! Failed login tracking per username, in addition to the rate limits per address.
+//! After `free_attempts` failures in a row every attempt has to wait twice as long as the
+//! previous one, and after `max_attempts` the username is locked for `lockout_seconds`.
+//! Unknown usernames are tracked too, so the responses don't reveal which ones exist.
+//! Each attempt is counted as a failure before it runs, so concurrent attempts can't get
+//! past the back-off, and given back if it fails for another reason than wrong credentials.
+
+mod memory_store;
+mod redis_store;
+
+use memory_store::MemoryStore;
+use redis_store::RedisStore;
+
+use crate::{
+    config::{FailurePolicy, LockoutConfig, RateLimitStoreKind},
+    db::RedisConn,
+    gql::{E, R},
+    models::{
+        passkey::PasskeyAssertion,
+        security_event::{SecurityEvent, SecurityEventKind},
+        two_factor::TwoFactor,
+        user::User,
+    },
+    Config, Res,
+};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use anyhow::Result;
+use async_graphql::Context;
+use chrono::Utc;
+use futures::future::{err, ok, Ready};
+use humantime::format_duration;
+use sqlx::PgPool;
+use std::{future::Future, net::IpAddr, sync::Arc, time::Duration};
+
+/// Seconds to wait before the next attempt after the amount of failures in a row.
+pub fn delay(conf: &LockoutConfig, failures: u32) -> i64 {
+    if failures >= conf.max_attempts {
+        conf.lockout_seconds
+    } else if failures <= conf.free_attempts {
+        0
+    } else {
+        2i64.saturating_pow(failures - conf.free_attempts)
+            .min(conf.lockout_seconds)
+    }
+}
+
+/// The outcome of checking the credentials of a login attempt.
+pub enum Credentials<T> {
+    Valid(T),
+    /// Wrong username, password or second factor, counted as a failure.
+    /// The error is returned to the client.
+    Invalid(E),
+}
+
+/// `None` is a wrong username or password.
+impl<T> From<Option<T>> for Credentials<T> {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(
+            Credentials::Invalid(E::InvalidCredentials),
+            Credentials::Valid,
+        )
+    }
+}
+
+/// A failure counted before running the attempt.
+pub struct Reservation {
+    pub failures: u32,
+    /// When the next attempt was allowed before this one.
+    pub previous_until: i64,
+    /// When the next attempt is allowed after this one.
+    pub until: i64,
+}
+
+/// The outcome of counting an attempt.
+pub enum Reserved {
+    Counted(Reservation),
+    /// Seconds until the username can be tried again.
+    Wait(i64),
+}
+
+/// Where the failures are counted, by the key of the username.
+#[async_trait::async_trait]
+pub trait LockoutStore: Send + Sync {
+    /// Count a failure at `now` in seconds, unless the next attempt is allowed only later.
+    /// The next attempt is allowed after the delay of the new failure count, the last delay is
+    /// for any more. The count is kept until `lockout_seconds` have passed since the failure.
+    async fn reserve(
+        &self,
+        key: &str,
+        now: i64,
+        lockout_seconds: i64,
+        delays: &[i64],
+    ) -> Result<Reserved>;
+
+    /// Take back a failure counted by `reserve`.
+    async fn release(&self, key: &str, reservation: &Reservation) -> Result<()>;
+
+    /// When the next attempt is allowed in seconds, if there are any failures.
+    async fn until(&self, key: &str) -> Result<Option<i64>>;
+
+    /// Forget the failures. Returns `true` if there were any.
+    async fn clear(&self, key: &str) -> Result<bool>;
+}
+
+/// Shared by every flow that checks a password or a second factor for an username.
+/// Failures are counted in the store of `[rate_limits]`, with the same failure policy.
+#[derive(Clone)]
+pub struct Lockout {
+    store: Arc<dyn LockoutStore>,
+    /// Used while the store fails, with `FailurePolicy::Local`.
+    fallback: Arc<MemoryStore>,
+    conf: LockoutConfig,
+    on_failure: FailurePolicy,
+}
+
+impl Lockout {
+    /// Use the store configured for rate limits.
+    pub fn new(redis_conn: RedisConn, conf: &Config) -> Self {
+        let store: Arc<dyn LockoutStore> = match conf.rate_limits.store {
+            RateLimitStoreKind::Redis => Arc::new(RedisStore::new(redis_conn)),
+            RateLimitStoreKind::Memory => Arc::new(MemoryStore::default()),
+        };
+
+        Lockout::with_store(store, &conf.lockout, conf.rate_limits.on_failure)
+    }
+
+    pub fn with_store(
+        store: Arc<dyn LockoutStore>,
+        conf: &LockoutConfig,
+        on_failure: FailurePolicy,
+    ) -> Self {
+        Lockout {
+            store,
+            fallback: Arc::default(),
+            conf: conf.clone(),
+            on_failure,
+        }
+    }
+
+    /// The lockout in the GraphQL context.
+    pub fn from_context<'a>(ctx: &'a Context<'_>) -> R<&'a Self> {
+        Ok(ctx.data::<Lockout>()?)
+    }
+
+    fn key(username: &str) -> String {
+        format!("LOGIN_FAILURES_{}", username.to_lowercase())
+    }
+
+    /// The store to use when the configured one fails, decided by the failure policy.
+    /// `None` if failures are not counted until it works again.
+    fn on_store_failure(&self, error: anyhow::Error) -> R<Option<&MemoryStore>> {
+        warn!(
+            "Lockout store failed, using failure policy {:?}: {}",
+            self.on_failure, error
+        );
+
+        match self.on_failure {
+            FailurePolicy::Open => Ok(None),
+            FailurePolicy::Closed => Err(error.into()),
+            FailurePolicy::Local => Ok(Some(&self.fallback)),
+        }
+    }
+
+    /// Seconds until the username can be tried again, 0 if it can be tried now.
+    pub async fn retry_in(&self, username: &str) -> R<i64> {
+        let key = Self::key(username);
+
+        let until = match self.store.until(&key).await {
+            Ok(until) => until,
+            Err(error) => match self.on_store_failure(error)? {
+                Some(fallback) => fallback.until(&key).await?,
+                None => None,
+            },
+        };
+
+        Ok((until.unwrap_or(0) - Utc::now().timestamp()).max(0))
+    }
+
+    /// Check the password of an user who is already logged in, before a sensitive change.
+    /// A wrong password counts as a failed login of the username.
+    pub async fn check_password(&self, ctx: &Context<'_>, user: &User, password: String) -> R<()> {
+        let pool = ctx.data::<PgPool>()?;
+        let conf = ctx.data::<Config>()?;
+
+        self.attempt(
+            pool,
+            &user.username,
+            Some(ctx.data::<IpAddr>()?.to_string()),
+            async {
+                let verified =
+                    User::from_credentials(pool, &conf.password, user.username.clone(), password)
+                        .await?;
+
+                Ok(match verified {
+                    Some(_) => Credentials::Valid(()),
+                    None => Credentials::Invalid(E::Message("Wrong password.".into())),
+                })
+            },
+        )
+        .await
+    }
+
+    /// Like `check_password`, but users with two-factor authentication also need a TOTP or
+    /// recovery code, or a passkey response. A wrong second factor counts as a failure too.
+    pub async fn reauthenticate(
+        &self,
+        ctx: &Context<'_>,
+        user: &User,
+        password: String,
+        otp: Option<&str>,
+        passkey: Option<&PasskeyAssertion>,
+    ) -> R<()> {
+        let pool = ctx.data::<PgPool>()?;
+        let conf = ctx.data::<Config>()?;
+
+        self.attempt(
+            pool,
+            &user.username,
+            Some(ctx.data::<IpAddr>()?.to_string()),
+            async {
+                let verified =
+                    User::from_credentials(pool, &conf.password, user.username.clone(), password)
+                        .await?;
+
+                if verified.is_none() {
+                    return Ok(Credentials::Invalid(E::Message("Wrong password.".into())));
+                }
+
+                Ok(
+                    if TwoFactor::verify_second_factor(ctx, user, otp, passkey).await? {
+                        Credentials::Valid(())
+                    } else {
+                        Credentials::Invalid(E::Message("Invalid two-factor code.".into()))
+                    },
+                )
+            },
+        )
+        .await
+    }
+
+    /// Run a login attempt for the username. During a back-off or a lockout the attempt is
+    /// rejected without running it. Invalid credentials count as a failure, valid ones reset
+    /// the count. Other errors, like a missing second factor, don't count.
+    pub async fn attempt<T, F>(
+        &self,
+        pool: &PgPool,
+        username: &str,
+        client_address: Option<String>,
+        attempt: F,
+    ) -> R<T>
+    where
+        F: Future<Output = R<Credentials<T>>>,
+    {
+        let reservation = self.reserve(username).await?;
+
+        match attempt.await {
+            Ok(Credentials::Valid(value)) => {
+                self.unlock(username).await?;
+
+                Ok(value)
+            }
+            Ok(Credentials::Invalid(error)) => {
+                if reservation.failures == self.conf.max_attempts {
+                    SecurityEvent::record(
+                        pool,
+                        SecurityEventKind::AccountLocked,
+                        User::from_username(pool, username)
+                            .await
+                            .ok()
+                            .map(|user| user.id),
+                        client_address,
+                        format!(
+                            "{} failed login attempts for {}",
+                            reservation.failures, username
+                        ),
+                    )
+                    .await?;
+                }
+
+                Err(error)
+            }
+            Err(error) => {
+                self.release(username, &reservation).await?;
+
+                Err(error)
+            }
+        }
+    }
+
+    /// Count the attempt as a failure, unless the username has to wait.
+    async fn reserve(&self, username: &str) -> R<Reservation> {
+        let delays: Vec<i64> = (1..=self.conf.max_attempts.max(1))
+            .map(|failures| delay(&self.conf, failures))
+            .collect();
+
+        let key = Self::key(username);
+        let now = Utc::now().timestamp();
+
+        let reserved = match self
+            .store
+            .reserve(&key, now, self.conf.lockout_seconds, &delays)
+            .await
+        {
+            Ok(reserved) => reserved,
+            Err(error) => match self.on_store_failure(error)? {
+                Some(fallback) => {
+                    fallback
+                        .reserve(&key, now, self.conf.lockout_seconds, &delays)
+                        .await?
+                }
+                // Not a failure that could lock the username
+                None => Reserved::Counted(Reservation {
+                    failures: 0,
+                    previous_until: 0,
+                    until: 0,
+                }),
+            },
+        };
+
+        match reserved {
+            Reserved::Counted(reservation) => Ok(reservation),
+            Reserved::Wait(retry_in) => Err(E::Message(format!(
+                "Too many failed login attempts. Try again in {}.",
+                format_duration(Duration::from_secs(retry_in as u64))
+            ))),
+        }
+    }
+
+    /// Give back a failure counted by `reserve`.
+    async fn release(&self, username: &str, reservation: &Reservation) -> R<()> {
+        let key = Self::key(username);
+
+        if let Err(error) = self.store.release(&key, reservation).await {
+            if let Some(fallback) = self.on_store_failure(error)? {
+                fallback.release(&key, reservation).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget the failures of the username, ending any back-off or lockout.
+    /// Returns `true` if there were any.
+    pub async fn unlock(&self, username: &str) -> R<bool> {
+        let key = Self::key(username);
+
+        match self.store.clear(&key).await {
+            Ok(cleared) => Ok(cleared),
+            Err(error) => match self.on_store_failure(error)? {
+                Some(fallback) => Ok(fallback.clear(&key).await?),
+                None => Ok(false),
+            },
+        }
+    }
+}
+
+impl FromRequest for Lockout {
+    type Error = Res<()>;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.app_data::<Lockout>() {
+            Some(lockout) => ok(lockout.clone()),
+            _ => {
+                error!("Lockout does not exists in app's data!");
+
+                err(Res::<()>::error("No Lockout in app's data"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{RedisConn, SqlxConn},
+        CONF_FILE,
+    };
+
+    fn redis_store(conf: &Config) -> Arc<dyn LockoutStore> {
+        Arc::new(RedisStore::new(RedisConn::new(conf)))
+    }
+
+    fn test_conf() -> LockoutConfig {
+        LockoutConfig {
+            free_attempts: 3,
+            max_attempts: 10,
+            lockout_seconds: 900,
+        }
+    }
+
+    #[test]
+    fn delays() {
+        let conf = test_conf();
+
+        assert_eq!(delay(&conf, 3), 0);
+        assert_eq!(delay(&conf, 4), 2);
+        assert_eq!(delay(&conf, 5), 4);
+        assert_eq!(delay(&conf, 9), 64);
+        assert_eq!(delay(&conf, 10), 900);
+        assert_eq!(delay(&conf, 100), 900);
+    }
+
+    /// Failures lead to a lockout, during which even the right password is rejected.
+    #[tokio::test]
+    async fn lock_and_unlock() {
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+        let lockout_conf = LockoutConfig {
+            free_attempts: 0,
+            max_attempts: 1,
+            lockout_seconds: 60,
+        };
+        let lockout = Lockout::with_store(redis_store(&conf), &lockout_conf, FailurePolicy::Closed);
+        let username = "lockout_test_username";
+
+        lockout.unlock(username).await.unwrap();
+
+        assert!(lockout
+            .attempt(&pool, username, None, async {
+                Ok(Credentials::<()>::Invalid(E::InvalidCredentials))
+            })
+            .await
+            .is_err());
+
+        assert!(lockout.retry_in(username).await.unwrap() > 0);
+        assert!(lockout
+            .attempt(&pool, username, None, async { Ok(Credentials::Valid(())) })
+            .await
+            .is_err());
+
+        assert!(lockout.unlock(username).await.unwrap());
+        assert!(lockout
+            .attempt(&pool, username, None, async { Ok(Credentials::Valid(())) })
+            .await
+            .is_ok());
+    }
+
+    /// Errors other than invalid credentials give the attempt back.
+    #[tokio::test]
+    async fn other_errors() {
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+        let lockout_conf = LockoutConfig {
+            free_attempts: 0,
+            max_attempts: 1,
+            lockout_seconds: 60,
+        };
+        let lockout = Lockout::with_store(redis_store(&conf), &lockout_conf, FailurePolicy::Closed);
+        let username = "lockout_test_other_errors";
+
+        lockout.unlock(username).await.unwrap();
+
+        assert!(lockout
+            .attempt(&pool, username, None, async {
+                Err::<Credentials<()>, _>(E::DatabaseError)
+            })
+            .await
+            .is_err());
+
+        assert_eq!(lockout.retry_in(username).await.unwrap(), 0);
+        assert!(!lockout.unlock(username).await.unwrap());
+    }
+
+    struct FailingStore;
+
+    #[async_trait::async_trait]
+    impl LockoutStore for FailingStore {
+        async fn reserve(&self, _: &str, _: i64, _: i64, _: &[i64]) -> Result<Reserved> {
+            Err(anyhow!("Store is down"))
+        }
+
+        async fn release(&self, _: &str, _: &Reservation) -> Result<()> {
+            Err(anyhow!("Store is down"))
+        }
+
+        async fn until(&self, _: &str) -> Result<Option<i64>> {
+            Err(anyhow!("Store is down"))
+        }
+
+        async fn clear(&self, _: &str) -> Result<bool> {
+            Err(anyhow!("Store is down"))
+        }
+    }
+
+    /// When the store fails, attempts are let through, failed or counted locally.
+    #[tokio::test]
+    async fn failure_policies() {
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+        let lockout_conf = LockoutConfig {
+            free_attempts: 0,
+            max_attempts: 1,
+            lockout_seconds: 60,
+        };
+        let username = "lockout_test_failure_policies";
+        let invalid = || async { Ok(Credentials::<()>::Invalid(E::InvalidCredentials)) };
+        let valid = || async { Ok(Credentials::Valid(())) };
+
+        let lockout =
+            Lockout::with_store(Arc::new(FailingStore), &lockout_conf, FailurePolicy::Open);
+        assert!(lockout
+            .attempt(&pool, username, None, invalid())
+            .await
+            .is_err());
+        assert_eq!(lockout.retry_in(username).await.unwrap(), 0);
+        assert!(lockout
+            .attempt(&pool, username, None, valid())
+            .await
+            .is_ok());
+
+        let lockout =
+            Lockout::with_store(Arc::new(FailingStore), &lockout_conf, FailurePolicy::Closed);
+        assert!(lockout
+            .attempt(&pool, username, None, valid())
+            .await
+            .is_err());
+
+        let lockout =
+            Lockout::with_store(Arc::new(FailingStore), &lockout_conf, FailurePolicy::Local);
+        assert!(lockout
+            .attempt(&pool, username, None, invalid())
+            .await
+            .is_err());
+        assert!(lockout.retry_in(username).await.unwrap() > 0);
+        // The fallback is shared by clones
+        assert!(lockout
+            .clone()
+            .attempt(&pool, username, None, valid())
+            .await
+            .is_err());
+        assert!(lockout.unlock(username).await.unwrap());
+        assert!(lockout
+            .attempt(&pool, username, None, valid())
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,112 @@
+use super::{LockoutStore, Reservation, Reserved};
+use crate::db::RedisConn;
+use anyhow::Result;
+use redis::{AsyncCommands, Script};
+
+/// Gets the current time in seconds, the lockout seconds and the delays of the failure counts
+/// from 1. Rejects the attempt during a back-off or a lockout with the seconds left. Otherwise
+/// counts it as a failure and sets when the next attempt is allowed. Returns the failures and the
+/// previous and new time of the next allowed attempt.
+const RESERVE: &str = r#"
+local now = tonumber(ARGV[1])
+local until = tonumber(redis.call('HGET', KEYS[1], 'until') or 0)
+if until > now then
+    return {0, until - now}
+end
+local failures = redis.call('HINCRBY', KEYS[1], 'count', 1)
+local allowed_at = now + tonumber(ARGV[math.min(failures, #ARGV - 2) + 2])
+redis.call('HSET', KEYS[1], 'until', allowed_at)
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+return {1, failures, until, allowed_at}
+"#;
+
+/// Gets the previous and new time of the next allowed attempt from `RESERVE`, and takes back
+/// the failure. The previous time is restored, unless another attempt has changed it since.
+const RELEASE: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then
+    return 0
+end
+if tonumber(redis.call('HGET', KEYS[1], 'until')) == tonumber(ARGV[2]) then
+    redis.call('HSET', KEYS[1], 'until', ARGV[1])
+end
+if redis.call('HINCRBY', KEYS[1], 'count', -1) <= 0 then
+    redis.call('DEL', KEYS[1])
+end
+return 1
+"#;
+
+lazy_static! {
+    static ref RESERVE_SCRIPT: Script = Script::new(RESERVE);
+    static ref RELEASE_SCRIPT: Script = Script::new(RELEASE);
+}
+
+/// Failures shared by every instance, in a hash per username. Each change is a single
+/// atomic script.
+pub struct RedisStore {
+    redis_conn: RedisConn,
+}
+
+impl RedisStore {
+    pub fn new(redis_conn: RedisConn) -> Self {
+        RedisStore { redis_conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl LockoutStore for RedisStore {
+    async fn reserve(
+        &self,
+        key: &str,
+        now: i64,
+        lockout_seconds: i64,
+        delays: &[i64],
+    ) -> Result<Reserved> {
+        let mut con = self.redis_conn.conn_async().await?;
+
+        let mut script = RESERVE_SCRIPT.key(key);
+        script.arg(now).arg(lockout_seconds);
+
+        for delay in delays {
+            script.arg(*delay);
+        }
+
+        let reply: Vec<i64> = script.invoke_async(&mut con).await?;
+
+        match reply.as_slice() {
+            [1, failures, previous_until, until] => Ok(Reserved::Counted(Reservation {
+                failures: *failures as u32,
+                previous_until: *previous_until,
+                until: *until,
+            })),
+            [_, retry_in, ..] => Ok(Reserved::Wait(*retry_in)),
+            _ => bail!("Unexpected reply from the lockout script: {:?}", reply),
+        }
+    }
+
+    async fn release(&self, key: &str, reservation: &Reservation) -> Result<()> {
+        let mut con = self.redis_conn.conn_async().await?;
+
+        RELEASE_SCRIPT
+            .key(key)
+            .arg(reservation.previous_until)
+            .arg(reservation.until)
+            .invoke_async::<_, ()>(&mut con)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn until(&self, key: &str) -> Result<Option<i64>> {
+        let mut con = self.redis_conn.conn_async().await?;
+
+        Ok(con.hget(key, "until").await?)
+    }
+
+    async fn clear(&self, key: &str) -> Result<bool> {
+        let mut con = self.redis_conn.conn_async().await?;
+
+        let deleted: u64 = con.del(key).await?;
+
+        Ok(deleted > 0)
+    }
+}
@@ -1,11 +1,23 @@
 mod client_ip;
 mod cors;
+pub mod guard;
 pub mod jwt;
+mod lockout;
+pub mod password;
+pub mod password_policy;
 mod rate_limiter;
+pub mod secret;
+pub mod totp;
 mod user;
+pub mod webauthn;
 
 pub use client_ip::ClientIP;
 pub use cors::create_cors;
 pub use jwt::JWT;
-pub use rate_limiter::{Group, Identifier, Limiter, RateLimiter};
-pub use user::UserFromJWT;
+pub use lockout::{Credentials, Lockout};
+pub use rate_limiter::{
+    policy, Algorithm, Cidr, Identifier, RateLimitState, RateLimited, RateLimiter,
+};
+pub use user::{
+    authenticated, current_permissions, current_user, JwtAudience, UserCache, UserFromJWT,
+};
@@ -0,0 +1,338 @@
+//! Password hashing with argon2id, using the cost parameters and pepper in `[password]`.
+//! Hashes made with other parameters still verify, but are reported as outdated,
+//! so they can be replaced after a successful login.
+//! Imported bcrypt and PBKDF2 hashes are stored as `<algorithm>:<hash>` and are always outdated.
+//! These are CPU intensive, use `spawn_blocking` in an asynchronous context.
+
+use crate::config::PasswordConfig;
+use anyhow::Result;
+use openssl::{hash::MessageDigest, memcmp, pkcs5::pbkdf2_hmac, pkey::PKey, sign::Signer};
+use serde::Deserialize;
+use sodiumoxide::crypto::pwhash::argon2id13::{self, HashedPassword, MemLimit, OpsLimit};
+
+/// Prefix of hashes of peppered passwords. The argon2 encoding has no room for it.
+const PEPPERED: &str = "peppered:";
+
+/// Byte length of libsodium's encoded hash buffer.
+const ENCODED_LEN: usize = 128;
+
+/// The outcome of checking a password against a stored hash.
+#[derive(PartialEq, Debug)]
+pub enum Verification {
+    /// The password is wrong.
+    Mismatch,
+    /// The password is right and the hash uses the configured parameters.
+    Current,
+    /// The password is right, but the hash should be replaced with a new one.
+    Outdated,
+}
+
+/// Algorithms of other systems whose hashes can be imported.
+/// PBKDF2 hashes are written as `<iterations>$<base64 salt>$<base64 hash>`.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForeignAlgorithm {
+    Bcrypt,
+    Pbkdf2Sha1,
+    Pbkdf2Sha256,
+    Pbkdf2Sha512,
+}
+
+impl ForeignAlgorithm {
+    const ALL: [ForeignAlgorithm; 4] = [
+        ForeignAlgorithm::Bcrypt,
+        ForeignAlgorithm::Pbkdf2Sha1,
+        ForeignAlgorithm::Pbkdf2Sha256,
+        ForeignAlgorithm::Pbkdf2Sha512,
+    ];
+
+    /// Prefix of stored hashes, also the name used in imports.
+    pub fn tag(self) -> &'static str {
+        match self {
+            ForeignAlgorithm::Bcrypt => "bcrypt",
+            ForeignAlgorithm::Pbkdf2Sha1 => "pbkdf2-sha1",
+            ForeignAlgorithm::Pbkdf2Sha256 => "pbkdf2-sha256",
+            ForeignAlgorithm::Pbkdf2Sha512 => "pbkdf2-sha512",
+        }
+    }
+
+    fn digest(self) -> MessageDigest {
+        match self {
+            ForeignAlgorithm::Pbkdf2Sha1 => MessageDigest::sha1(),
+            ForeignAlgorithm::Pbkdf2Sha512 => MessageDigest::sha512(),
+            _ => MessageDigest::sha256(),
+        }
+    }
+
+    /// The algorithm and the hash itself, if the stored hash was imported.
+    fn split(stored: &str) -> Option<(Self, &str)> {
+        let separator = stored.find(':')?;
+        let tag = &stored[..separator];
+
+        Self::ALL
+            .iter()
+            .find(|algorithm| algorithm.tag() == tag)
+            .map(|algorithm| (*algorithm, &stored[separator + 1..]))
+    }
+}
+
+/// Parts of a PBKDF2 hash: iterations, salt and the derived key.
+fn pbkdf2_parts(hash: &str) -> Result<(usize, Vec<u8>, Vec<u8>)> {
+    let parts: Vec<&str> = hash.split('$').collect();
+
+    if parts.len() != 3 {
+        bail!("PBKDF2 hashes should be written as <iterations>$<base64 salt>$<base64 hash>.");
+    }
+
+    let iterations: usize = parts[0].parse()?;
+    let key = base64::decode(parts[2])?;
+
+    if iterations == 0 || key.is_empty() {
+        bail!("Invalid PBKDF2 hash.");
+    }
+
+    Ok((iterations, base64::decode(parts[1])?, key))
+}
+
+/// Check the format of a hash from another system and tag it with it's algorithm for storing.
+pub fn foreign(algorithm: ForeignAlgorithm, hash: &str) -> Result<String> {
+    match algorithm {
+        ForeignAlgorithm::Bcrypt => {
+            if hash.len() != 60 || !["$2a$", "$2b$", "$2y$"].iter().any(|v| hash.starts_with(v)) {
+                bail!("Invalid bcrypt hash.");
+            }
+        }
+        _ => {
+            pbkdf2_parts(hash)?;
+        }
+    }
+
+    Ok(format!("{}:{}", algorithm.tag(), hash))
+}
+
+fn verify_foreign(algorithm: ForeignAlgorithm, hash: &str, password: &str) -> Result<bool> {
+    if algorithm == ForeignAlgorithm::Bcrypt {
+        return Ok(bcrypt::verify(password, hash)?);
+    }
+
+    let (iterations, salt, key) = pbkdf2_parts(hash)?;
+    let mut derived = vec![0u8; key.len()];
+
+    pbkdf2_hmac(
+        password.as_bytes(),
+        &salt,
+        iterations,
+        algorithm.digest(),
+        &mut derived,
+    )?;
+
+    Ok(memcmp::eq(&derived, &key))
+}
+
+/// The bytes that are hashed: the password itself, or it's HMAC with the pepper.
+fn input(pepper: &str, password: &str) -> Result<Vec<u8>> {
+    if pepper.is_empty() {
+        return Ok(password.as_bytes().to_vec());
+    }
+
+    let key = PKey::hmac(pepper.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+
+    signer.update(password.as_bytes())?;
+
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Memory in KiB and iterations of an encoded argon2id hash.
+fn parameters(encoded: &str) -> Option<(usize, usize)> {
+    let mut parts = encoded.split('$');
+
+    if parts.nth(1) != Some("argon2id") {
+        return None;
+    }
+
+    let (mut memory, mut iterations) = (None, None);
+
+    for param in parts.nth(1)?.split(',') {
+        match param.split_at(param.find('=')?) {
+            ("m", value) => memory = value[1..].parse().ok(),
+            ("t", value) => iterations = value[1..].parse().ok(),
+            _ => (),
+        }
+    }
+
+    Some((memory?, iterations?))
+}
+
+/// Hash the password with the configured parameters and pepper.
+pub fn hash(conf: &PasswordConfig, password: &str) -> Result<String> {
+    sodiumoxide::init().unwrap();
+
+    let hashed = match argon2id13::pwhash(
+        &input(&conf.pepper, password)?,
+        OpsLimit(conf.ops_limit),
+        MemLimit(conf.mem_limit),
+    ) {
+        Ok(h) => h,
+        Err(()) => bail!("Failed to hash password."),
+    };
+
+    let encoded = std::str::from_utf8(&hashed.0)?.trim_end_matches('\u{0}');
+
+    Ok(if conf.pepper.is_empty() {
+        encoded.to_string()
+    } else {
+        format!("{}{}", PEPPERED, encoded)
+    })
+}
+
+/// Check the password against a stored hash.
+pub fn verify(conf: &PasswordConfig, stored: &str, password: &str) -> Result<Verification> {
+    if let Some((algorithm, hash)) = ForeignAlgorithm::split(stored) {
+        return Ok(if verify_foreign(algorithm, hash, password)? {
+            Verification::Outdated
+        } else {
+            Verification::Mismatch
+        });
+    }
+
+    sodiumoxide::init().unwrap();
+
+    let (encoded, peppered) = match stored.strip_prefix(PEPPERED) {
+        Some(encoded) => (encoded, true),
+        None => (stored, false),
+    };
+
+    if peppered && conf.pepper.is_empty() {
+        bail!("The password was hashed with a pepper, but none is configured.");
+    }
+
+    if encoded.len() >= ENCODED_LEN {
+        bail!("Invalid password hash.");
+    }
+
+    let mut padded = [0u8; ENCODED_LEN];
+    padded[..encoded.len()].copy_from_slice(encoded.as_bytes());
+
+    let hashed = match HashedPassword::from_slice(&padded) {
+        Some(hashed) => hashed,
+        None => bail!("Invalid password hash."),
+    };
+
+    let pepper = if peppered { conf.pepper.as_str() } else { "" };
+
+    if !argon2id13::pwhash_verify(&hashed, &input(pepper, password)?) {
+        return Ok(Verification::Mismatch);
+    }
+
+    let current = peppered != conf.pepper.is_empty()
+        && parameters(encoded) == Some((conf.mem_limit / 1024, conf.ops_limit));
+
+    Ok(if current {
+        Verification::Current
+    } else {
+        Verification::Outdated
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_conf(pepper: &str) -> PasswordConfig {
+        PasswordConfig {
+            ops_limit: argon2id13::OPSLIMIT_INTERACTIVE.0,
+            mem_limit: argon2id13::MEMLIMIT_INTERACTIVE.0,
+            pepper: pepper.into(),
+        }
+    }
+
+    #[test]
+    fn hash_verify() {
+        let conf = test_conf("");
+        let hashed = hash(&conf, "a_password").unwrap();
+
+        assert_eq!(
+            verify(&conf, &hashed, "a_password").unwrap(),
+            Verification::Current
+        );
+        assert_eq!(
+            verify(&conf, &hashed, "wrong_password").unwrap(),
+            Verification::Mismatch
+        );
+    }
+
+    /// Changing the parameters or adding a pepper makes existing hashes outdated.
+    #[test]
+    fn outdated() {
+        let conf = test_conf("");
+        let hashed = hash(&conf, "a_password").unwrap();
+
+        let stronger = PasswordConfig {
+            ops_limit: conf.ops_limit + 1,
+            ..test_conf("")
+        };
+        assert_eq!(
+            verify(&stronger, &hashed, "a_password").unwrap(),
+            Verification::Outdated
+        );
+
+        let peppered = test_conf("a_pepper");
+        assert_eq!(
+            verify(&peppered, &hashed, "a_password").unwrap(),
+            Verification::Outdated
+        );
+
+        let rehashed = hash(&peppered, "a_password").unwrap();
+        assert!(rehashed.starts_with(PEPPERED));
+        assert_eq!(
+            verify(&peppered, &rehashed, "a_password").unwrap(),
+            Verification::Current
+        );
+
+        // Peppered hashes can't be checked without the pepper
+        assert!(verify(&conf, &rehashed, "a_password").is_err());
+    }
+
+    /// Imported hashes verify, and should be replaced after a successful login.
+    #[test]
+    fn foreign_hashes() {
+        let conf = test_conf("");
+
+        let bcrypt = foreign(
+            ForeignAlgorithm::Bcrypt,
+            &bcrypt::hash("a_password", 4).unwrap(),
+        )
+        .unwrap();
+        assert!(bcrypt.starts_with("bcrypt:$2"));
+
+        let mut key = [0u8; 32];
+        pbkdf2_hmac(
+            b"a_password",
+            b"salt",
+            1000,
+            MessageDigest::sha256(),
+            &mut key,
+        )
+        .unwrap();
+        let pbkdf2 = foreign(
+            ForeignAlgorithm::Pbkdf2Sha256,
+            &format!("1000${}${}", base64::encode(b"salt"), base64::encode(key)),
+        )
+        .unwrap();
+
+        for stored in [bcrypt, pbkdf2].iter() {
+            assert_eq!(
+                verify(&conf, stored, "a_password").unwrap(),
+                Verification::Outdated
+            );
+            assert_eq!(
+                verify(&conf, stored, "wrong_password").unwrap(),
+                Verification::Mismatch
+            );
+        }
+
+        assert!(foreign(ForeignAlgorithm::Bcrypt, "not a hash").is_err());
+        assert!(foreign(ForeignAlgorithm::Pbkdf2Sha1, "1000$c2FsdA==").is_err());
+    }
+}
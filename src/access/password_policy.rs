@@ -0,0 +1,339 @@
+//! Rules new passwords have to follow, configured in `[password_policy]`.
+//! Custom rules can be added by implementing `PasswordRule`.
+
+use crate::{
+    config::PasswordPolicyConfig,
+    gql::{E, R},
+};
+use anyhow::Result;
+use openssl::hash::{hash, MessageDigest};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind},
+    path::PathBuf,
+};
+use tokio::task::spawn_blocking;
+use validator::{ValidationError, ValidationErrors};
+
+/// Who the password is for, if known. Passwords shouldn't contain these.
+#[derive(Clone, Default, Debug)]
+pub struct PasswordOwner {
+    pub username: Option<String>,
+    pub email: Option<String>,
+}
+
+/// A single requirement. Returns a message ending the sentence "Field password ..."
+/// if the password breaks it. Errors are for failures of the check itself.
+pub trait PasswordRule: Send + Sync {
+    fn check(&self, password: &str, owner: &PasswordOwner) -> Result<Option<String>>;
+}
+
+/// Length in characters, not bytes.
+pub struct Length {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl PasswordRule for Length {
+    fn check(&self, password: &str, _: &PasswordOwner) -> Result<Option<String>> {
+        let length = password.chars().count();
+
+        Ok(if length < self.min || length > self.max {
+            Some(format!("should be {} to {} characters", self.min, self.max))
+        } else {
+            None
+        })
+    }
+}
+
+/// Estimated entropy in bits: the length times the bits per character, which are limited both
+/// by the character classes used and how evenly the characters are distributed.
+/// Repeated characters and small alphabets are penalized, long passphrases are not.
+pub fn entropy(password: &str) -> f64 {
+    let (mut lower, mut upper, mut digit, mut symbol, mut other) = (0, 0, 0, 0, 0);
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    let mut length = 0;
+
+    for c in password.chars() {
+        match c {
+            'a'..='z' => lower = 26,
+            'A'..='Z' => upper = 26,
+            '0'..='9' => digit = 10,
+            c if c.is_ascii() => symbol = 33,
+            _ => other = 100,
+        }
+
+        *counts.entry(c).or_insert(0) += 1;
+        length += 1;
+    }
+
+    if length == 0 {
+        return 0.0;
+    }
+
+    let alphabet = (lower + upper + digit + symbol + other) as f64;
+
+    let distribution: f64 = counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / length as f64;
+
+            -p * p.log2()
+        })
+        .sum();
+
+    length as f64 * distribution.min(alphabet.log2())
+}
+
+pub struct Entropy {
+    pub min_bits: f64,
+}
+
+impl PasswordRule for Entropy {
+    fn check(&self, password: &str, _: &PasswordOwner) -> Result<Option<String>> {
+        Ok(if entropy(password) < self.min_bits {
+            Some("is too easy to guess, use more varied characters or more words".into())
+        } else {
+            None
+        })
+    }
+}
+
+/// The username, or the part of the email address before `@`.
+pub struct NoPersonalInfo;
+
+impl PasswordRule for NoPersonalInfo {
+    fn check(&self, password: &str, owner: &PasswordOwner) -> Result<Option<String>> {
+        let password = password.to_lowercase();
+
+        let local_part = owner
+            .email
+            .as_ref()
+            .and_then(|email| email.split('@').next());
+
+        let found = owner
+            .username
+            .as_deref()
+            .into_iter()
+            .chain(local_part)
+            // Very short ones could appear by chance
+            .filter(|info| info.chars().count() >= 3)
+            .any(|info| password.contains(&info.to_lowercase()));
+
+        Ok(if found {
+            Some("should not contain your username or email address".into())
+        } else {
+            None
+        })
+    }
+}
+
+/// Known breached passwords, in the k-anonymity layout of the Pwned Passwords range API.
+/// The directory has a file for every 5 character prefix of the uppercase hex SHA-1,
+/// with lines of the remaining 35 characters and a count: `<SUFFIX>:<COUNT>`.
+/// Only the file of the password's prefix is read.
+pub struct Breached {
+    pub dir: PathBuf,
+}
+
+impl PasswordRule for Breached {
+    fn check(&self, password: &str, _: &PasswordOwner) -> Result<Option<String>> {
+        let digest: String = hash(MessageDigest::sha1(), password.as_bytes())?
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+
+        let (prefix, suffix) = digest.split_at(5);
+
+        let file = match File::open(self.dir.join(prefix)) {
+            Ok(file) => file,
+            // No file means no breached passwords with the prefix
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        for line in BufReader::new(file).lines() {
+            if line?.split(':').next().map(str::trim) == Some(suffix) {
+                return Ok(Some(
+                    "has appeared in a data breach, choose another one".into(),
+                ));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Every rule is checked, and all the broken ones are reported.
+#[derive(Default)]
+pub struct PasswordPolicy {
+    rules: Vec<Box<dyn PasswordRule>>,
+}
+
+impl PasswordPolicy {
+    pub fn new() -> Self {
+        PasswordPolicy { rules: vec![] }
+    }
+
+    /// The rules enabled in the config.
+    pub fn from_config(conf: &PasswordPolicyConfig) -> Self {
+        let mut policy = PasswordPolicy::new()
+            .with_rule(Length {
+                min: conf.min_length,
+                max: conf.max_length,
+            })
+            .with_rule(Entropy {
+                min_bits: conf.min_entropy,
+            })
+            .with_rule(NoPersonalInfo);
+
+        if !conf.breached_list.is_empty() {
+            policy = policy.with_rule(Breached {
+                dir: conf.breached_list.clone().into(),
+            });
+        }
+
+        policy
+    }
+
+    pub fn with_rule<T: PasswordRule + 'static>(mut self, rule: T) -> Self {
+        self.rules.push(Box::new(rule));
+
+        self
+    }
+
+    /// Check the password against every rule.
+    /// Broken rules are reported as errors of the field, like other input validation.
+    pub fn check(
+        &self,
+        field: &'static str,
+        password: &str,
+        owner: &PasswordOwner,
+    ) -> Result<Result<(), ValidationErrors>> {
+        let mut errors = ValidationErrors::new();
+
+        for rule in &self.rules {
+            if let Some(message) = rule.check(password, owner)? {
+                let mut error = ValidationError::new("password_policy");
+                error.message = Some(Cow::from(message));
+
+                errors.add(field, error);
+            }
+        }
+
+        Ok(if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        })
+    }
+}
+
+/// Check a new password with the configured policy in a blocking thread,
+/// since the breached password list is read from disk.
+pub async fn validate(
+    conf: &PasswordPolicyConfig,
+    field: &'static str,
+    password: &str,
+    owner: PasswordOwner,
+) -> R<()> {
+    let policy = PasswordPolicy::from_config(conf);
+    let password = password.to_string();
+
+    spawn_blocking(move || policy.check(field, &password, &owner))
+        .await??
+        .map_err(E::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_conf() -> PasswordPolicyConfig {
+        PasswordPolicyConfig {
+            min_length: 12,
+            max_length: 128,
+            min_entropy: 50.0,
+            breached_list: String::new(),
+        }
+    }
+
+    fn messages(password: &str, owner: &PasswordOwner) -> Vec<String> {
+        match PasswordPolicy::from_config(&test_conf())
+            .check("password", password, owner)
+            .unwrap()
+        {
+            Ok(()) => vec![],
+            Err(errors) => errors.field_errors()["password"]
+                .iter()
+                .map(|error| error.message.as_ref().unwrap().to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn entropy_estimate() {
+        assert_eq!(entropy(""), 0.0);
+        assert_eq!(entropy("                    "), 0.0);
+        assert!(entropy("aaaaaaaaaaaaaaaaaaab") < 10.0);
+        assert!(entropy("correct horse battery staple") > 80.0);
+    }
+
+    #[test]
+    fn policy() {
+        let owner = PasswordOwner {
+            username: Some("policy_user".into()),
+            email: Some("someone@email.com".into()),
+        };
+
+        assert!(messages("correct horse battery staple", &owner).is_empty());
+
+        // Passphrases longer than 50 characters are fine
+        assert!(messages(
+            "a much longer passphrase of many words that is over fifty characters",
+            &owner
+        )
+        .is_empty());
+
+        // Twenty spaces
+        assert_eq!(messages("                    ", &owner).len(), 1);
+
+        // Too short and too easy at once
+        assert_eq!(messages("aaaa", &owner).len(), 2);
+
+        assert_eq!(
+            messages("my name is Policy_User, hi there", &owner),
+            vec!["should not contain your username or email address"]
+        );
+        assert_eq!(messages("someone else's passphrase", &owner).len(), 1);
+    }
+
+    #[test]
+    fn breached() {
+        let dir = std::env::temp_dir().join(format!("dia_breached_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // SHA-1 of "correct horse battery staple"
+        std::fs::write(
+            dir.join("ABF7A"),
+            "0000000000000000000000000000000000A:1\nAD6438836DBE526AA231ABDE2D0EEF74D42:42\n",
+        )
+        .unwrap();
+
+        let rule = Breached { dir: dir.clone() };
+        let owner = PasswordOwner::default();
+
+        assert!(rule
+            .check("correct horse battery staple", &owner)
+            .unwrap()
+            .is_some());
+        assert!(rule
+            .check("a passphrase nobody has used", &owner)
+            .unwrap()
+            .is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use super::{Algorithm, Limiter, RateLimitState, RateLimitStore};
+use anyhow::Result;
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryFrom,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Expired buckets are removed when there are at least this many.
+const PRUNE_AT: usize = 1024;
+
+/// Longest a GCRA bucket can be overdrawn for, so a huge cost can't overflow the time.
+const MAX_DEBT: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+enum Bucket {
+    /// Cost counted in the window, which ends when the bucket expires.
+    Window(u64),
+    /// Times of the allowed requests in the window, once for each unit of cost up to the capacity.
+    Log(VecDeque<Instant>),
+    /// Theoretical arrival time of the next request.
+    Gcra(Instant),
+}
+
+/// Buckets of a single process, the same algorithms as `RedisStore`.
+/// For a single instance, tests and falling back when Redis can't be reached.
+#[derive(Default)]
+pub struct MemoryStore {
+    /// The bucket and when it expires, by key.
+    buckets: Mutex<HashMap<String, (Bucket, Instant)>>,
+}
+
+impl MemoryStore {
+    /// Count a request made at `now`.
+    fn count(&self, limiter: &Limiter, now: Instant) -> RateLimitState {
+        let window = limiter.bucket_lifetime;
+        let capacity = limiter.full_count;
+        let cost = limiter.cost;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= PRUNE_AT {
+            buckets.retain(|_, (_, expires)| *expires > now);
+        }
+
+        let (bucket, expires) = buckets
+            .entry(limiter.key())
+            .or_insert_with(|| (Bucket::Window(0), now));
+
+        if *expires <= now {
+            *bucket = match limiter.algorithm {
+                Algorithm::FixedWindow => Bucket::Window(0),
+                Algorithm::SlidingLog => Bucket::Log(VecDeque::new()),
+                Algorithm::Gcra => Bucket::Gcra(now),
+            };
+
+            if limiter.algorithm == Algorithm::FixedWindow {
+                *expires = now + window;
+            }
+        }
+
+        // Remaining capacity, until the full capacity is back, and until the next request
+        let (remaining, reset, retry_after) = match bucket {
+            Bucket::Window(count) => {
+                let ttl = *expires - now;
+
+                if *count >= capacity {
+                    (0, ttl, Some(ttl))
+                } else {
+                    *count = count.saturating_add(cost);
+
+                    (capacity.saturating_sub(*count), ttl, None)
+                }
+            }
+            Bucket::Log(times) => {
+                while matches!(times.front(), Some(time) if *time + window <= now) {
+                    times.pop_front();
+                }
+
+                let until = |time: Option<&Instant>| {
+                    time.map_or(Duration::from_secs(0), |time| *time + window - now)
+                };
+
+                if times.len() as u64 >= capacity {
+                    (0, until(times.back()), Some(until(times.front())))
+                } else {
+                    if cost > 0 {
+                        // More than the capacity would only be stored, not blocked longer
+                        times.extend((0..cost.min(capacity)).map(|_| now));
+                        *expires = now + window;
+                    }
+
+                    let remaining = capacity.saturating_sub(times.len() as u64);
+
+                    (remaining, until(times.back()), None)
+                }
+            }
+            Bucket::Gcra(tat) => {
+                let interval = window / capacity.max(1) as u32;
+                let start = (*tat).max(now);
+                let next = start + interval;
+
+                if next > now + window {
+                    (0, *tat - now, Some(next - (now + window)))
+                } else {
+                    let debt = interval
+                        .checked_mul(u32::try_from(cost).unwrap_or(u32::MAX))
+                        .unwrap_or(MAX_DEBT)
+                        .min(MAX_DEBT);
+
+                    *tat = start + debt;
+                    *expires = *tat;
+
+                    let remaining = (now + window).saturating_duration_since(*tat).as_nanos()
+                        / interval.as_nanos().max(1);
+
+                    (remaining as u64, *tat - now, None)
+                }
+            }
+        };
+
+        RateLimitState {
+            policy: limiter.policy.clone(),
+            identifier: limiter.identifier.to_string(),
+            limit: capacity,
+            remaining,
+            reset,
+            retry_after,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn check(&self, limiter: &Limiter) -> Result<RateLimitState> {
+        Ok(self.count(limiter, Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::Identifier;
+
+    fn limiter(algorithm: Algorithm) -> Limiter {
+        let mut limiter = Limiter::new(
+            "test",
+            Identifier::Address("127.0.0.1".parse().unwrap()),
+            Duration::from_secs(60),
+            3,
+        );
+        limiter.algorithm(algorithm);
+
+        limiter
+    }
+
+    /// Every algorithm lets the capacity through, and the next request after the window.
+    #[test]
+    fn algorithms() {
+        for algorithm in [
+            Algorithm::FixedWindow,
+            Algorithm::SlidingLog,
+            Algorithm::Gcra,
+        ]
+        .iter()
+        {
+            let store = MemoryStore::default();
+            let limiter = limiter(*algorithm);
+            let start = Instant::now();
+
+            for remaining in (0..3).rev() {
+                let state = store.count(&limiter, start);
+
+                assert!(state.allowed(), "{}", algorithm);
+                assert_eq!(state.remaining, remaining, "{}", algorithm);
+            }
+
+            let state = store.count(&limiter, start + Duration::from_secs(1));
+            assert!(!state.allowed(), "{}", algorithm);
+            assert!(state.retry_after.unwrap() <= Duration::from_secs(60));
+
+            let state = store.count(&limiter, start + Duration::from_secs(61));
+            assert!(state.allowed(), "{}", algorithm);
+        }
+    }
+
+    /// GCRA lets requests through evenly after a burst.
+    #[test]
+    fn gcra_interval() {
+        let store = MemoryStore::default();
+        let limiter = limiter(Algorithm::Gcra);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(store.count(&limiter, start).allowed());
+        }
+
+        let state = store.count(&limiter, start);
+        assert_eq!(state.retry_after, Some(Duration::from_secs(20)));
+
+        assert!(!store
+            .count(&limiter, start + Duration::from_secs(19))
+            .allowed());
+        assert!(store
+            .count(&limiter, start + Duration::from_secs(20))
+            .allowed());
+    }
+
+    /// A costly request is let through while any capacity is left, and overdraws the bucket.
+    #[test]
+    fn cost() {
+        for algorithm in [
+            Algorithm::FixedWindow,
+            Algorithm::SlidingLog,
+            Algorithm::Gcra,
+        ]
+        .iter()
+        {
+            let store = MemoryStore::default();
+            let mut limiter = limiter(*algorithm);
+            let start = Instant::now();
+
+            // Checking doesn't use the capacity
+            limiter.cost(0);
+            assert_eq!(store.count(&limiter, start).remaining, 3, "{}", algorithm);
+
+            limiter.cost(2);
+            assert_eq!(store.count(&limiter, start).remaining, 1, "{}", algorithm);
+
+            let state = store.count(&limiter, start);
+            assert!(state.allowed(), "{}", algorithm);
+            assert_eq!(state.remaining, 0, "{}", algorithm);
+
+            limiter.cost(0);
+            assert!(!store.count(&limiter, start).allowed(), "{}", algorithm);
+        }
+    }
+
+    /// A huge cost overdraws the bucket instead of overflowing.
+    #[test]
+    fn huge_cost() {
+        for algorithm in [
+            Algorithm::FixedWindow,
+            Algorithm::SlidingLog,
+            Algorithm::Gcra,
+        ]
+        .iter()
+        {
+            let store = MemoryStore::default();
+            let mut limiter = limiter(*algorithm);
+            let start = Instant::now();
+
+            limiter.cost(u64::MAX);
+            assert!(store.count(&limiter, start).allowed(), "{}", algorithm);
+            assert!(!store.count(&limiter, start).allowed(), "{}", algorithm);
+        }
+    }
+}
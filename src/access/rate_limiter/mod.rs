@@ -0,0 +1,806 @@
+//! Rate limits by named policies in `[rate_limits]`, counted in a `RateLimitStore`.
+
+mod memory_store;
+mod redis_store;
+
+pub use memory_store::MemoryStore;
+pub use redis_store::RedisStore;
+
+use crate::{
+    config::{FailurePolicy, RateLimitPolicy, RateLimitStoreKind, RateLimitsConfig},
+    db::RedisConn,
+    Res,
+};
+use actix_web::{
+    dev::Payload,
+    http::{header::RETRY_AFTER, HeaderMap, HeaderName, HeaderValue},
+    FromRequest, HttpRequest,
+};
+use anyhow::Result;
+use async_graphql::ErrorExtensions;
+use futures::future::{err, ok, Ready};
+use humantime::format_duration;
+use serde::Deserialize;
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// Policies the resolvers use. Every one of them has to be in `[rate_limits.policies]`,
+/// more can be added for the global policy.
+pub mod policy {
+    /// Logging in with a password, a passkey or a magic link.
+    pub const LOGIN: &str = "login";
+    /// Creating users.
+    pub const REGISTER: &str = "register";
+    /// Requesting password reset emails.
+    pub const PASSWORD_RESET: &str = "password_reset";
+    /// Requesting magic link emails.
+    pub const MAGIC_LINK: &str = "magic_link";
+
+    pub const ALL: [&str; 4] = [LOGIN, REGISTER, PASSWORD_RESET, MAGIC_LINK];
+}
+
+/// How requests are counted. Every store implements each of them.
+#[derive(Deserialize, Display, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    FixedWindow,
+    SlidingLog,
+    Gcra,
+}
+
+/// How the client is identified. Address when a user is not known, and a user when possible.
+/// Refresh token is used when wanting to rate limit a single session.
+#[derive(Display, Clone, Copy)]
+pub enum Identifier {
+    Address(IpAddr),
+    User(Uuid),
+}
+
+/// A range of addresses, written as `10.0.0.0/8` or `::1/128`.
+/// An address without a prefix length is a range of one.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                mask_eq(&net.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                mask_eq(&net.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the first `prefix` bits are the same.
+fn mask_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    a.iter().zip(b).enumerate().all(|(i, (a, b))| {
+        let bits = (prefix as usize).saturating_sub(i * 8).min(8);
+        let mask = (0xff00u16 >> bits) as u8;
+
+        a & mask == b & mask
+    })
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (address, prefix) = match s.find('/') {
+            Some(i) => (s[..i].parse::<IpAddr>()?, Some(s[i + 1..].parse::<u8>()?)),
+            None => (s.parse::<IpAddr>()?, None),
+        };
+
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+
+        if prefix > max {
+            bail!("Invalid prefix length in {}.", s);
+        }
+
+        Ok(Cidr { address, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// Where the client stands in a policy after a request.
+#[derive(Clone, Debug)]
+pub struct RateLimitState {
+    pub policy: String,
+    pub identifier: String,
+    /// Requests allowed in the window.
+    pub limit: u64,
+    /// Requests left right now.
+    pub remaining: u64,
+    /// Until the full capacity is available again.
+    pub reset: Duration,
+    /// Until the next request is allowed, if this one was denied.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitState {
+    pub fn allowed(&self) -> bool {
+        self.retry_after.is_none()
+    }
+
+    /// `Retry-After` in whole seconds, 0 if the request was allowed.
+    pub fn retry_after_seconds(&self) -> u64 {
+        seconds(self.retry_after.unwrap_or_default())
+    }
+
+    /// Set the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers,
+    /// and `Retry-After` if the request was denied. Times are in whole seconds, rounded up.
+    pub fn set_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            ("ratelimit-limit", self.limit),
+            ("ratelimit-remaining", self.remaining),
+            ("ratelimit-reset", seconds(self.reset)),
+        ];
+
+        for (name, value) in values.iter() {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from(*value));
+        }
+
+        if !self.allowed() {
+            headers.insert(RETRY_AFTER, HeaderValue::from(self.retry_after_seconds()));
+        }
+    }
+}
+
+/// Whole seconds, rounded up. Clients waiting for less would be denied again.
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + (duration.subsec_nanos() > 0) as u64
+}
+
+/// The error of a denied request.
+#[derive(Debug)]
+pub struct RateLimited(pub RateLimitState);
+
+impl Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "You are rate limited! Try again in {}. (In policy '{}', identified by {}).",
+            format_duration(Duration::from_secs(self.0.retry_after_seconds())),
+            self.0.policy,
+            self.0.identifier
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Denials get the extensions `code: "RATE_LIMITED"` and `retryAfter` in seconds.
+impl ErrorExtensions for RateLimited {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string()).extend_with(|_, extensions| {
+            extensions.set("code", "RATE_LIMITED");
+            extensions.set("retryAfter", self.0.retry_after_seconds());
+        })
+    }
+}
+
+impl RateLimited {
+    /// The GraphQL error of a failed `limit`, with the extensions if it was a denial.
+    pub fn gql_error(error: anyhow::Error) -> async_graphql::Error {
+        match error.downcast_ref::<RateLimited>() {
+            Some(rate_limited) => rate_limited.extend(),
+            None => async_graphql::Error::new(error.to_string()),
+        }
+    }
+}
+
+pub struct Limiter {
+    /// Name of the policy, keeps the buckets of policies apart.
+    pub policy: String,
+    pub identifier: Identifier,
+    /// The window of the algorithm.
+    pub bucket_lifetime: Duration,
+    /// What the buckets requests left -count is set as when created.
+    pub full_count: u64,
+    pub algorithm: Algorithm,
+    /// How much of the capacity the request uses, 1 by default. A request is allowed while
+    /// any capacity is left, so a costly one can overdraw the bucket. 0 only checks.
+    pub cost: u64,
+}
+
+impl Limiter {
+    /// Create a new limiter, counting with a fixed window.
+    pub fn new(
+        policy: &str,
+        identifier: Identifier,
+        bucket_lifetime: Duration,
+        full_count: u64,
+    ) -> Limiter {
+        Limiter {
+            policy: policy.to_string(),
+            identifier,
+            bucket_lifetime,
+            full_count,
+            algorithm: Algorithm::FixedWindow,
+            cost: 1,
+        }
+    }
+
+    /// Limiter of a configured policy, with the identifier's override if it has one.
+    pub fn from_policy(name: &str, policy: &RateLimitPolicy, identifier: Identifier) -> Limiter {
+        let full_count = policy
+            .overrides
+            .get(&identifier.to_string())
+            .copied()
+            .unwrap_or(policy.capacity);
+
+        Limiter {
+            policy: name.to_string(),
+            identifier,
+            bucket_lifetime: Duration::from_secs(policy.window),
+            full_count,
+            algorithm: policy.algorithm,
+            cost: 1,
+        }
+    }
+
+    /// Key of the client's bucket. Each algorithm stores it differently.
+    fn key(&self) -> String {
+        format!("RL_{}_{}_{}", self.algorithm, self.policy, self.identifier)
+    }
+
+    /// Set the bucket lifetime as seconds.
+    pub fn lifetime_seconds(&mut self, seconds: u64) -> &mut Self {
+        self.bucket_lifetime = Duration::from_secs(seconds);
+
+        self
+    }
+
+    /// Set the default bucket count it starts with.
+    pub fn full_count(&mut self, count: u64) -> &mut Self {
+        self.full_count = count;
+
+        self
+    }
+
+    /// Set the algorithm used to count the requests.
+    pub fn algorithm(&mut self, algorithm: Algorithm) -> &mut Self {
+        self.algorithm = algorithm;
+
+        self
+    }
+
+    /// Set how much of the capacity the request uses.
+    pub fn cost(&mut self, cost: u64) -> &mut Self {
+        self.cost = cost;
+
+        self
+    }
+
+    /// Set to the address to enum variant `Identifier::Address`, with the given address.
+    pub fn address(&mut self, addr: IpAddr) -> &mut Self {
+        self.identifier = Identifier::Address(addr);
+
+        self
+    }
+
+    /// Identifier to `Identifier::User` with the user's UUID.
+    pub fn user(&mut self, user: Uuid) -> &mut Self {
+        self.identifier = Identifier::User(user);
+
+        self
+    }
+}
+
+/// Where the buckets are kept.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Count the request's cost, atomically with checking if the rate limit is exceeded.
+    /// A denied request is not an error, see `RateLimitState::allowed`.
+    async fn check(&self, limiter: &Limiter) -> Result<RateLimitState>;
+}
+
+/// Global rate limiter keeping track of client request rates per IP address.
+/// Different resources are in different rate limiting buckets, configured in `[rate_limits]`.
+/// Every request gets it's own record of the denials, to be reported in the response.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    /// Used while the store fails, with `FailurePolicy::Local`.
+    fallback: Arc<MemoryStore>,
+    conf: RateLimitsConfig,
+    denied: Arc<Mutex<Vec<RateLimitState>>>,
+}
+
+impl RateLimiter {
+    /// Use the configured store.
+    /// Panics if a policy the resolvers, the global limit or the cost budget use is not configured.
+    pub fn new(redis_conn: RedisConn, conf: &RateLimitsConfig) -> Self {
+        let store: Arc<dyn RateLimitStore> = match conf.store {
+            RateLimitStoreKind::Redis => Arc::new(RedisStore::new(redis_conn)),
+            RateLimitStoreKind::Memory => Arc::new(MemoryStore::default()),
+        };
+
+        RateLimiter::with_store(store, conf)
+    }
+
+    /// Panics if a policy the resolvers, the global limit or the cost budget use is not configured.
+    pub fn with_store(store: Arc<dyn RateLimitStore>, conf: &RateLimitsConfig) -> Self {
+        let optional = [conf.global.as_str(), conf.cost.as_str()];
+        let optional = optional.iter().copied().filter(|name| !name.is_empty());
+
+        for name in policy::ALL.iter().copied().chain(optional) {
+            if !conf.policies.contains_key(name) {
+                panic!("Rate limit policy '{}' is not configured", name);
+            }
+        }
+
+        RateLimiter {
+            store,
+            fallback: Arc::default(),
+            conf: conf.clone(),
+            denied: Arc::default(),
+        }
+    }
+
+    /// A rate limiter with an empty record of denials.
+    pub fn for_request(&self) -> Self {
+        RateLimiter {
+            denied: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Denials returned by `limit` so far.
+    pub fn denied(&self) -> Vec<RateLimitState> {
+        self.denied.lock().unwrap().clone()
+    }
+
+    /// Limiter of the named policy for the identifier.
+    /// `None` for allowlisted addresses, which are never limited.
+    pub fn limiter(&self, policy: &str, identifier: Identifier) -> Result<Option<Limiter>> {
+        if let Identifier::Address(address) = identifier {
+            if self
+                .conf
+                .allowlist
+                .iter()
+                .any(|cidr| cidr.contains(address))
+            {
+                return Ok(None);
+            }
+        }
+
+        match self.conf.policies.get(policy) {
+            Some(conf) => Ok(Some(Limiter::from_policy(policy, conf, identifier))),
+            None => bail!("Unknown rate limit policy '{}'.", policy),
+        }
+    }
+
+    /// Count a request of the identifier in the named policy.
+    /// Errors with `RateLimited` if the request is denied, and records the denial.
+    pub async fn limit(&self, policy: &str, identifier: Identifier) -> Result<()> {
+        let state = match self.limiter(policy, identifier)? {
+            Some(limiter) => self.run(&limiter).await?,
+            None => return Ok(()),
+        };
+
+        if !state.allowed() {
+            self.denied.lock().unwrap().push(state.clone());
+
+            return Err(RateLimited(state).into());
+        }
+
+        Ok(())
+    }
+
+    /// Count a request with the global policy.
+    /// `None` if there is no global policy, or the address is allowlisted.
+    pub async fn limit_global(&self, address: IpAddr) -> Result<Option<RateLimitState>> {
+        if self.conf.global.is_empty() {
+            return Ok(None);
+        }
+
+        match self.limiter(&self.conf.global, Identifier::Address(address))? {
+            Some(limiter) => Ok(Some(self.run(&limiter).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Check if the client has any of the cost budget left, without using it.
+    /// Errors with `RateLimited` if not, and records the denial.
+    /// Users are identified by their ID and anonymous clients by their address.
+    pub async fn check_cost(&self, identifier: Identifier) -> Result<()> {
+        match self.run_cost(identifier, 0).await? {
+            Some(state) if !state.allowed() => {
+                self.denied.lock().unwrap().push(state.clone());
+
+                Err(RateLimited(state).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Take the cost of an operation from the client's budget. The operation already ran,
+    /// so going over the budget only denies the next requests.
+    pub async fn charge_cost(&self, identifier: Identifier, cost: u64) -> Result<()> {
+        self.run_cost(identifier, cost).await.map(|_| ())
+    }
+
+    /// `None` if there is no cost policy, or the address is allowlisted.
+    async fn run_cost(&self, identifier: Identifier, cost: u64) -> Result<Option<RateLimitState>> {
+        if self.conf.cost.is_empty() {
+            return Ok(None);
+        }
+
+        match self.limiter(&self.conf.cost, identifier)? {
+            Some(mut limiter) => Ok(Some(self.run(limiter.cost(cost)).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Run a limiter check in the store. If the store fails, the failure policy decides.
+    pub async fn run(&self, limiter: &Limiter) -> Result<RateLimitState> {
+        let error = match self.store.check(limiter).await {
+            Ok(state) => return Ok(state),
+            Err(error) => error,
+        };
+
+        warn!(
+            "Rate limit store failed, using failure policy {:?}: {}",
+            self.conf.on_failure, error
+        );
+
+        match self.conf.on_failure {
+            FailurePolicy::Open => Ok(RateLimitState {
+                policy: limiter.policy.clone(),
+                identifier: limiter.identifier.to_string(),
+                limit: limiter.full_count,
+                remaining: limiter.full_count,
+                reset: Duration::from_secs(0),
+                retry_after: None,
+            }),
+            FailurePolicy::Closed => Err(error),
+            FailurePolicy::Local => self.fallback.check(limiter).await,
+        }
+    }
+}
+
+impl FromRequest for RateLimiter {
+    type Error = Res<()>;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.app_data::<RateLimiter>() {
+            Some(rate_limiter) => ok(rate_limiter.for_request()),
+            _ => {
+                error!("RateLimiter does not exists in app's data!");
+
+                err(Res::<()>::error("No RateLimiter in app's data"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::RedisConn, res::Res, Config, CONF_FILE};
+    use actix_web::{test, web, App};
+
+    fn test_limiter() -> Limiter {
+        Limiter {
+            policy: "test".into(),
+            identifier: Identifier::Address("127.0.0.1".parse().unwrap()),
+            bucket_lifetime: Duration::from_secs(60),
+            full_count: 10,
+            algorithm: Algorithm::FixedWindow,
+            cost: 1,
+        }
+    }
+
+    fn redis_conn() -> RedisConn {
+        RedisConn::new(&Config::from_file(CONF_FILE))
+    }
+
+    /// Fails without Redis, instead of falling back to counting in memory.
+    fn rate_limiter() -> RateLimiter {
+        let mut conf = Config::from_file(CONF_FILE).rate_limits;
+        conf.on_failure = FailurePolicy::Closed;
+
+        RateLimiter::new(redis_conn(), &conf)
+    }
+
+    async fn handler(rl: RateLimiter) -> Res<()> {
+        match rl.run(&test_limiter()).await {
+            Ok(state) if state.allowed() => Res::ok("", ()),
+            _ => Res::<()>::error(""),
+        }
+    }
+
+    /// Send too many requests. Rate limiter should deny them. Runs without Redis.
+    #[actix_rt::test]
+    async fn block_requests() {
+        let l = RateLimiter::with_store(
+            Arc::new(MemoryStore::default()),
+            &Config::from_file(CONF_FILE).rate_limits,
+        );
+
+        let mut app =
+            test::init_service(App::new().app_data(l).route("/", web::get().to(handler))).await;
+
+        // Should be fine because bucket started with 10
+        for _ in 0..10 {
+            assert!(
+                test::call_service(&mut app, test::TestRequest::default().to_request())
+                    .await
+                    .status()
+                    .is_success()
+            );
+        }
+
+        // Bucket capasity is now 0, should fail
+        assert!(
+            !test::call_service(&mut app, test::TestRequest::default().to_request())
+                .await
+                .status()
+                .is_success()
+        );
+    }
+
+    /// Parallel requests with their own connections. No more than `full_count` should get
+    /// through, and the keys should always expire.
+    #[actix_rt::test]
+    async fn parallel_requests() {
+        let l = rate_limiter();
+
+        for algorithm in [
+            Algorithm::FixedWindow,
+            Algorithm::SlidingLog,
+            Algorithm::Gcra,
+        ]
+        .iter()
+        {
+            let mut limiter = test_limiter();
+            limiter.user(Uuid::new_v4()).algorithm(*algorithm);
+
+            let results = futures::future::join_all((0..50).map(|_| l.run(&limiter))).await;
+
+            assert_eq!(
+                results
+                    .iter()
+                    .filter(|result| result.as_ref().unwrap().allowed())
+                    .count(),
+                10,
+                "{}",
+                algorithm
+            );
+
+            let ttl: i64 = redis::cmd("PTTL")
+                .arg(limiter.key())
+                .query_async(&mut redis_conn().conn_async().await.unwrap())
+                .await
+                .unwrap();
+            assert!(ttl > 0, "{}", algorithm);
+        }
+    }
+
+    #[test]
+    fn cidr() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains("10.1.200.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(!net.contains("::1".parse().unwrap()));
+
+        let net: Cidr = "192.168.1.128/25".parse().unwrap();
+        assert!(net.contains("192.168.1.200".parse().unwrap()));
+        assert!(!net.contains("192.168.1.100".parse().unwrap()));
+
+        let single: Cidr = "2001:db8::1".parse().unwrap();
+        assert!(single.contains("2001:db8::1".parse().unwrap()));
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("not an address".parse::<Cidr>().is_err());
+    }
+
+    /// Policies come from the config, with overrides and allowlisted addresses.
+    #[test]
+    fn policies() {
+        let mut conf = Config::from_file(CONF_FILE).rate_limits;
+        let user = Uuid::new_v4();
+
+        conf.allowlist = vec!["10.0.0.0/8".parse().unwrap()];
+        conf.policies.insert(
+            "test".into(),
+            toml::from_str(&format!(
+                r#"
+                algorithm = "gcra"
+                window = 60
+                capacity = 5
+                overrides = {{ "{}" = 100 }}
+                "#,
+                user
+            ))
+            .unwrap(),
+        );
+
+        let l = RateLimiter::new(redis_conn(), &conf);
+
+        let limiter = l
+            .limiter("test", Identifier::Address("127.0.0.1".parse().unwrap()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(limiter.full_count, 5);
+        assert_eq!(limiter.algorithm, Algorithm::Gcra);
+        assert_eq!(limiter.bucket_lifetime, Duration::from_secs(60));
+
+        let limiter = l.limiter("test", Identifier::User(user)).unwrap().unwrap();
+        assert_eq!(limiter.full_count, 100);
+
+        assert!(l
+            .limiter("test", Identifier::Address("10.20.30.40".parse().unwrap()))
+            .unwrap()
+            .is_none());
+
+        assert!(l.limiter("unknown", Identifier::User(user)).is_err());
+    }
+
+    /// The state counts down, and denials report when to try again.
+    #[actix_rt::test]
+    async fn state() {
+        let l = rate_limiter();
+
+        for algorithm in [
+            Algorithm::FixedWindow,
+            Algorithm::SlidingLog,
+            Algorithm::Gcra,
+        ]
+        .iter()
+        {
+            let mut limiter = test_limiter();
+            limiter.user(Uuid::new_v4()).algorithm(*algorithm);
+
+            for remaining in (0..10).rev() {
+                let state = l.run(&limiter).await.unwrap();
+
+                assert!(state.allowed(), "{}", algorithm);
+                assert_eq!(state.limit, 10);
+                assert_eq!(state.remaining, remaining, "{}", algorithm);
+                assert!(state.reset <= Duration::from_secs(60));
+            }
+
+            let state = l.run(&limiter).await.unwrap();
+            let retry_after = state.retry_after.unwrap();
+
+            assert_eq!(state.remaining, 0);
+            assert!(retry_after > Duration::from_secs(0), "{}", algorithm);
+            assert!(retry_after <= Duration::from_secs(60), "{}", algorithm);
+
+            let mut headers = HeaderMap::new();
+            state.set_headers(&mut headers);
+            assert_eq!(headers.get("RateLimit-Limit").unwrap(), "10");
+            assert_eq!(headers.get("RateLimit-Remaining").unwrap(), "0");
+            assert!(headers.contains_key("Retry-After"));
+        }
+    }
+
+    /// Denials from `limit` are errors, and recorded for the request.
+    #[actix_rt::test]
+    async fn denials() {
+        let mut conf = Config::from_file(CONF_FILE).rate_limits;
+        conf.on_failure = FailurePolicy::Closed;
+        conf.policies.insert(
+            "test".into(),
+            toml::from_str(
+                r#"
+                algorithm = "fixed-window"
+                window = 60
+                capacity = 1
+                overrides = {}
+                "#,
+            )
+            .unwrap(),
+        );
+
+        let l = RateLimiter::new(redis_conn(), &conf).for_request();
+        let user = Uuid::new_v4();
+
+        l.limit("test", Identifier::User(user)).await.unwrap();
+        assert!(l.denied().is_empty());
+
+        let error = l.limit("test", Identifier::User(user)).await.unwrap_err();
+        assert!(error.downcast_ref::<RateLimited>().is_some());
+        assert_eq!(l.denied().len(), 1);
+        assert_eq!(l.denied()[0].policy, "test");
+
+        // Shared by clones, but not by other requests
+        assert_eq!(l.clone().denied().len(), 1);
+        assert!(l.for_request().denied().is_empty());
+    }
+
+    /// The cost budget is checked before an operation and charged after it.
+    #[actix_rt::test]
+    async fn cost_budget() {
+        let mut conf = Config::from_file(CONF_FILE).rate_limits;
+        conf.on_failure = FailurePolicy::Closed;
+        conf.cost = "test".into();
+        conf.policies.insert(
+            "test".into(),
+            toml::from_str(
+                r#"
+                algorithm = "gcra"
+                window = 60
+                capacity = 10
+                overrides = {}
+                "#,
+            )
+            .unwrap(),
+        );
+
+        let l = RateLimiter::new(redis_conn(), &conf).for_request();
+        let user = Uuid::new_v4();
+
+        l.check_cost(Identifier::User(user)).await.unwrap();
+        // Over the budget, but it already ran
+        l.charge_cost(Identifier::User(user), 15).await.unwrap();
+        assert!(l.denied().is_empty());
+
+        let error = l.check_cost(Identifier::User(user)).await.unwrap_err();
+        assert!(error.downcast_ref::<RateLimited>().is_some());
+        assert_eq!(l.denied()[0].policy, "test");
+    }
+
+    struct FailingStore;
+
+    #[async_trait::async_trait]
+    impl RateLimitStore for FailingStore {
+        async fn check(&self, _: &Limiter) -> Result<RateLimitState> {
+            Err(anyhow!("Store is down"))
+        }
+    }
+
+    /// When the store fails, requests are let through, failed or counted locally.
+    #[actix_rt::test]
+    async fn failure_policies() {
+        let mut conf = Config::from_file(CONF_FILE).rate_limits;
+        let mut limiter = test_limiter();
+        limiter.full_count(1);
+
+        conf.on_failure = FailurePolicy::Open;
+        let l = RateLimiter::with_store(Arc::new(FailingStore), &conf);
+        assert!(l.run(&limiter).await.unwrap().allowed());
+        assert!(l.run(&limiter).await.unwrap().allowed());
+
+        conf.on_failure = FailurePolicy::Closed;
+        let l = RateLimiter::with_store(Arc::new(FailingStore), &conf);
+        assert!(l.run(&limiter).await.is_err());
+
+        conf.on_failure = FailurePolicy::Local;
+        let l = RateLimiter::with_store(Arc::new(FailingStore), &conf);
+        assert!(l.run(&limiter).await.unwrap().allowed());
+        // The fallback is shared by the requests
+        assert!(!l.for_request().run(&limiter).await.unwrap().allowed());
+    }
+}
@@ -0,0 +1,147 @@
+use super::{Algorithm, Limiter, RateLimitState, RateLimitStore};
+use crate::db::RedisConn;
+use anyhow::Result;
+use redis::Script;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Every script gets the key, the capacity, the window in milliseconds, an unique request ID
+// and the cost of the request. They return whether the request is allowed, how much capacity is
+// left, and the milliseconds until the full capacity is available again and until the next
+// request would be allowed. A request is allowed while any capacity is left, and its whole cost
+// is taken even if it's more than what is left. A cost of 0 only checks.
+// Time is taken from Redis, so the servers' clocks don't have to agree.
+// Effects replication is needed for writing after `TIME` on Redis versions before 5.
+
+/// A counter that is reset when the window expires. Bursts of twice the capacity
+/// are possible around the edge of two windows.
+const FIXED_WINDOW: &str = r#"
+local capacity = tonumber(ARGV[1])
+local cost = tonumber(ARGV[4])
+local count = tonumber(redis.call('GET', KEYS[1]) or 0)
+local ttl = math.max(redis.call('PTTL', KEYS[1]), 0)
+if count >= capacity then
+    return {0, 0, ttl, ttl}
+end
+if cost > 0 then
+    count = redis.call('INCRBY', KEYS[1], cost)
+    if redis.call('PTTL', KEYS[1]) < 0 then
+        redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    end
+    ttl = math.max(redis.call('PTTL', KEYS[1]), 0)
+end
+return {1, math.max(capacity - count, 0), ttl, 0}
+"#;
+
+/// Timestamps of the allowed requests in the window, in a sorted set, one for each unit of cost
+/// up to the capacity.
+/// Exact, but keeps up to capacity entries per client.
+const SLIDING_LOG: &str = r#"
+redis.replicate_commands()
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local capacity = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local cost = tonumber(ARGV[4])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+if count >= capacity then
+    local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+    local newest = redis.call('ZRANGE', KEYS[1], -1, -1, 'WITHSCORES')
+    return {
+        0,
+        0,
+        math.max(tonumber(newest[2] or now) + window - now, 0),
+        math.max(tonumber(oldest[2] or now) + window - now, 0)
+    }
+end
+for i = 1, math.min(cost, capacity) do
+    redis.call('ZADD', KEYS[1], now, ARGV[3] .. ':' .. i)
+end
+if cost > 0 then
+    redis.call('PEXPIRE', KEYS[1], window)
+end
+local newest = redis.call('ZRANGE', KEYS[1], -1, -1, 'WITHSCORES')
+local reset = 0
+if newest[2] then
+    reset = math.max(tonumber(newest[2]) + window - now, 0)
+end
+return {1, math.max(capacity - count - cost, 0), reset, 0}
+"#;
+
+/// The generic cell rate algorithm. Stores only the theoretical arrival time of the next request.
+/// The capacity can be used in a burst, after which requests are let through evenly
+/// over the window.
+const GCRA: &str = r#"
+redis.replicate_commands()
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + tonumber(time[2]) / 1000
+local window = tonumber(ARGV[2])
+local interval = window / tonumber(ARGV[1])
+local cost = tonumber(ARGV[4])
+local tat = math.max(tonumber(redis.call('GET', KEYS[1])) or now, now)
+local allow_at = tat + interval - window
+if allow_at > now then
+    return {0, 0, math.ceil(tat - now), math.ceil(allow_at - now)}
+end
+if cost > 0 then
+    tat = tat + interval * cost
+    redis.call('SET', KEYS[1], tat, 'PX', math.ceil(tat - now))
+end
+return {1, math.max(math.floor((now + window - tat) / interval), 0), math.ceil(tat - now), 0}
+"#;
+
+lazy_static! {
+    static ref FIXED_WINDOW_SCRIPT: Script = Script::new(FIXED_WINDOW);
+    static ref SLIDING_LOG_SCRIPT: Script = Script::new(SLIDING_LOG);
+    static ref GCRA_SCRIPT: Script = Script::new(GCRA);
+}
+
+fn script(algorithm: Algorithm) -> &'static Script {
+    match algorithm {
+        Algorithm::FixedWindow => &FIXED_WINDOW_SCRIPT,
+        Algorithm::SlidingLog => &SLIDING_LOG_SCRIPT,
+        Algorithm::Gcra => &GCRA_SCRIPT,
+    }
+}
+
+/// Buckets shared by every instance. Each check is a single atomic script.
+pub struct RedisStore {
+    redis_conn: RedisConn,
+}
+
+impl RedisStore {
+    pub fn new(redis_conn: RedisConn) -> Self {
+        RedisStore { redis_conn }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for RedisStore {
+    async fn check(&self, limiter: &Limiter) -> Result<RateLimitState> {
+        let mut con = self.redis_conn.conn_async().await?;
+
+        let (allowed, remaining, reset_ms, retry_ms): (bool, u64, u64, u64) =
+            script(limiter.algorithm)
+                .key(limiter.key())
+                .arg(limiter.full_count)
+                .arg(limiter.bucket_lifetime.as_millis() as u64)
+                .arg(Uuid::new_v4().to_string())
+                .arg(limiter.cost)
+                .invoke_async(&mut con)
+                .await?;
+
+        Ok(RateLimitState {
+            policy: limiter.policy.clone(),
+            identifier: limiter.identifier.to_string(),
+            limit: limiter.full_count,
+            remaining,
+            reset: Duration::from_millis(reset_ms),
+            retry_after: if allowed {
+                None
+            } else {
+                Some(Duration::from_millis(retry_ms))
+            },
+        })
+    }
+}
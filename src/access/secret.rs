@@ -0,0 +1,58 @@
+use anyhow::Result;
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, sign::Signer};
+use rand::{distributions::Alphanumeric, Rng};
+
+/// A random alphanumeric string, for tokens handed out to clients.
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// HMAC-SHA256 of the data with the server's key.
+pub fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+
+    signer.update(data)?;
+
+    Ok(signer.sign_to_vec()?)
+}
+
+/// HMAC-SHA256 of the secret with the server's key, hex encoded.
+/// Used to store tokens, so a database dump doesn't contain usable secrets.
+pub fn hash(key: &[u8], secret: &str) -> Result<String> {
+    Ok(hmac(key, secret.as_bytes())?
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Check the secret against a stored hash in constant time.
+pub fn verify(key: &[u8], secret: &str, stored_hash: &str) -> Result<bool> {
+    let hashed = hash(key, secret)?;
+
+    // The hash length is fixed, so comparing it leaks nothing
+    if hashed.len() != stored_hash.len() {
+        return Ok(false);
+    }
+
+    Ok(memcmp::eq(hashed.as_bytes(), stored_hash.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_verify() {
+        let secret = random_string(100);
+        let hashed = hash(b"key", &secret).unwrap();
+
+        assert!(verify(b"key", &secret, &hashed).unwrap());
+        assert!(!verify(b"other_key", &secret, &hashed).unwrap());
+        assert!(!verify(b"key", "wrong_secret", &hashed).unwrap());
+    }
+}
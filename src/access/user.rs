@@ -1,55 +1,158 @@
-use crate::{access::JWT, models::user::User, res::Res};
+use crate::{
+    access::jwt::{JwtClaims, JWT},
+    gql::{E, R},
+    models::{role::Role, user::User},
+    res::Res,
+};
 use actix_web::{dev::Payload, FromRequest, HttpRequest};
 use anyhow::Result;
-use futures::future::{err, ok, Ready};
+use async_graphql::Context;
+use futures::{
+    future::{err, ok, LocalBoxFuture},
+    lock::Mutex,
+};
+use sqlx::PgPool;
+use std::sync::Arc;
 
-/// An user decoded from a valid JWT.
-/// In case there is no `Authorization` header, the user in `None`.
-/// When the header exists, it's value has to be valid.
+/// Claims of the user decoded from a valid JWT.
+/// In case there is no `Authorization` header, the claims are `None`.
+/// When the header exists, it's value has to be valid and intended for the route's audience.
 #[derive(Clone)]
-pub struct UserFromJWT(pub Option<User>);
+pub struct UserFromJWT(pub Option<JwtClaims>);
+
+/// The audience a route accepts tokens for, set with `.app_data(JwtAudience::new(..))`.
+/// Defaults to the audience of the server itself.
+#[derive(Clone, Default)]
+pub struct JwtAudience(Option<String>);
+
+impl JwtAudience {
+    pub fn new<S: Into<String>>(audience: S) -> Self {
+        JwtAudience(Some(audience.into()))
+    }
+}
+
+impl UserFromJWT {
+    /// Load the live user by the `sub` claim, if there is a token.
+    pub async fn user(&self, pool: &PgPool) -> Result<Option<User>> {
+        match &self.0 {
+            Some(claims) => Ok(Some(claims.load_user(pool).await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The claims of the caller in a GraphQL resolver.
+/// Errors if the request had no `Authorization` header.
+pub fn authenticated<'a>(ctx: &Context<'a>) -> R<&'a JwtClaims> {
+    ctx.data_opt::<JwtClaims>().ok_or(E::Unauthorized)
+}
+
+/// The live user of the request's JWT and their permissions,
+/// so guards and resolvers load them only once.
+/// Inserted into GraphQL data for every request.
+#[derive(Clone, Default)]
+pub struct UserCache(Arc<Mutex<Cached>>);
+
+#[derive(Default)]
+struct Cached {
+    user: Option<User>,
+    permissions: Option<Vec<String>>,
+}
+
+/// The current state of the authenticated user in a GraphQL resolver.
+/// Cached for the rest of the request if a `UserCache` exists.
+pub async fn current_user(ctx: &Context<'_>) -> R<User> {
+    let claims = authenticated(ctx)?;
+    let pool = ctx.data::<PgPool>()?;
+
+    let cache = match ctx.data_opt::<UserCache>() {
+        Some(cache) => cache,
+        None => return Ok(claims.load_user(pool).await?),
+    };
+
+    let mut cached = cache.0.lock().await;
+
+    if let Some(user) = &cached.user {
+        return Ok(user.clone());
+    }
+
+    let user = claims.load_user(pool).await?;
+    cached.user = Some(user.clone());
+
+    Ok(user)
+}
+
+/// Permissions granted to the authenticated user by their active roles.
+/// Cached like `current_user`.
+pub async fn current_permissions(ctx: &Context<'_>) -> R<Vec<String>> {
+    let claims = authenticated(ctx)?;
+    let pool = ctx.data::<PgPool>()?;
+
+    let cache = match ctx.data_opt::<UserCache>() {
+        Some(cache) => cache,
+        None => return Role::effective_permissions(pool, claims.sub).await,
+    };
+
+    let mut cached = cache.0.lock().await;
+
+    if let Some(permissions) = &cached.permissions {
+        return Ok(permissions.clone());
+    }
+
+    let permissions = Role::effective_permissions(pool, claims.sub).await?;
+    cached.permissions = Some(permissions.clone());
+
+    Ok(permissions)
+}
 
 impl FromRequest for UserFromJWT {
     type Error = Res<()>;
-    type Future = Ready<Result<Self, Self::Error>>;
-    type Config = ();
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = JwtAudience;
 
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
         // Get header value if it exists
         // If there is no header, continue wihtout user
         let header_val = match req.headers().get("Authorization") {
             Some(header) => header,
-            None => return ok(UserFromJWT(None)),
+            None => return Box::pin(ok(UserFromJWT(None))),
         };
 
         // Header value to &str
         let header_str = match header_val.to_str() {
             Ok(value) => value,
             Err(error) => {
-                return err(Res::<()>::error(format!(
+                return Box::pin(err(Res::<()>::error(format!(
                     "Failed to parse Authorization -header: {}.",
                     error
-                )))
+                ))))
             }
         };
 
         // Get the JWT provider
         let jwt = match req.app_data::<JWT>() {
-            Some(jwt) => jwt,
+            Some(jwt) => jwt.clone(),
             None => {
                 error!("JWT doesn't exist in actix state");
 
-                return err(Res::<()>::error("JWT doesn't exist in state."));
+                return Box::pin(err(Res::<()>::error("JWT doesn't exist in state.")));
             }
         };
 
-        // Decode claims
-        let claims = match jwt.decode(header_str) {
-            Ok(claims) => claims,
-            Err(error) => return err(Res::<()>::error(format!("JWT is invalid: {}.", error))),
+        // The route's audience, or the server's own
+        let audience = match req.app_data::<JwtAudience>().and_then(|aud| aud.0.as_ref()) {
+            Some(audience) => audience.clone(),
+            None => jwt.audience().to_string(),
         };
 
-        // Get the user data
-        return ok(UserFromJWT(Some(claims.claims.user)));
+        let token = header_str.to_string();
+
+        // Decoding checks the denylist, which might need Redis
+        Box::pin(async move {
+            match jwt.decode(&token, &audience).await {
+                Ok(claims) => Ok(UserFromJWT(Some(claims.claims))),
+                Err(error) => Err(Res::<()>::error(format!("JWT is invalid: {}.", error))),
+            }
+        })
     }
 }
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use openssl::{
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{Id, PKey, Public},
+    rsa::Rsa,
+    sign::Verifier,
+};
+use serde::Deserialize;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+/// COSE algorithm identifiers.
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+pub const RS256: i64 = -257;
+
+/// COSE key parameters.
+const KTY: i128 = 1;
+const ALG: i128 = 3;
+const CRV_OR_N: i128 = -1;
+const X_OR_E: i128 = -2;
+const Y: i128 = -3;
+
+/// Key types and curves.
+const KTY_OKP: i128 = 1;
+const KTY_EC2: i128 = 2;
+const KTY_RSA: i128 = 3;
+const CRV_P256: i128 = 1;
+const CRV_ED25519: i128 = 6;
+
+/// A credential public key and it's COSE algorithm.
+/// Stored as a DER encoded SubjectPublicKeyInfo.
+#[derive(Debug, Clone)]
+pub struct CoseKey {
+    pub algorithm: i64,
+    pub der: Vec<u8>,
+}
+
+impl CoseKey {
+    /// A key loaded from the database.
+    pub fn new(algorithm: i64, der: Vec<u8>) -> Self {
+        CoseKey { algorithm, der }
+    }
+
+    /// Parse the COSE key at the start of the bytes, ignoring anything after it.
+    pub fn parse_prefix(bytes: &[u8]) -> Result<Self> {
+        let mut deserializer = serde_cbor::Deserializer::from_slice(bytes);
+
+        let map = match Value::deserialize(&mut deserializer)? {
+            Value::Map(map) => map,
+            _ => bail!("Invalid COSE key."),
+        };
+
+        let algorithm = integer(&map, ALG)? as i64;
+
+        let pkey = match (integer(&map, KTY)?, algorithm) {
+            (KTY_EC2, ES256) => {
+                if integer(&map, CRV_OR_N)? != CRV_P256 {
+                    bail!("Only P-256 elliptic curve keys are supported.");
+                }
+
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+                let x = BigNum::from_slice(bytes_of(&map, X_OR_E)?)?;
+                let y = BigNum::from_slice(bytes_of(&map, Y)?)?;
+
+                PKey::from_ec_key(EcKey::from_public_key_affine_coordinates(&group, &x, &y)?)?
+            }
+            (KTY_OKP, EDDSA) => {
+                if integer(&map, CRV_OR_N)? != CRV_ED25519 {
+                    bail!("Only Ed25519 keys are supported.");
+                }
+
+                PKey::public_key_from_raw_bytes(bytes_of(&map, X_OR_E)?, Id::ED25519)?
+            }
+            (KTY_RSA, RS256) => PKey::from_rsa(Rsa::from_public_components(
+                BigNum::from_slice(bytes_of(&map, CRV_OR_N)?)?,
+                BigNum::from_slice(bytes_of(&map, X_OR_E)?)?,
+            )?)?,
+            (kty, alg) => bail!("Unsupported COSE key type {} with algorithm {}.", kty, alg),
+        };
+
+        Ok(CoseKey {
+            algorithm,
+            der: pkey.public_key_to_der()?,
+        })
+    }
+
+    /// Check the signature of the data.
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> Result<bool> {
+        let pkey: PKey<Public> = PKey::public_key_from_der(&self.der)?;
+
+        Ok(match self.algorithm {
+            // ECDSA signatures are DER encoded
+            ES256 | RS256 => {
+                let mut verifier = Verifier::new(MessageDigest::sha256(), &pkey)?;
+                verifier.update(data)?;
+                verifier.verify(signature)?
+            }
+            EDDSA => Verifier::new_without_digest(&pkey)?.verify_oneshot(signature, data)?,
+            alg => bail!("Unsupported COSE algorithm {}.", alg),
+        })
+    }
+}
+
+fn integer(map: &BTreeMap<Value, Value>, key: i128) -> Result<i128> {
+    match map.get(&Value::Integer(key)) {
+        Some(Value::Integer(value)) => Ok(*value),
+        _ => bail!("COSE key parameter {} is missing.", key),
+    }
+}
+
+fn bytes_of(map: &BTreeMap<Value, Value>, key: i128) -> Result<&[u8]> {
+    match map.get(&Value::Integer(key)) {
+        Some(Value::Bytes(value)) => Ok(value),
+        _ => bail!("COSE key parameter {} is missing.", key),
+    }
+}
@@ -0,0 +1,278 @@
+//! WebAuthn (passkey) ceremony verification.
+//! Only the `none` attestation is requested, so the attestation statement is not checked.
+//! Supported public keys are ES256, EdDSA and RS256.
+
+pub mod cose;
+#[cfg(test)]
+pub mod soft;
+
+pub use cose::CoseKey;
+
+use crate::config::WebAuthnConfig;
+use anyhow::Result;
+use openssl::{
+    hash::{hash, MessageDigest},
+    memcmp,
+};
+use serde::Deserialize;
+use serde_cbor::Value;
+use std::convert::TryInto;
+
+/// User present.
+const FLAG_UP: u8 = 0x01;
+/// User verified, with a PIN or biometrics.
+const FLAG_UV: u8 = 0x04;
+/// Attested credential data included.
+const FLAG_AT: u8 = 0x40;
+
+/// Relying party identifier hash, flags and signature counter.
+const AUTH_DATA_LEN: usize = 37;
+
+/// What the client signed, as JSON.
+#[derive(Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub ceremony: String,
+    /// Base64url encoded.
+    pub challenge: String,
+    pub origin: String,
+}
+
+impl ClientData {
+    pub fn parse(client_data_json: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(client_data_json)?)
+    }
+}
+
+/// A credential created by an authenticator.
+pub struct NewCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: CoseKey,
+    pub sign_count: u32,
+}
+
+/// Parsed authenticator data.
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    /// Attested credential data and extensions.
+    rest: &'a [u8],
+}
+
+impl<'a> AuthenticatorData<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.len() < AUTH_DATA_LEN {
+            bail!("Authenticator data is too short.");
+        }
+
+        Ok(AuthenticatorData {
+            rp_id_hash: &data[..32],
+            flags: data[32],
+            sign_count: u32::from_be_bytes(data[33..37].try_into()?),
+            rest: &data[AUTH_DATA_LEN..],
+        })
+    }
+
+    /// Check the relying party and the user presence and verification flags.
+    fn check(&self, conf: &WebAuthnConfig, require_uv: bool) -> Result<()> {
+        let expected = hash(MessageDigest::sha256(), conf.rp_id.as_bytes())?;
+
+        if !memcmp::eq(self.rp_id_hash, &expected) {
+            bail!("The credential is for another relying party.");
+        }
+
+        if self.flags & FLAG_UP == 0 {
+            bail!("User presence is required.");
+        }
+
+        if require_uv && self.flags & FLAG_UV == 0 {
+            bail!("User verification is required.");
+        }
+
+        Ok(())
+    }
+}
+
+/// Check the client data of a ceremony against the expected challenge and origin.
+pub fn check_client_data(
+    conf: &WebAuthnConfig,
+    client_data: &ClientData,
+    ceremony: &str,
+    challenge: &str,
+) -> Result<()> {
+    if client_data.ceremony != ceremony {
+        bail!("Wrong WebAuthn ceremony {}.", client_data.ceremony);
+    }
+
+    // Compared in constant time, which requires equal lengths
+    if client_data.challenge.len() != challenge.len()
+        || !memcmp::eq(client_data.challenge.as_bytes(), challenge.as_bytes())
+    {
+        bail!("Wrong WebAuthn challenge.");
+    }
+
+    if client_data.origin != conf.origin {
+        bail!("Wrong WebAuthn origin {}.", client_data.origin);
+    }
+
+    Ok(())
+}
+
+/// Verify the response of `navigator.credentials.create()`.
+/// The client data must have been checked with `check_client_data`.
+pub fn verify_registration(
+    conf: &WebAuthnConfig,
+    attestation_object: &[u8],
+    require_uv: bool,
+) -> Result<NewCredential> {
+    let attestation: Value = serde_cbor::from_slice(attestation_object)?;
+
+    let auth_data = match &attestation {
+        Value::Map(map) => match map.get(&Value::Text("authData".into())) {
+            Some(Value::Bytes(bytes)) => bytes,
+            _ => bail!("Attestation object has no authenticator data."),
+        },
+        _ => bail!("Invalid attestation object."),
+    };
+
+    let data = AuthenticatorData::parse(auth_data)?;
+    data.check(conf, require_uv)?;
+
+    if data.flags & FLAG_AT == 0 {
+        bail!("No attested credential data.");
+    }
+
+    // AAGUID (16) and credential id length (2)
+    if data.rest.len() < 18 {
+        bail!("Attested credential data is too short.");
+    }
+
+    let id_len = u16::from_be_bytes(data.rest[16..18].try_into()?) as usize;
+    let key_start = 18 + id_len;
+
+    if data.rest.len() < key_start {
+        bail!("Attested credential data is too short.");
+    }
+
+    Ok(NewCredential {
+        credential_id: data.rest[18..key_start].to_vec(),
+        // Extensions might follow the key, parsing stops after the first item
+        public_key: CoseKey::parse_prefix(&data.rest[key_start..])?,
+        sign_count: data.sign_count,
+    })
+}
+
+/// Verify the response of `navigator.credentials.get()`.
+/// The client data must have been checked with `check_client_data`.
+/// Returns the new signature counter.
+pub fn verify_assertion(
+    conf: &WebAuthnConfig,
+    public_key: &CoseKey,
+    stored_sign_count: u32,
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+    require_uv: bool,
+) -> Result<u32> {
+    let data = AuthenticatorData::parse(authenticator_data)?;
+    data.check(conf, require_uv)?;
+
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&hash(MessageDigest::sha256(), client_data_json)?);
+
+    if !public_key.verify(&signed, signature)? {
+        bail!("Invalid WebAuthn signature.");
+    }
+
+    // Authenticators without a counter always report zero.
+    // Otherwise a counter that didn't grow means the credential was cloned.
+    if (data.sign_count != 0 || stored_sign_count != 0) && data.sign_count <= stored_sign_count {
+        bail!("The WebAuthn signature counter did not increase.");
+    }
+
+    Ok(data.sign_count)
+}
+
+/// Unpadded base64url, used for binary values in WebAuthn JSON.
+pub fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn from_base64url(value: &str) -> Result<Vec<u8>> {
+    Ok(base64::decode_config(value, base64::URL_SAFE_NO_PAD)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{soft::SoftAuthenticator, *};
+
+    fn test_conf() -> WebAuthnConfig {
+        WebAuthnConfig {
+            rp_id: "localhost".into(),
+            rp_name: "dia".into(),
+            origin: "http://localhost:8080".into(),
+            challenge_timeout: 300,
+        }
+    }
+
+    #[test]
+    fn register_and_assert() {
+        let conf = test_conf();
+        let mut authenticator = SoftAuthenticator::new(&conf.rp_id, &conf.origin);
+
+        let (client_data, attestation) = authenticator.register("challenge_1");
+        check_client_data(
+            &conf,
+            &ClientData::parse(&client_data).unwrap(),
+            "webauthn.create",
+            "challenge_1",
+        )
+        .unwrap();
+        let credential = verify_registration(&conf, &attestation, true).unwrap();
+
+        assert_eq!(credential.credential_id, authenticator.credential_id());
+
+        let (client_data, auth_data, signature) = authenticator.assert("challenge_2");
+        let count = verify_assertion(
+            &conf,
+            &credential.public_key,
+            credential.sign_count,
+            &client_data,
+            &auth_data,
+            &signature,
+            true,
+        )
+        .unwrap();
+
+        assert!(count > credential.sign_count);
+
+        // Replaying the same assertion fails the counter check
+        assert!(verify_assertion(
+            &conf,
+            &credential.public_key,
+            count,
+            &client_data,
+            &auth_data,
+            &signature,
+            true,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn wrong_origin() {
+        let conf = test_conf();
+        let mut authenticator = SoftAuthenticator::new(&conf.rp_id, "https://phishing.example");
+
+        let (client_data, _) = authenticator.register("challenge");
+
+        assert!(check_client_data(
+            &conf,
+            &ClientData::parse(&client_data).unwrap(),
+            "webauthn.create",
+            "challenge",
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! A software authenticator for tests, so no hardware is needed.
+
+use super::cose;
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey},
+    hash::{hash, MessageDigest},
+    nid::Nid,
+    pkey::{PKey, Private},
+    rand::rand_bytes,
+    sign::Signer,
+};
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+/// An ES256 credential for one relying party. Reports user presence and verification.
+pub struct SoftAuthenticator {
+    rp_id: String,
+    origin: String,
+    key: PKey<Private>,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+}
+
+impl SoftAuthenticator {
+    pub fn new(rp_id: &str, origin: &str) -> Self {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let mut credential_id = vec![0u8; 16];
+        rand_bytes(&mut credential_id).unwrap();
+
+        SoftAuthenticator {
+            rp_id: rp_id.into(),
+            origin: origin.into(),
+            key: PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap(),
+            credential_id,
+            sign_count: 0,
+        }
+    }
+
+    pub fn credential_id(&self) -> Vec<u8> {
+        self.credential_id.clone()
+    }
+
+    /// Client data JSON and attestation object for the challenge.
+    pub fn register(&mut self, challenge: &str) -> (Vec<u8>, Vec<u8>) {
+        let mut auth_data = self.auth_data(0x01 | 0x04 | 0x40);
+
+        // AAGUID, credential id and the public key
+        auth_data.extend_from_slice(&[0u8; 16]);
+        auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.credential_id);
+        auth_data.extend_from_slice(&self.cose_key());
+
+        let mut attestation = BTreeMap::new();
+        attestation.insert(Value::Text("fmt".into()), Value::Text("none".into()));
+        attestation.insert(Value::Text("attStmt".into()), Value::Map(BTreeMap::new()));
+        attestation.insert(Value::Text("authData".into()), Value::Bytes(auth_data));
+
+        (
+            self.client_data("webauthn.create", challenge),
+            serde_cbor::to_vec(&Value::Map(attestation)).unwrap(),
+        )
+    }
+
+    /// Client data JSON, authenticator data and signature for the challenge.
+    pub fn assert(&mut self, challenge: &str) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let client_data = self.client_data("webauthn.get", challenge);
+        let auth_data = self.auth_data(0x01 | 0x04);
+
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+        signer.update(&auth_data).unwrap();
+        signer
+            .update(&hash(MessageDigest::sha256(), &client_data).unwrap())
+            .unwrap();
+
+        (client_data, auth_data, signer.sign_to_vec().unwrap())
+    }
+
+    fn client_data(&self, ceremony: &str, challenge: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "type": ceremony,
+            "challenge": challenge,
+            "origin": self.origin,
+        }))
+        .unwrap()
+    }
+
+    /// Relying party hash, flags and an incremented counter.
+    fn auth_data(&mut self, flags: u8) -> Vec<u8> {
+        self.sign_count += 1;
+
+        let mut data = hash(MessageDigest::sha256(), self.rp_id.as_bytes())
+            .unwrap()
+            .to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+
+        data
+    }
+
+    fn cose_key(&self) -> Vec<u8> {
+        let ec = self.key.ec_key().unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let mut x = BigNum::new().unwrap();
+        let mut y = BigNum::new().unwrap();
+        ec.public_key()
+            .affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut ctx)
+            .unwrap();
+
+        let mut key = BTreeMap::new();
+        key.insert(Value::Integer(1), Value::Integer(2));
+        key.insert(Value::Integer(3), Value::Integer(cose::ES256 as i128));
+        key.insert(Value::Integer(-1), Value::Integer(1));
+        key.insert(Value::Integer(-2), Value::Bytes(padded(x.to_vec())));
+        key.insert(Value::Integer(-3), Value::Bytes(padded(y.to_vec())));
+
+        serde_cbor::to_vec(&Value::Map(key)).unwrap()
+    }
+}
+
+/// Left pad a P-256 coordinate to 32 bytes.
+fn padded(bytes: Vec<u8>) -> Vec<u8> {
+    let mut padded = vec![0u8; 32 - bytes.len()];
+    padded.extend(bytes);
+
+    padded
+}
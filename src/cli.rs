@@ -0,0 +1,52 @@
+//! Commands run instead of the server, like `dia import-users users.csv --dry-run`.
+
+use crate::models::user::{import_users, ImportFormat};
+use sqlx::PgPool;
+use std::fs::read_to_string;
+
+const USAGE: &str = "Usage:
+    dia                                 Run the server
+    dia import-users <FILE> [--dry-run] Import users from a .csv or .jsonl file";
+
+/// Run the command in the arguments, without the program name. Exits on errors.
+pub async fn run(pool: &PgPool, args: &[String]) {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["import-users", path] => import(pool, path, false).await,
+        ["import-users", path, "--dry-run"] => import(pool, path, true).await,
+        _ => exit(USAGE),
+    }
+}
+
+async fn import(pool: &PgPool, path: &str, dry_run: bool) {
+    let format = ImportFormat::from_path(path)
+        .unwrap_or_else(|| exit("The file should have a .csv or .jsonl extension."));
+
+    let data = read_to_string(path)
+        .unwrap_or_else(|error| exit(&format!("Failed to read {}: {}", path, error)));
+
+    let report = import_users(pool, format, &data, dry_run).await;
+
+    for failure in &report.failures {
+        println!(
+            "Line {} ({}): {}",
+            failure.line,
+            failure.username.as_deref().unwrap_or("-"),
+            failure.message
+        );
+    }
+
+    println!(
+        "{} {} users, {} failed.",
+        if dry_run { "Would import" } else { "Imported" },
+        report.imported,
+        report.failures.len()
+    );
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+
+    std::process::exit(1)
+}
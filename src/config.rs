@@ -1,7 +1,12 @@
+use crate::{
+    access::{jwt::ProfileClaim, Algorithm as RateLimitAlgorithm, Cidr},
+    mail::MailTemplate,
+};
 use actix_web::{dev::Payload, FromRequest, HttpRequest};
 use futures::future::{err, ok, Ready};
+use jsonwebtoken::Algorithm;
 use serde::Deserialize;
-use std::fs::read_to_string;
+use std::{collections::HashMap, fs::read_to_string};
 use toml::from_str;
 
 /// App configuration. All fields must exists in the file.
@@ -9,8 +14,19 @@ use toml::from_str;
 pub struct Config {
     pub bind_to: String,
     pub allow_registerations: bool,
+    /// Block `createRefreshToken` for users without a verified email address.
+    pub require_verified_email: bool,
     pub pg: PG,
     pub rd: RD,
+    pub jwt: JWTConfig,
+    pub tokens: Tokens,
+    pub mail: MailConfig,
+    pub webauthn: WebAuthnConfig,
+    pub lockout: LockoutConfig,
+    pub password: PasswordConfig,
+    pub password_policy: PasswordPolicyConfig,
+    pub rate_limits: RateLimitsConfig,
+    pub graphql: GraphQLConfig,
 }
 
 /// PostgreSQL config options.
@@ -26,6 +42,196 @@ pub struct RD {
     pub url: String,
 }
 
+/// JWT signing key management.
+#[derive(Deserialize, Clone)]
+pub struct JWTConfig {
+    /// Directory of PEM encoded private keys. Can be shared by multiple instances.
+    pub key_dir: String,
+    /// Algorithm of new signing keys: `RS256`, `ES256` or `EdDSA`.
+    /// Changing it rotates the key, existing tokens stay valid during the grace period.
+    pub algorithm: Algorithm,
+    /// Seconds until a new signing key is generated.
+    pub rotation_interval: u64,
+    /// Seconds a rotated key is still accepted.
+    /// Should be longer than the longest allowed JWT lifetime.
+    pub grace_period: u64,
+    /// Set as `iss` in signed tokens.
+    pub issuer: String,
+    /// The `aud` this server's own routes accept.
+    pub audience: String,
+    /// Other applications tokens can be signed for with `signJwt`.
+    pub audiences: Vec<String>,
+    /// User fields included in signed tokens, for example `["username", "groups"]`.
+    pub profile_claims: Vec<ProfileClaim>,
+}
+
+/// Secrets handed out to clients, like refresh tokens.
+#[derive(Deserialize, Clone)]
+pub struct Tokens {
+    /// Key for hashing stored tokens. Should be a long random string.
+    /// Changing it invalidates every stored token.
+    pub hash_key: String,
+    /// Seconds a password reset token is valid for.
+    pub password_reset_lifetime: i64,
+    /// Seconds an email verification token is valid for.
+    pub email_verification_lifetime: i64,
+    /// Seconds a magic link is valid for. Should be short, minutes rather than hours.
+    pub magic_link_lifetime: i64,
+}
+
+/// Outbound mail.
+#[derive(Deserialize, Clone)]
+pub struct MailConfig {
+    /// Sender of every message, for example `dia <noreply@example.com>`.
+    pub from: String,
+    pub backend: MailBackend,
+    /// Directory the spool backend writes messages to. Printed to stdout if empty.
+    pub spool_dir: String,
+    /// How many times a failed delivery is retried, with an exponential back-off.
+    pub retries: u32,
+    pub smtp: SmtpConfig,
+    pub templates: MailTemplates,
+}
+
+/// Where messages are delivered.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MailBackend {
+    Smtp,
+    /// Files or stdout, for development and tests.
+    Spool,
+}
+
+/// SMTP server of the `smtp` backend.
+#[derive(Deserialize, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    /// Use TLS. Disable only for local relays and test sinks.
+    pub tls: bool,
+    /// No authentication if empty.
+    pub username: String,
+    pub password: String,
+}
+
+/// Messages sent to users. Variables are written as `{{name}}`.
+#[derive(Deserialize, Clone)]
+pub struct MailTemplates {
+    /// Variables: `username` and `token`.
+    pub password_reset: MailTemplate,
+    /// Variables: `username`, `email` and `token`.
+    pub email_verification: MailTemplate,
+    /// Variables: `username` and `token`. The link itself is written in the template.
+    pub magic_link: MailTemplate,
+}
+
+/// The relying party of passkeys. Changing `rp_id` makes existing passkeys unusable.
+#[derive(Deserialize, Clone)]
+pub struct WebAuthnConfig {
+    /// Domain the passkeys are scoped to, for example `example.com`.
+    pub rp_id: String,
+    /// Shown to users by authenticators.
+    pub rp_name: String,
+    /// Origin of the frontend, for example `https://example.com`.
+    pub origin: String,
+    /// Seconds a registration or login challenge is valid for.
+    pub challenge_timeout: u64,
+}
+
+/// Failed login attempts per username, from any address.
+#[derive(Deserialize, Clone)]
+pub struct LockoutConfig {
+    /// Failures in a row before attempts are delayed.
+    pub free_attempts: u32,
+    /// Failures in a row before the username is locked.
+    pub max_attempts: u32,
+    /// Seconds the username stays locked, and failures are remembered for.
+    pub lockout_seconds: i64,
+}
+
+/// Password hashing. Changing these is safe, hashes are upgraded when users log in.
+#[derive(Deserialize, Clone)]
+pub struct PasswordConfig {
+    /// Argon2id iterations.
+    pub ops_limit: usize,
+    /// Argon2id memory in bytes.
+    pub mem_limit: usize,
+    /// A secret mixed into every new hash, not stored in the database. Empty for none.
+    /// Can't be changed or removed later, peppered hashes only verify with it.
+    pub pepper: String,
+}
+
+/// Requirements for new passwords. Existing passwords are not checked again.
+#[derive(Deserialize, Clone)]
+pub struct PasswordPolicyConfig {
+    /// Length in characters.
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Estimated entropy in bits, see `password_policy::entropy`.
+    pub min_entropy: f64,
+    /// Directory of breached password hashes by SHA-1 prefix, in the layout of the
+    /// Pwned Passwords range API. Not checked if empty.
+    pub breached_list: String,
+}
+
+/// Rate limits by policy name. The policies in `rate_limiter::policy` are required.
+#[derive(Deserialize, Clone)]
+pub struct RateLimitsConfig {
+    /// Where requests are counted. `memory` only works with a single instance.
+    pub store: RateLimitStoreKind,
+    pub on_failure: FailurePolicy,
+    /// Policy applied to every GraphQL request, per address. None if empty.
+    pub global: String,
+    /// Policy charged with the complexity of every GraphQL operation, per user or per address
+    /// when not logged in. None if empty.
+    pub cost: String,
+    /// Addresses that are never limited, for example `["10.0.0.0/8", "::1"]`.
+    pub allowlist: Vec<Cidr>,
+    pub policies: HashMap<String, RateLimitPolicy>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitStoreKind {
+    Redis,
+    Memory,
+}
+
+/// What to do with requests when the store fails, like when Redis can't be reached.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Let every request through.
+    Open,
+    /// Fail every request with the error.
+    Closed,
+    /// Count requests in the memory of the instance until the store works again.
+    Local,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RateLimitPolicy {
+    /// `fixed-window`, `sliding-log` or `gcra`.
+    pub algorithm: RateLimitAlgorithm,
+    /// Seconds.
+    pub window: u64,
+    /// Requests allowed in the window.
+    pub capacity: u64,
+    /// Capacities of single addresses or user IDs, instead of `capacity`.
+    pub overrides: HashMap<String, u64>,
+}
+
+/// Limits of a single GraphQL operation.
+#[derive(Deserialize, Clone)]
+pub struct GraphQLConfig {
+    /// Nesting of the selections.
+    pub max_depth: usize,
+    /// Sum of the field costs, 1 for most fields.
+    pub max_complexity: usize,
+    /// Aliased fields, fragments included.
+    pub max_aliases: usize,
+}
+
 impl Config {
     /// Creates a config from the specified file.
     /// Might panic with fs or parsing errors.
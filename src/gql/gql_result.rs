@@ -1,9 +1,9 @@
 use async_graphql::Error as GraphQLError;
 use redis::RedisError;
 use sqlx::Error as SqlxError;
+use std::str::Utf8Error;
 use thiserror::Error;
 use validator::ValidationErrors;
-use std::str::Utf8Error;
 
 /// The type GraphQL handler functions returns.
 /// The `?`-syntax converts any supported error into `E`.
@@ -27,6 +27,12 @@ pub enum E {
     Redis(RedisError),
     #[error("Invalid input.")]
     InvalidInput,
+    #[error("Wrong username or password.")]
+    InvalidCredentials,
+    #[error("Authentication required.")]
+    Unauthorized,
+    #[error("Forbidden, {}.", .0)]
+    Forbidden(String),
     #[error("Not found.")]
     NotFound,
     #[error("{} not found.", .0)]
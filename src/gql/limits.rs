@@ -0,0 +1,323 @@
+//! Limits of a single operation, configured in `[graphql]`. Checked by an extension,
+//! so they apply to POST and websocket requests alike.
+
+use crate::{
+    access::{Identifier, RateLimited, RateLimiter},
+    config::GraphQLConfig,
+};
+use async_graphql::{
+    extensions::{Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextValidation},
+    parser::types::{ExecutableDocument, Selection, SelectionSet},
+    Name, ServerError, ServerResult, ValidationResult, Variables,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Complexity of the request's operation, set when it's validated.
+/// Shared with the route through the request data.
+#[derive(Clone, Default)]
+pub struct QueryCost(Arc<AtomicUsize>);
+
+impl QueryCost {
+    /// 0 if the operation wasn't validated.
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The client's cost budget, charged with the complexity of every operation when it's
+/// validated. For websocket connections, POST requests are charged by the route after running.
+pub struct CostBudget {
+    pub rate_limiter: RateLimiter,
+    pub client: Identifier,
+}
+
+/// Rejects operations over the configured depth, complexity or aliases.
+/// The complexity of the others is recorded in the request's `QueryCost`, if it has one,
+/// and charged from its `CostBudget`, if it has one.
+pub struct OperationLimits(pub GraphQLConfig);
+
+impl ExtensionFactory for OperationLimits {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(OperationLimitsExtension(self.0.clone()))
+    }
+}
+
+struct OperationLimitsExtension(GraphQLConfig);
+
+#[async_trait::async_trait]
+impl Extension for OperationLimitsExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+
+        if count_aliases(&document) > self.0.max_aliases {
+            return Err(ServerError::new("Query has too many aliases."));
+        }
+
+        Ok(document)
+    }
+
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let budget = ctx.data_opt::<CostBudget>();
+
+        if let Some(budget) = budget {
+            budget
+                .rate_limiter
+                .check_cost(budget.client)
+                .await
+                .map_err(|error| vec![RateLimited::gql_error(error).into_server_error()])?;
+        }
+
+        let result = next.run(ctx).await?;
+
+        if result.depth > self.0.max_depth {
+            return Err(vec![ServerError::new("Query is nested too deep.")]);
+        }
+
+        if result.complexity > self.0.max_complexity {
+            return Err(vec![ServerError::new("Query is too complex.")]);
+        }
+
+        if let Some(cost) = ctx.data_opt::<QueryCost>() {
+            cost.0.store(result.complexity, Ordering::Relaxed);
+        }
+
+        if let Some(budget) = budget {
+            if let Err(error) = budget
+                .rate_limiter
+                .charge_cost(budget.client, result.complexity as u64)
+                .await
+            {
+                error!("Failed to charge the query cost: {}", error);
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Most aliases in any operation of the document, with the aliases of fragments counted
+/// every time they're spread.
+fn count_aliases(document: &ExecutableDocument) -> usize {
+    let mut counter = AliasCounter {
+        document,
+        fragments: HashMap::new(),
+        visiting: Vec::new(),
+    };
+
+    document
+        .operations
+        .iter()
+        .map(|(_, operation)| counter.selection_set(&operation.node.selection_set.node))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Counts every fragment once, so spreading fragments in fragments can't make it slow.
+struct AliasCounter<'a> {
+    document: &'a ExecutableDocument,
+    fragments: HashMap<&'a Name, usize>,
+    /// Fragments being counted, cycles are rejected later by the validation.
+    visiting: Vec<&'a Name>,
+}
+
+impl<'a> AliasCounter<'a> {
+    fn selection_set(&mut self, set: &'a SelectionSet) -> usize {
+        let mut count = 0usize;
+
+        for selection in &set.items {
+            let aliases = match &selection.node {
+                Selection::Field(field) => {
+                    field.node.alias.is_some() as usize
+                        + self.selection_set(&field.node.selection_set.node)
+                }
+                Selection::FragmentSpread(spread) => self.fragment(&spread.node.fragment_name.node),
+                Selection::InlineFragment(fragment) => {
+                    self.selection_set(&fragment.node.selection_set.node)
+                }
+            };
+
+            count = count.saturating_add(aliases);
+        }
+
+        count
+    }
+
+    fn fragment(&mut self, name: &'a Name) -> usize {
+        if let Some(count) = self.fragments.get(name) {
+            return *count;
+        }
+
+        let document = self.document;
+
+        let fragment = match document.fragments.get(name) {
+            Some(fragment) if !self.visiting.contains(&name) => fragment,
+            _ => return 0,
+        };
+
+        self.visiting.push(name);
+        let count = self.selection_set(&fragment.node.selection_set.node);
+        self.visiting.pop();
+
+        self.fragments.insert(name, count);
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{FailurePolicy, RateLimitStoreKind},
+        db::RedisConn,
+        gql::build_schema,
+        Config, CONF_FILE,
+    };
+    use async_graphql::{parser::parse_query, Request};
+    use uuid::Uuid;
+
+    fn aliases(query: &str) -> usize {
+        count_aliases(&parse_query(query).unwrap())
+    }
+
+    /// The schema rejects operations over the limits, and records the cost of the others.
+    #[tokio::test]
+    async fn schema_limits() {
+        let schema = build_schema(&GraphQLConfig {
+            max_depth: 5,
+            max_complexity: 2,
+            max_aliases: 1,
+        });
+
+        let cost = QueryCost::default();
+        let res = schema
+            .execute(Request::new("{ a: add(a: 1, b: 1) add(a: 1, b: 2) }").data(cost.clone()))
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(cost.get(), 2);
+
+        let res = schema
+            .execute(Request::new(
+                "{ add(a: 1, b: 1) b: add(a: 1, b: 2) c: add(a: 1, b: 3) }",
+            ))
+            .await;
+        assert_eq!(res.errors[0].message, "Query has too many aliases.");
+
+        let res = schema
+            .execute(Request::new(
+                "{ a: add(a: 1, b: 1) add(a: 1, b: 2) one: add(a: 1, b: 3) }",
+            ))
+            .await;
+        assert!(res.is_err());
+    }
+
+    /// With a cost budget, every operation is charged, and rejected once it's used up.
+    #[tokio::test]
+    async fn cost_budget() {
+        let conf = Config::from_file(CONF_FILE);
+        let mut limits = conf.rate_limits.clone();
+        limits.store = RateLimitStoreKind::Memory;
+        limits.on_failure = FailurePolicy::Closed;
+        limits.cost = "test".into();
+        limits.policies.insert(
+            "test".into(),
+            toml::from_str(
+                r#"
+                algorithm = "fixed-window"
+                window = 60
+                capacity = 3
+                overrides = {}
+                "#,
+            )
+            .unwrap(),
+        );
+
+        let schema = build_schema(&conf.graphql);
+        let rate_limiter = RateLimiter::new(RedisConn::new(&conf), &limits);
+        let client = Identifier::User(Uuid::new_v4());
+        let request = |query: &str| {
+            Request::new(query).data(CostBudget {
+                rate_limiter: rate_limiter.clone(),
+                client,
+            })
+        };
+
+        let query = "{ a: add(a: 1, b: 1) add(a: 1, b: 2) }";
+        assert!(schema.execute(request(query)).await.is_ok());
+        assert!(schema.execute(request(query)).await.is_ok());
+
+        let res = schema.execute(request(query)).await;
+        assert!(res.is_err());
+        assert_eq!(
+            serde_json::to_value(&res.errors[0]).unwrap()["extensions"]["code"],
+            "RATE_LIMITED"
+        );
+    }
+
+    #[test]
+    fn count() {
+        assert_eq!(aliases("{ a: add(a: 1, b: 1) b: add(a: 1, b: 2) }"), 2);
+        assert_eq!(aliases("{ add(a: 1, b: 1) }"), 0);
+
+        // The largest operation counts
+        assert_eq!(
+            aliases(
+                "query A { a: add(a: 1, b: 1) } query B { a: add(a: 1, b: 1) b: add(a: 1, b: 1) }"
+            ),
+            2
+        );
+
+        // Fragments count every time they're spread, inline fragments once
+        assert_eq!(
+            aliases(
+                r#"{ ...F ...F ... on Query { c: add(a: 1, b: 1) } }
+                fragment F on Query { a: add(a: 1, b: 1) b: add(a: 1, b: 1) }"#
+            ),
+            5
+        );
+
+        // Cycles don't loop
+        assert_eq!(
+            aliases(
+                r#"{ ...F }
+                fragment F on Query { a: add(a: 1, b: 1) ...G }
+                fragment G on Query { ...F }"#
+            ),
+            1
+        );
+    }
+
+    /// Fragments spreading fragments twice would double the count on every level.
+    #[test]
+    fn nested_fragments() {
+        let mut query = String::from("{ ...F0 }");
+
+        for i in 0..64 {
+            query.push_str(&format!(
+                " fragment F{} on Query {{ ...F{} ...F{} }}",
+                i,
+                i + 1,
+                i + 1
+            ));
+        }
+        query.push_str(" fragment F64 on Query { a: add(a: 1, b: 1) }");
+
+        assert_eq!(aliases(&query), usize::MAX);
+    }
+}
@@ -1,15 +1,19 @@
 mod gql_result;
+mod limits;
 mod mutation;
 mod query;
 mod subscription;
 
 pub use gql_result::{E, R};
+pub use limits::{CostBudget, QueryCost};
 
+use crate::config::GraphQLConfig;
 use async_graphql::{extensions::*, *};
 
 pub type DiaSchema = Schema<query::Query, mutation::Mutation, subscription::Subscription>;
 
-pub fn build_schema() -> DiaSchema {
+/// The schema, rejecting operations over the configured depth, complexity and aliases.
+pub fn build_schema(conf: &GraphQLConfig) -> DiaSchema {
     Schema::build(
         query::Query::default(),
         mutation::Mutation::default(),
@@ -18,5 +22,6 @@ pub fn build_schema() -> DiaSchema {
     .data(())
     .extension(ApolloTracing)
     .extension(Analyzer)
+    .extension(limits::OperationLimits(conf.clone()))
     .finish()
 }
@@ -1,5 +1,15 @@
-use crate::models::{JwtMutation, RefreshTokenMutation, UserMutation};
+use crate::models::{
+    JwtMutation, PasskeyMutation, RefreshTokenMutation, RoleMutation, TwoFactorMutation,
+    UserMutation,
+};
 use async_graphql::*;
 
 #[derive(MergedObject, Default)]
-pub struct Mutation(UserMutation, RefreshTokenMutation, JwtMutation);
+pub struct Mutation(
+    UserMutation,
+    RefreshTokenMutation,
+    JwtMutation,
+    RoleMutation,
+    TwoFactorMutation,
+    PasskeyMutation,
+);
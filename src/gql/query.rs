@@ -1,5 +1,5 @@
-use crate::models::{Add, JwtQuery, Ping, RefreshTokenQuery, UserQuery};
+use crate::models::{Add, JwtQuery, Ping, RefreshTokenQuery, RoleQuery, UserQuery};
 use async_graphql::*;
 
 #[derive(MergedObject, Default)]
-pub struct Query(Ping, Add, UserQuery, JwtQuery, RefreshTokenQuery);
+pub struct Query(Ping, Add, UserQuery, JwtQuery, RefreshTokenQuery, RoleQuery);
@@ -1,33 +1,84 @@
+#[cfg(test)]
+lazy_static! {
+    /// Shared by every test query, so a JWT signed in one query is accepted in another.
+    pub static ref TEST_JWT: crate::access::JWT = {
+        use crate::{access::jwt::Denylist, db::RedisConn, Config, CONF_FILE};
+
+        crate::access::JWT::generate()
+            .unwrap()
+            .with_denylist(Denylist::new(RedisConn::new(&Config::from_file(CONF_FILE))))
+    };
+}
+
+/// An username with a random suffix, so tests running at the same time or again on the same
+/// database don't share users. The prefix can be up to 11 characters.
+#[cfg(test)]
+pub fn unique_name(prefix: &str) -> String {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    let suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect();
+
+    format!("{}_{}", prefix, suffix.to_lowercase())
+}
+
 /// Used to write GraphQL tests faster.
 /// Builds the schema and the request to mimic a normal HTTP -request based query.
+/// An optional JWT is decoded like the `Authorization` header: `gql_test!(query, Some(jwt))`.
+/// The config can be changed with `gql_test!(query, jwt, conf)`.
 #[allow(unused_macros)]
 macro_rules! gql_test {
     ($query:expr) => {{
+        gql_test!($query, None::<&str>)
+    }};
+    ($query:expr, $jwt:expr) => {{
+        gql_test!($query, $jwt, crate::Config::from_file(crate::CONF_FILE))
+    }};
+    ($query:expr, $jwt:expr, $conf:expr) => {{
         {
             use crate::{
-                access::{ClientIP, RateLimiter, JWT},
+                access::{ClientIP, Lockout, RateLimiter, UserCache},
                 db::{RedisConn, SqlxConn},
                 gql::build_schema,
-                Config, CONF_FILE,
+                macros::TEST_JWT,
+                mail::MailQueue,
             };
             use async_graphql::{Data, Request};
-            let conf = Config::from_file(CONF_FILE);
+            let conf = $conf;
+            let schema = build_schema(&conf.graphql);
 
             let mut req = Request::new($query);
             let mut data = Data::default();
 
             let rd = RedisConn::new(&conf);
 
-            data.insert(RateLimiter::new(rd.clone()));
+            data.insert(RateLimiter::new(rd.clone(), &conf.rate_limits));
+            data.insert(Lockout::new(rd.clone(), &conf));
             data.insert(rd.into_inner());
             data.insert(SqlxConn::new(&conf).await.into_inner());
             data.insert(ClientIP::new("127.0.0.1").unwrap().into_inner());
+            data.insert(MailQueue::from_config(&conf).unwrap());
             data.insert(conf);
-            data.insert(JWT::generate());
+            data.insert(TEST_JWT.clone());
+
+            if let Some(token) = $jwt {
+                let claims = TEST_JWT
+                    .decode(token, TEST_JWT.audience())
+                    .await
+                    .unwrap()
+                    .claims;
+
+                data.insert(claims);
+            }
+
+            data.insert(UserCache::default());
 
             req.data = data;
 
-            let res = build_schema().execute(req).await;
+            let res = schema.execute(req).await;
 
             println!("{:#?}", res);
 
@@ -39,8 +90,20 @@ macro_rules! gql_test {
 /// Creates an user for tests.
 /// If the username exists already, the tests this is used in fails.
 /// Username is `test_user` and password is `password_of_20_characters`.
+/// With `gql_test_user!(username)` the email is `<username>@email.com`.
 #[allow(unused_macros)]
 macro_rules! gql_test_user {
+    ($username:expr) => {{
+        gql_test!(format!(
+            r#"mutation {{
+                createUser(newUser: {{ username: "{0}", password: "password_of_20_characters", email: "{0}@email.com" }}) {{
+                  id
+                }}
+              }}
+              "#,
+            $username
+        ));
+    }};
     () => {{
         // The query might fail, since this is called in multiple tests.
         // This is just to make sure the user exists every time it is needed.
@@ -52,3 +115,45 @@ macro_rules! gql_test_user {
           "#);
     }}
 }
+
+/// Logs in with the password and signs a JWT, for tests that need the `Authorization` header.
+/// Uses a new refresh token every time.
+#[allow(unused_macros)]
+macro_rules! gql_test_login {
+    ($username:expr, $password:expr) => {{
+        let created = gql_test!(format!(
+            r#"mutation {{
+                createRefreshToken(newToken: {{ username: "{}", password: "{}" }}) {{
+                  tokenString
+                }}
+              }}
+              "#,
+            $username, $password
+        ));
+        let token_string = created.data.into_json().unwrap()["createRefreshToken"]["tokenString"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let signed = gql_test!(format!(
+            r#"mutation {{ signJwt(refreshTokenString: "{}") }}"#,
+            token_string
+        ));
+
+        signed.data.into_json().unwrap()["signJwt"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }};
+}
+
+/// Signs a JWT for a new user, for tests that need the `Authorization` header.
+#[allow(unused_macros)]
+macro_rules! gql_test_jwt {
+    () => {{
+        let username = crate::macros::unique_name("jwt_user");
+        gql_test_user!(&username);
+
+        gql_test_login!(&username, "password_of_20_characters")
+    }};
+}
@@ -0,0 +1,60 @@
+//! Outbound mail. Messages are handed to a `MailQueue`, which delivers them
+//! in the background with the configured `Mailer` and retries failures.
+
+mod queue;
+mod smtp;
+mod spool;
+mod template;
+
+pub use queue::MailQueue;
+pub use smtp::SmtpMailer;
+pub use spool::SpoolMailer;
+pub use template::MailTemplate;
+
+use anyhow::Result;
+use lettre::{
+    message::{header::ContentType, Mailbox, MultiPart, SinglePart},
+    Message,
+};
+
+/// A backend delivering messages, like an SMTP server.
+#[async_trait::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: Message) -> Result<()>;
+}
+
+/// A message to a single recipient, with a plain text body and an optional HTML alternative.
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+impl Mail {
+    /// Build the message to send from the sender's address.
+    pub fn message(&self, from: &Mailbox) -> Result<Message> {
+        let builder = Message::builder()
+            .from(from.clone())
+            .to(self.to.parse()?)
+            .subject(&self.subject);
+
+        Ok(match &self.html {
+            Some(html) => builder.multipart(
+                MultiPart::alternative()
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(ContentType::TEXT_PLAIN)
+                            .body(self.text.clone()),
+                    )
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(ContentType::TEXT_HTML)
+                            .body(html.clone()),
+                    ),
+            )?,
+            None => builder.body(self.text.clone())?,
+        })
+    }
+}
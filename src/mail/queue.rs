@@ -0,0 +1,165 @@
+use super::{Mail, Mailer, SmtpMailer, SpoolMailer};
+use crate::{config::MailBackend, Config, Res};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use anyhow::Result;
+use futures::future::{err, ok, Ready};
+use lettre::message::Mailbox;
+use std::{path::Path, sync::Arc, time::Duration};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+/// A message waiting for delivery.
+struct Queued {
+    mail: Mail,
+    attempt: u32,
+}
+
+/// Delivers mail in the background, so sending never blocks a request.
+/// Failed deliveries are retried with an exponential back-off.
+#[derive(Clone)]
+pub struct MailQueue {
+    sender: UnboundedSender<Queued>,
+}
+
+impl MailQueue {
+    /// Start a queue with the backend configured in `[mail]`.
+    pub fn from_config(conf: &Config) -> Result<Self> {
+        let mailer: Arc<dyn Mailer> = match conf.mail.backend {
+            MailBackend::Smtp => Arc::new(SmtpMailer::new(&conf.mail.smtp)?),
+            MailBackend::Spool => Arc::new(SpoolMailer::new(
+                Some(Path::new(&conf.mail.spool_dir)).filter(|dir| !dir.as_os_str().is_empty()),
+            )),
+        };
+
+        Ok(Self::start(
+            mailer,
+            conf.mail.from.parse()?,
+            conf.mail.retries,
+        ))
+    }
+
+    /// Spawn the delivery task. Messages are sent one at a time.
+    pub fn start(mailer: Arc<dyn Mailer>, from: Mailbox, retries: u32) -> Self {
+        let (sender, mut receiver) = unbounded_channel::<Queued>();
+        let retry_sender = sender.clone();
+
+        tokio::spawn(async move {
+            while let Some(queued) = receiver.recv().await {
+                let message = match queued.mail.message(&from) {
+                    Ok(message) => message,
+                    Err(error) => {
+                        error!("Invalid mail to {}: {}", queued.mail.to, error);
+                        continue;
+                    }
+                };
+
+                let error = match mailer.send(message).await {
+                    Ok(()) => continue,
+                    Err(error) => error,
+                };
+
+                if queued.attempt >= retries {
+                    error!(
+                        "Failed to deliver mail to {} after {} attempts: {}",
+                        queued.mail.to,
+                        queued.attempt + 1,
+                        error
+                    );
+                    continue;
+                }
+
+                let delay = Duration::from_secs(2u64.pow(queued.attempt));
+
+                warn!(
+                    "Failed to deliver mail to {}, retrying in {:?}: {}",
+                    queued.mail.to, delay, error
+                );
+
+                let retry_sender = retry_sender.clone();
+
+                // Wait without holding up the rest of the queue
+                tokio::spawn(async move {
+                    tokio::time::delay_for(delay).await;
+
+                    let _ = retry_sender.send(Queued {
+                        mail: queued.mail,
+                        attempt: queued.attempt + 1,
+                    });
+                });
+            }
+        });
+
+        MailQueue { sender }
+    }
+
+    /// Queue the message for delivery. Errors only if the delivery task has stopped.
+    pub fn send(&self, mail: Mail) -> Result<()> {
+        self.sender
+            .send(Queued { mail, attempt: 0 })
+            .map_err(|_| anyhow!("The mail queue has stopped."))
+    }
+}
+
+impl FromRequest for MailQueue {
+    type Error = Res<()>;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.app_data::<MailQueue>() {
+            Some(queue) => ok(queue.clone()),
+            _ => {
+                error!("MailQueue does not exists in app's data!");
+
+                err(Res::<()>::error("No MailQueue in app's data"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lettre::Message;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails the first delivery attempts.
+    struct FlakyMailer {
+        attempts: AtomicU32,
+        failures: u32,
+    }
+
+    #[async_trait::async_trait]
+    impl Mailer for FlakyMailer {
+        async fn send(&self, _: Message) -> Result<()> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                bail!("Temporary failure");
+            }
+
+            Ok(())
+        }
+    }
+
+    /// A failed delivery should be retried.
+    #[tokio::test]
+    async fn retry() {
+        let mailer = Arc::new(FlakyMailer {
+            attempts: AtomicU32::new(0),
+            failures: 1,
+        });
+
+        let queue = MailQueue::start(mailer.clone(), "dia@email.com".parse().unwrap(), 3);
+
+        queue
+            .send(Mail {
+                to: "user@email.com".into(),
+                subject: "Subject".into(),
+                text: "Body".into(),
+                html: None,
+            })
+            .unwrap();
+
+        tokio::time::delay_for(Duration::from_millis(1500)).await;
+
+        assert_eq!(mailer.attempts.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,70 @@
+use super::Mailer;
+use crate::config::SmtpConfig;
+use anyhow::Result;
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message,
+    Tokio02Executor,
+};
+
+/// Delivers messages to an SMTP server.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio02Executor>,
+}
+
+impl SmtpMailer {
+    /// Connect with TLS if configured, and authenticate if an username is set.
+    /// Plain connections are meant for local relays and test sinks.
+    pub fn new(conf: &SmtpConfig) -> Result<Self> {
+        let mut builder = if conf.tls {
+            AsyncSmtpTransport::<Tokio02Executor>::relay(&conf.host)?
+        } else {
+            AsyncSmtpTransport::<Tokio02Executor>::builder_dangerous(&conf.host)
+        }
+        .port(conf.port);
+
+        if !conf.username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                conf.username.clone(),
+                conf.password.clone(),
+            ));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, message: Message) -> Result<()> {
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mail::Mail, Config, CONF_FILE};
+
+    /// Needs an SMTP sink at `[mail.smtp]`, like MailHog from `docker-compose.yml`.
+    #[tokio::test]
+    async fn send_to_sink() {
+        let conf = Config::from_file(CONF_FILE);
+        let mailer = SmtpMailer::new(&conf.mail.smtp).unwrap();
+
+        let mail = Mail {
+            to: "user@email.com".into(),
+            subject: "Subject".into(),
+            text: "Body".into(),
+            html: Some("<p>Body</p>".into()),
+        };
+
+        mailer
+            .send(mail.message(&conf.mail.from.parse().unwrap()).unwrap())
+            .await
+            .unwrap();
+    }
+}
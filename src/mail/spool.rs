@@ -0,0 +1,85 @@
+use super::Mailer;
+use anyhow::Result;
+use lettre::Message;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tokio::task::spawn_blocking;
+use uuid::Uuid;
+
+/// Writes messages to `<dir>/<id>.eml` files, or to stdout without a directory.
+/// For development and tests.
+pub struct SpoolMailer {
+    dir: Option<PathBuf>,
+}
+
+impl SpoolMailer {
+    pub fn new(dir: Option<&Path>) -> Self {
+        SpoolMailer {
+            dir: dir.map(Path::to_path_buf),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for SpoolMailer {
+    async fn send(&self, message: Message) -> Result<()> {
+        let formatted = message.formatted();
+
+        match &self.dir {
+            Some(dir) => {
+                let path = dir.join(format!("{}.eml", Uuid::new_v4().to_simple()));
+                let dir = dir.clone();
+
+                spawn_blocking(move || -> Result<()> {
+                    std::fs::create_dir_all(dir)?;
+                    std::fs::write(path, formatted)?;
+
+                    Ok(())
+                })
+                .await??;
+            }
+            None => {
+                let mut stdout = std::io::stdout();
+
+                stdout.write_all(&formatted)?;
+                stdout.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::Mail;
+
+    /// A spooled message is written to it's own file.
+    #[tokio::test]
+    async fn spool_to_dir() {
+        let dir = std::env::temp_dir().join(format!("dia_spool_{}", Uuid::new_v4().to_simple()));
+        let mailer = SpoolMailer::new(Some(&dir));
+
+        let mail = Mail {
+            to: "user@email.com".into(),
+            subject: "Subject".into(),
+            text: "Body".into(),
+            html: None,
+        };
+
+        mailer
+            .send(
+                mail.message(&"dia <dia@email.com>".parse().unwrap())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
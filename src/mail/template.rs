@@ -0,0 +1,65 @@
+use super::Mail;
+use serde::Deserialize;
+
+/// A message configured in `config.toml`. Variables like `{{token}}` are replaced when rendering.
+/// Values are HTML escaped in the HTML body.
+#[derive(Deserialize, Clone)]
+pub struct MailTemplate {
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+impl MailTemplate {
+    /// Render the template into a message to the address.
+    pub fn render(&self, to: &str, vars: &[(&str, &str)]) -> Mail {
+        Mail {
+            to: to.into(),
+            subject: replace(&self.subject, vars, |value| value.into()),
+            text: replace(&self.text, vars, |value| value.into()),
+            html: self
+                .html
+                .as_ref()
+                .map(|html| replace(html, vars, escape_html)),
+        }
+    }
+}
+
+fn replace(template: &str, vars: &[(&str, &str)], escape: fn(&str) -> String) -> String {
+    vars.iter()
+        .fold(template.to_string(), |rendered, (name, value)| {
+            rendered.replace(&format!("{{{{{}}}}}", name), &escape(value))
+        })
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let template = MailTemplate {
+            subject: "Hi {{username}}".into(),
+            text: "Your token is {{token}}".into(),
+            html: Some("<p>{{username}}</p>".into()),
+        };
+
+        let mail = template.render(
+            "user@email.com",
+            &[("username", "<user>"), ("token", "abc")],
+        );
+
+        assert_eq!(mail.subject, "Hi <user>");
+        assert_eq!(mail.text, "Your token is abc");
+        assert_eq!(mail.html.unwrap(), "<p>&lt;user&gt;</p>");
+    }
+}
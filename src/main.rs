@@ -14,21 +14,27 @@ extern crate anyhow;
 #[macro_use]
 extern crate enum_display_derive;
 
+// Declared first, so the test macros are available in every module.
+#[macro_use]
+mod macros;
+
 mod access;
+mod cli;
 mod config;
 mod db;
 mod gql;
 mod logging;
-#[macro_use]
-mod macros;
+mod mail;
 mod models;
 mod res;
 mod routes;
 
 use crate::{
-    access::{create_cors, RateLimiter, JWT},
+    access::{create_cors, jwt::Denylist, Lockout, RateLimiter, JWT},
     db::{RedisConn, SqlxConn},
     gql::build_schema,
+    mail::MailQueue,
+    models::refresh_token::RefreshToken,
 };
 use actix_web::{App, HttpServer};
 pub use config::Config;
@@ -47,13 +53,35 @@ async fn main() -> std::io::Result<()> {
     let conf = Config::from_file(CONF_FILE);
     let pg = SqlxConn::new(&conf).await;
     let rd = RedisConn::new(&conf);
-    let rl = RateLimiter::new(rd.clone());
-    let schema = build_schema();
-    let jwt = JWT::generate().unwrap();
+    let rl = RateLimiter::new(rd.clone(), &conf.rate_limits);
+    let lockout = Lockout::new(rd.clone(), &conf);
+    let schema = build_schema(&conf.graphql);
+    let jwt = JWT::from_config(&conf)
+        .unwrap()
+        .with_denylist(Denylist::new(rd.clone()));
+
+    let mail = MailQueue::from_config(&conf).unwrap();
+
+    // Check for key rotation in the background
+    jwt.schedule_rotation();
 
     // Run Sqlx migrations
     pg.migrate().await;
 
+    // Run a command instead of the server if one is given
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if !args.is_empty() {
+        cli::run(&pg.clone().into_inner(), &args).await;
+
+        return Ok(());
+    }
+
+    // Hash refresh tokens stored before hashing was introduced
+    RefreshToken::hash_legacy_tokens(&pg.clone().into_inner(), conf.tokens.hash_key.as_bytes())
+        .await
+        .unwrap();
+
     // Parse address and port to bind to
     let addr: SocketAddr = conf.bind_to.parse().unwrap();
 
@@ -65,8 +93,11 @@ async fn main() -> std::io::Result<()> {
             .app_data(pg.clone())
             .app_data(rd.clone())
             .app_data(rl.clone())
+            .app_data(lockout.clone())
             .app_data(jwt.clone())
-            .service(routes::build())
+            .app_data(mail.clone())
+            .service(routes::build(&conf))
+            .service(routes::well_known())
     })
     .bind(addr)?
     .run()
@@ -14,12 +14,12 @@ impl Add {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::gql::build_schema;
+    use crate::{gql::build_schema, Config, CONF_FILE};
 
     #[tokio::test]
     /// Simple example for testing GQL queries.
     async fn add() {
-        let s = build_schema();
+        let s = build_schema(&Config::from_file(CONF_FILE).graphql);
         let q = Request::new("query { add(a: 1, b: 1) }");
 
         let res = s.execute(q).await;
@@ -1,59 +1,109 @@
 use crate::{
-    access::jwt::{JwtClaims, JWT},
-    models::{refresh_token::RefreshToken, user::User},
+    access::jwt::{ProfileClaim, JWT},
+    gql::R,
+    models::{refresh_token::RefreshToken, role::Role, user::User},
+    Config,
 };
 
 use async_graphql::*;
-use chrono::{Duration, Utc};
+use std::net::IpAddr;
+
+/// A signed JWT and the refresh token to use next time.
+#[derive(SimpleObject)]
+struct JwtRefresh {
+    jwt: String,
+    /// A new token for rotating refresh tokens, otherwise the same token.
+    refresh_token: RefreshToken,
+}
 
 #[derive(Default)]
 pub struct JwtMutation;
 
+/// Redeem the refresh token and sign a JWT for the user it belongs to.
+async fn sign(
+    ctx: &Context<'_>,
+    refresh_token_string: &str,
+    lifetime: i64,
+    audience: Option<String>,
+    allow_rotation: bool,
+) -> R<(String, RefreshToken)> {
+    let pool = ctx.data::<sqlx::PgPool>()?;
+    let jwt = ctx.data::<JWT>()?;
+
+    // Get the token the string refers to, rotating it if needed
+    let refresh_token = RefreshToken::redeem(
+        pool,
+        jwt.denylist()?,
+        ctx.data::<Config>()?.tokens.hash_key.as_bytes(),
+        refresh_token_string,
+        &ctx.data::<IpAddr>()?.to_string(),
+        lifetime,
+        allow_rotation,
+    )
+    .await?;
+
+    // Get the user correspoding to the refresh token
+    let user = User::from_id(pool, refresh_token.user_id).await?;
+
+    let audience = audience.as_deref().unwrap_or_else(|| jwt.audience());
+    let mut claims = jwt.claims(&user, audience, lifetime, refresh_token.id)?;
+
+    if jwt.includes(ProfileClaim::Permissions) {
+        claims.permissions = Some(Role::effective_permissions(pool, user.id).await?);
+    }
+
+    Ok((jwt.encode(&claims)?, refresh_token))
+}
+
 #[Object]
 impl JwtMutation {
     /// Generate a new JWT from a token string.
     /// Lifetime must be equal or lower as the tokens maximum JWT lifetime.
+    /// The audience has to be registered, and defaults to this server.
+    /// Rotating refresh tokens have to use `refreshJwt`.
     async fn sign_jwt(
         &self,
         ctx: &Context<'_>,
         refresh_token_string: String,
         #[graphql(default = 300)] lifetime: i64,
+        audience: Option<String>,
     ) -> Result<String> {
-        let pool = ctx.data::<sqlx::PgPool>()?;
-
-        // Get the token the string refers to
-        let refresh_token = sqlx::query_as!(
-            RefreshToken,
-            "SELECT * FROM refresh_tokens WHERE expires > NOW() AND token_string = $1;",
-            refresh_token_string
-        )
-        .fetch_one(pool)
-        .await?;
-
-        // Check that the lifetime of an allowed length
-        if lifetime > refresh_token.max_jwt_lifetime as i64 {
-            return Err(Error::new(format!(
-                "Requested JWT lifetime exceeds the limit of {}.",
-                refresh_token.max_jwt_lifetime,
-            )));
-        }
-
-        // Get the user correspoding to the refresh token
-        let user = sqlx::query_as!(
-            User,
-            "SELECT * FROM users WHERE id = $1",
-            refresh_token.user_id
-        )
-        .fetch_one(pool)
-        .await?;
-
-        let claims = JwtClaims {
-            user,
-            parent_token: refresh_token.id,
-            iat: Utc::now().timestamp(),
-            exp: (Utc::now() + Duration::seconds(lifetime)).timestamp(),
-        };
-
-        Ok(ctx.data::<JWT>()?.encode(&claims)?)
+        Ok(sign(ctx, &refresh_token_string, lifetime, audience, false)
+            .await?
+            .0)
+    }
+
+    /// Generate a new JWT like `signJwt`, and get the refresh token to use next time.
+    /// Rotating refresh tokens are replaced on every use, and using a replaced token
+    /// revokes every token of the session.
+    async fn refresh_jwt(
+        &self,
+        ctx: &Context<'_>,
+        refresh_token_string: String,
+        #[graphql(default = 300)] lifetime: i64,
+        audience: Option<String>,
+    ) -> Result<JwtRefresh> {
+        let (jwt, refresh_token) =
+            sign(ctx, &refresh_token_string, lifetime, audience, true).await?;
+
+        Ok(JwtRefresh { jwt, refresh_token })
+    }
+
+    /// Revoke a signed JWT until it expires. Holding the token is enough to revoke it.
+    /// The audience defaults to this server.
+    async fn revoke_jwt(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        audience: Option<String>,
+    ) -> Result<bool> {
+        let jwt = ctx.data::<JWT>()?;
+        let audience = audience.as_deref().unwrap_or_else(|| jwt.audience());
+
+        let claims = jwt.verify(&token, audience)?.claims;
+
+        jwt.denylist()?.revoke_jwt(&claims).await?;
+
+        Ok(true)
     }
 }
@@ -1,5 +1,5 @@
 use crate::{
-    access::jwt::{JwtClaims, JWT},
+    access::jwt::{Jwks, JwtClaims, PublicKey, JWT},
     gql::E,
 };
 use async_graphql::*;
@@ -10,27 +10,46 @@ pub struct JwtQuery;
 #[Object]
 impl JwtQuery {
     /// The public key can be used to validate signed and issued JSON web tokens.
-    async fn jwt_public_key<'ctx>(&self, ctx: &Context<'ctx>) -> std::result::Result<&'ctx str, E> {
-        Ok(std::str::from_utf8(&ctx.data::<JWT>()?.public_key)?)
+    /// Available in PEM and JWK format.
+    /// Only the current signing key, use `jwks` to get every accepted key.
+    async fn jwt_public_key(&self, ctx: &Context<'_>) -> std::result::Result<PublicKey, E> {
+        Ok(ctx.data::<JWT>()?.public_key()?)
     }
 
-    /// Check if the server would accept the token.
+    /// Every key JWTs are accepted from, in the same format as `/.well-known/jwks.json`.
+    async fn jwks(&self, ctx: &Context<'_>) -> std::result::Result<Jwks, E> {
+        Ok(ctx.data::<JWT>()?.jwks()?)
+    }
+
+    /// Check if the token would be accepted for the audience, defaulting to this server.
+    /// Revoked tokens are not valid.
     /// Note that the token might expire right after validating it here.
-    async fn is_jwt_valid(&self, ctx: &Context<'_>, token: String) -> std::result::Result<bool, E> {
+    async fn is_jwt_valid(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        audience: Option<String>,
+    ) -> std::result::Result<bool, E> {
         let jwt = ctx.data::<JWT>()?;
+        let audience = audience.as_deref().unwrap_or_else(|| jwt.audience());
 
-        match jwt.decode(&token) {
+        match jwt.decode(&token, audience).await {
             Ok(_) => Ok(true),
             Err(_) => Ok(false),
         }
     }
 
     /// Decode a signed JWT. Only if the token is usable and valid the claims are returned.
+    /// The audience defaults to this server.
     async fn decode_jwt(
         &self,
         ctx: &Context<'_>,
         jwt: String,
+        audience: Option<String>,
     ) -> std::result::Result<JwtClaims, E> {
-        Ok(ctx.data::<JWT>()?.decode(&jwt)?.claims)
+        let provider = ctx.data::<JWT>()?;
+        let audience = audience.as_deref().unwrap_or_else(|| provider.audience());
+
+        Ok(provider.decode(&jwt, audience).await?.claims)
     }
 }
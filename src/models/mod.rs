@@ -1,13 +1,21 @@
 mod add;
 mod count;
 mod jwt;
+pub mod one_time_token;
+pub mod passkey;
 mod ping;
 pub mod refresh_token;
+pub mod role;
+pub mod security_event;
+pub mod two_factor;
 pub mod user;
 
 pub use add::Add;
 pub use count::CountSubscription;
 pub use jwt::{JwtMutation, JwtQuery};
+pub use passkey::PasskeyMutation;
 pub use ping::Ping;
 pub use refresh_token::{RefreshTokenMutation, RefreshTokenQuery};
+pub use role::{RoleMutation, RoleQuery};
+pub use two_factor::TwoFactorMutation;
 pub use user::{UserMutation, UserQuery};
@@ -0,0 +1,150 @@
+use crate::{
+    access::secret,
+    gql::{E, R},
+};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use std::fmt::Display;
+use uuid::Uuid;
+
+/// Length of generated token strings.
+const TOKEN_LEN: usize = 64;
+
+/// Length of the public prefix tokens are looked up by.
+const PREFIX_LEN: usize = 12;
+
+/// What a token can be used for. Stored as text, a token of one kind can't be used as another.
+#[derive(Display, Clone, Copy, PartialEq, Debug)]
+pub enum OneTimeTokenKind {
+    PasswordReset,
+    /// Confirms the address in the payload belongs to the user.
+    EmailVerification,
+    /// Logs in without a password. Bound to the address it was sent to.
+    MagicLink,
+}
+
+/// A single-use token sent to an user, valid until it expires or is used.
+#[derive(Debug)]
+pub struct OneTimeToken {
+    pub id: Uuid,
+    pub created: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    pub used: Option<DateTime<Utc>>,
+    pub user_id: Uuid,
+    pub kind: String,
+    pub token_prefix: String,
+    token_hash: String,
+    /// Data the token is bound to, like the email address to verify.
+    pub payload: Option<String>,
+}
+
+impl OneTimeToken {
+    /// Create a token valid for the lifetime, with optional data it's bound to.
+    /// Returns the token string, which is not stored.
+    pub async fn create(
+        pool: &PgPool,
+        hash_key: &[u8],
+        kind: OneTimeTokenKind,
+        user_id: Uuid,
+        lifetime: Duration,
+        payload: Option<&str>,
+    ) -> R<String> {
+        let token_string = secret::random_string(TOKEN_LEN);
+
+        sqlx::query!(
+            r#"
+            INSERT INTO one_time_tokens (expires, user_id, kind, token_prefix, token_hash, payload)
+            VALUES ($1, $2, $3, $4, $5, $6);
+            "#,
+            Utc::now() + lifetime,
+            user_id,
+            kind.to_string(),
+            &token_string[..PREFIX_LEN],
+            secret::hash(hash_key, &token_string)?,
+            payload
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(token_string)
+    }
+
+    /// Find an unused token of the kind by it's string, without using it.
+    /// For checks that shouldn't use up the token when they fail, before `use_once`.
+    pub async fn find(
+        pool: &PgPool,
+        hash_key: &[u8],
+        kind: OneTimeTokenKind,
+        token_string: &str,
+    ) -> R<OneTimeToken> {
+        let prefix = match token_string.get(..PREFIX_LEN) {
+            Some(prefix) => prefix,
+            None => return Err(E::ItemNotFound("Token".into())),
+        };
+
+        let candidates = sqlx::query_as!(
+            OneTimeToken,
+            r#"
+            SELECT * FROM one_time_tokens
+            WHERE token_prefix = $1 AND kind = $2 AND used IS NULL AND expires > NOW();
+            "#,
+            prefix,
+            kind.to_string()
+        )
+        .fetch_all(pool)
+        .await?;
+
+        for token in candidates {
+            if secret::verify(hash_key, token_string, &token.token_hash)? {
+                return Ok(token);
+            }
+        }
+
+        Err(E::ItemNotFound("Token".into()))
+    }
+
+    /// Mark the token used. Fails if it has been used since it was found,
+    /// so a token can only be used once, even by concurrent requests.
+    pub async fn use_once(self, pool: &PgPool) -> R<OneTimeToken> {
+        sqlx::query_as!(
+            OneTimeToken,
+            r#"
+            UPDATE one_time_tokens SET used = NOW()
+            WHERE id = $1 AND used IS NULL AND expires > NOW() RETURNING *;
+            "#,
+            self.id
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| E::ItemNotFound("Token".into()))
+    }
+
+    /// Find an unused token of the kind by it's string and mark it used.
+    pub async fn redeem(
+        pool: &PgPool,
+        hash_key: &[u8],
+        kind: OneTimeTokenKind,
+        token_string: &str,
+    ) -> R<OneTimeToken> {
+        Self::find(pool, hash_key, kind, token_string)
+            .await?
+            .use_once(pool)
+            .await
+    }
+
+    /// Mark every unused token of the kind for the user used.
+    pub async fn invalidate(pool: &PgPool, kind: OneTimeTokenKind, user_id: Uuid) -> R<()> {
+        sqlx::query!(
+            r#"
+            UPDATE one_time_tokens SET used = NOW()
+            WHERE user_id = $1 AND kind = $2 AND used IS NULL;
+            "#,
+            user_id,
+            kind.to_string()
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+mod mutation;
+
+pub use mutation::PasskeyMutation;
+
+use crate::{
+    access::webauthn::{self, ClientData, CoseKey},
+    config::WebAuthnConfig,
+    gql::{E, R},
+    models::security_event::{SecurityEvent, SecurityEventKind},
+};
+use async_graphql::*;
+use chrono::{DateTime, Utc};
+use openssl::rand::rand_bytes;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sqlx::{Done, PgPool};
+use uuid::Uuid;
+
+/// Byte length of generated challenges.
+const CHALLENGE_LEN: usize = 32;
+
+/// A WebAuthn credential of an user.
+#[derive(SimpleObject, Debug)]
+pub struct Passkey {
+    pub id: Uuid,
+    pub created: DateTime<Utc>,
+    pub user_id: Uuid,
+    pub name: Option<String>,
+    #[graphql(skip)]
+    pub credential_id: Vec<u8>,
+    #[graphql(skip)]
+    pub public_key: Vec<u8>,
+    #[graphql(skip)]
+    pub algorithm: i64,
+    #[graphql(skip)]
+    pub sign_count: i64,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// The response of `navigator.credentials.create()`, binary values base64url encoded.
+#[derive(InputObject)]
+pub struct PasskeyAttestation {
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// The response of `navigator.credentials.get()`, binary values base64url encoded.
+#[derive(InputObject)]
+pub struct PasskeyAssertion {
+    pub credential_id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+/// Which ceremony a challenge was issued for, and to whom.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+enum Ceremony {
+    /// Adding a credential for the user.
+    Registration(Uuid),
+    /// Logging in, as the user if known.
+    Login(Option<Uuid>),
+}
+
+impl Ceremony {
+    fn client_data_type(&self) -> &'static str {
+        match self {
+            Ceremony::Registration(_) => "webauthn.create",
+            Ceremony::Login(_) => "webauthn.get",
+        }
+    }
+}
+
+fn challenge_key(challenge: &str) -> String {
+    format!("WEBAUTHN_CHALLENGE_{}", challenge)
+}
+
+/// Store a new random challenge for the ceremony until the timeout.
+async fn create_challenge(
+    redis: &redis::Client,
+    conf: &WebAuthnConfig,
+    ceremony: Ceremony,
+) -> R<String> {
+    let mut bytes = [0u8; CHALLENGE_LEN];
+    rand_bytes(&mut bytes).map_err(anyhow::Error::from)?;
+    let challenge = webauthn::base64url(&bytes);
+
+    let mut con = redis.get_async_connection().await.map_err(E::Redis)?;
+
+    con.set_ex::<_, _, ()>(
+        challenge_key(&challenge),
+        serde_json::to_string(&ceremony).map_err(anyhow::Error::from)?,
+        conf.challenge_timeout as usize,
+    )
+    .await
+    .map_err(E::Redis)?;
+
+    Ok(challenge)
+}
+
+/// Check the client data and consume the challenge it was signed for.
+/// A challenge can be used only once.
+async fn take_challenge(
+    redis: &redis::Client,
+    conf: &WebAuthnConfig,
+    client_data_json: &[u8],
+    is_registration: bool,
+) -> R<Ceremony> {
+    let client_data = ClientData::parse(client_data_json)?;
+
+    let mut con = redis.get_async_connection().await.map_err(E::Redis)?;
+
+    let (stored, _): (Option<String>, u64) = redis::pipe()
+        .atomic()
+        .get(challenge_key(&client_data.challenge))
+        .del(challenge_key(&client_data.challenge))
+        .query_async(&mut con)
+        .await
+        .map_err(E::Redis)?;
+
+    let ceremony: Ceremony = match stored {
+        Some(stored) => serde_json::from_str(&stored).map_err(anyhow::Error::from)?,
+        None => return Err(E::Message("Unknown or expired WebAuthn challenge.".into())),
+    };
+
+    if matches!(ceremony, Ceremony::Registration(_)) != is_registration {
+        return Err(E::Message("Wrong WebAuthn ceremony.".into()));
+    }
+
+    webauthn::check_client_data(
+        conf,
+        &client_data,
+        ceremony.client_data_type(),
+        &client_data.challenge,
+    )?;
+
+    Ok(ceremony)
+}
+
+impl Passkey {
+    pub async fn for_user(pool: &PgPool, user_id: Uuid) -> R<Vec<Passkey>> {
+        Ok(sqlx::query_as!(
+            Passkey,
+            "SELECT * FROM passkeys WHERE user_id = $1 ORDER BY created",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// `true` if the user has registered atleast one passkey.
+    pub async fn exists_for_user(pool: &PgPool, user_id: Uuid) -> R<bool> {
+        Ok(sqlx::query!(
+            "SELECT id FROM passkeys WHERE user_id = $1 LIMIT 1",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?
+        .is_some())
+    }
+
+    /// Verify a registration response and store the credential.
+    pub async fn register(
+        pool: &PgPool,
+        redis: &redis::Client,
+        conf: &WebAuthnConfig,
+        user_id: Uuid,
+        attestation: &PasskeyAttestation,
+        name: Option<String>,
+    ) -> R<Passkey> {
+        let client_data_json = webauthn::from_base64url(&attestation.client_data_json)?;
+
+        if take_challenge(redis, conf, &client_data_json, true).await?
+            != Ceremony::Registration(user_id)
+        {
+            return Err(E::Message("The challenge is for another user.".into()));
+        }
+
+        let credential = webauthn::verify_registration(
+            conf,
+            &webauthn::from_base64url(&attestation.attestation_object)?,
+            false,
+        )?;
+
+        let passkey = sqlx::query_as!(
+            Passkey,
+            r#"
+            INSERT INTO passkeys (user_id, name, credential_id, public_key, algorithm, sign_count)
+            VALUES ($1, $2, $3, $4, $5, $6) RETURNING *;
+            "#,
+            user_id,
+            name,
+            credential.credential_id,
+            credential.public_key.der,
+            credential.public_key.algorithm,
+            credential.sign_count as i64
+        )
+        .fetch_one(pool)
+        .await?;
+
+        SecurityEvent::record(
+            pool,
+            SecurityEventKind::PasskeyRegistered,
+            Some(user_id),
+            None,
+            passkey.id.to_string(),
+        )
+        .await?;
+
+        Ok(passkey)
+    }
+
+    /// Remove one of the user's passkeys. Returns `false` if there was no such passkey.
+    pub async fn delete(pool: &PgPool, user_id: Uuid, id: Uuid) -> R<bool> {
+        let deleted = sqlx::query!(
+            "DELETE FROM passkeys WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .execute(pool)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if deleted {
+            SecurityEvent::record(
+                pool,
+                SecurityEventKind::PasskeyRemoved,
+                Some(user_id),
+                None,
+                id.to_string(),
+            )
+            .await?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Verify a login response. The passkey has to belong to the user the challenge was
+    /// issued for, if any. Passwordless logins should require user verification.
+    pub async fn authenticate(
+        pool: &PgPool,
+        redis: &redis::Client,
+        conf: &WebAuthnConfig,
+        assertion: &PasskeyAssertion,
+        user_id: Option<Uuid>,
+        require_uv: bool,
+    ) -> R<Passkey> {
+        let client_data_json = webauthn::from_base64url(&assertion.client_data_json)?;
+
+        let challenge_user = match take_challenge(redis, conf, &client_data_json, false).await? {
+            Ceremony::Login(challenge_user) => challenge_user,
+            Ceremony::Registration(_) => return Err(E::InvalidInput),
+        };
+
+        let passkey = sqlx::query_as!(
+            Passkey,
+            "SELECT * FROM passkeys WHERE credential_id = $1",
+            webauthn::from_base64url(&assertion.credential_id)?
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| E::ItemNotFound("Passkey".into()))?;
+
+        // Both the challenge and the caller might expect a specific user
+        for expected_user in [challenge_user, user_id].iter().flatten() {
+            if *expected_user != passkey.user_id {
+                return Err(E::ItemNotFound("Passkey".into()));
+            }
+        }
+
+        let sign_count = webauthn::verify_assertion(
+            conf,
+            &CoseKey::new(passkey.algorithm, passkey.public_key.clone()),
+            passkey.sign_count as u32,
+            &client_data_json,
+            &webauthn::from_base64url(&assertion.authenticator_data)?,
+            &webauthn::from_base64url(&assertion.signature)?,
+            require_uv,
+        )?;
+
+        Ok(sqlx::query_as!(
+            Passkey,
+            "UPDATE passkeys SET sign_count = $2, last_used = NOW() WHERE id = $1 RETURNING *;",
+            passkey.id,
+            sign_count as i64
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+}
@@ -0,0 +1,433 @@
+use super::{create_challenge, Ceremony, Passkey, PasskeyAssertion, PasskeyAttestation};
+use crate::{
+    access::{
+        current_user,
+        guard::{Guard, RequireAuth},
+        policy, secret,
+        webauthn::{self, cose},
+        Identifier, Lockout, RateLimited, RateLimiter,
+    },
+    gql::{E, R},
+    models::{
+        refresh_token::{RefreshToken, TokenOptions},
+        two_factor::TwoFactor,
+        user::User,
+    },
+    Config,
+};
+use async_graphql::*;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Algorithms offered to authenticators, in order of preference.
+const ALGORITHMS: [i64; 3] = [cose::ES256, cose::EDDSA, cose::RS256];
+
+/// Credential descriptors for `excludeCredentials` and `allowCredentials`.
+fn descriptors(passkeys: &[Passkey]) -> Vec<Value> {
+    passkeys
+        .iter()
+        .map(|passkey| {
+            json!({
+                "type": "public-key",
+                "id": webauthn::base64url(&passkey.credential_id),
+            })
+        })
+        .collect()
+}
+
+/// A descriptor for a credential that doesn't exist, the same for every request with the
+/// username. Listed when the username is unknown or has no passkeys, so the options look like
+/// those of an user with a passkey.
+fn decoy_descriptor(hash_key: &[u8], username: &str) -> R<Value> {
+    let id = secret::hmac(
+        hash_key,
+        format!("passkey_decoy:{}", username.to_lowercase()).as_bytes(),
+    )?;
+
+    Ok(json!({
+        "type": "public-key",
+        "id": webauthn::base64url(&id),
+    }))
+}
+
+/// A newly registered passkey.
+#[derive(SimpleObject)]
+struct PasskeyRegistration {
+    passkey: Passkey,
+    /// Single-use recovery codes, if this is the user's first second factor.
+    /// They are not shown again.
+    recovery_codes: Option<Vec<String>>,
+}
+
+#[derive(Default)]
+pub struct PasskeyMutation;
+
+#[Object]
+impl PasskeyMutation {
+    /// Options for `navigator.credentials.create()` to add a passkey for the authenticated user.
+    /// Binary values are base64url encoded.
+    /// Requires the password, and the second factor if two-factor authentication is enabled.
+    #[graphql(guard(RequireAuth()))]
+    async fn start_passkey_registration(
+        &self,
+        ctx: &Context<'_>,
+        password: String,
+        otp: Option<String>,
+        passkey: Option<PasskeyAssertion>,
+    ) -> std::result::Result<Json<Value>, E> {
+        let conf = ctx.data::<Config>()?;
+        let user = current_user(ctx).await?;
+
+        Lockout::from_context(ctx)?
+            .reauthenticate(ctx, &user, password, otp.as_deref(), passkey.as_ref())
+            .await?;
+
+        let challenge = create_challenge(
+            ctx.data::<redis::Client>()?,
+            &conf.webauthn,
+            Ceremony::Registration(user.id),
+        )
+        .await?;
+
+        let existing = Passkey::for_user(ctx.data::<PgPool>()?, user.id).await?;
+
+        Ok(Json(json!({
+            "publicKey": {
+                "rp": { "id": conf.webauthn.rp_id, "name": conf.webauthn.rp_name },
+                "user": {
+                    "id": webauthn::base64url(user.id.as_bytes()),
+                    "name": user.username,
+                    "displayName": user.display_name.as_ref().unwrap_or(&user.username),
+                },
+                "challenge": challenge,
+                "pubKeyCredParams": ALGORITHMS
+                    .iter()
+                    .map(|alg| json!({ "type": "public-key", "alg": alg }))
+                    .collect::<Vec<_>>(),
+                "timeout": conf.webauthn.challenge_timeout * 1000,
+                "attestation": "none",
+                "excludeCredentials": descriptors(&existing),
+                "authenticatorSelection": {
+                    "residentKey": "preferred",
+                    "userVerification": "preferred",
+                },
+            }
+        })))
+    }
+
+    /// Store the passkey created with the options from `startPasskeyRegistration`.
+    /// The challenge is only issued after checking the password, so it stands in for it here.
+    /// Users with a passkey need it, or a TOTP code, as a second factor with their password.
+    /// The first passkey enables two-factor authentication, and comes with recovery codes.
+    #[graphql(guard(RequireAuth()))]
+    async fn finish_passkey_registration(
+        &self,
+        ctx: &Context<'_>,
+        attestation: PasskeyAttestation,
+        name: Option<String>,
+    ) -> std::result::Result<PasskeyRegistration, E> {
+        if matches!(&name, Some(name) if name.chars().count() > 50) {
+            return Err(E::Message(
+                "Field name should be atmost 50 characters.".into(),
+            ));
+        }
+
+        let pool = ctx.data::<PgPool>()?;
+        let conf = ctx.data::<Config>()?;
+        let user_id = current_user(ctx).await?.id;
+
+        let first = !TwoFactor::is_enabled(pool, user_id).await?;
+
+        let passkey = Passkey::register(
+            pool,
+            ctx.data::<redis::Client>()?,
+            &conf.webauthn,
+            user_id,
+            &attestation,
+            name,
+        )
+        .await?;
+
+        let recovery_codes = if first {
+            Some(
+                TwoFactor::regenerate_recovery_codes(
+                    pool,
+                    conf.tokens.hash_key.as_bytes(),
+                    user_id,
+                )
+                .await?,
+            )
+        } else {
+            None
+        };
+
+        Ok(PasskeyRegistration {
+            passkey,
+            recovery_codes,
+        })
+    }
+
+    /// Remove one of the authenticated user's passkeys.
+    /// Requires the password and the second factor, like `startPasskeyRegistration`.
+    #[graphql(guard(RequireAuth()))]
+    async fn delete_passkey(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        password: String,
+        otp: Option<String>,
+        passkey: Option<PasskeyAssertion>,
+    ) -> std::result::Result<bool, E> {
+        let user = current_user(ctx).await?;
+
+        Lockout::from_context(ctx)?
+            .reauthenticate(ctx, &user, password, otp.as_deref(), passkey.as_ref())
+            .await?;
+
+        Passkey::delete(ctx.data::<PgPool>()?, user.id, id).await
+    }
+
+    /// Options for `navigator.credentials.get()`.
+    /// Without an username any discoverable passkey can be used, for a passwordless login.
+    /// With an username the user's passkeys are allowed, also as a second factor.
+    async fn start_passkey_login(
+        &self,
+        ctx: &Context<'_>,
+        username: Option<String>,
+    ) -> std::result::Result<Json<Value>, E> {
+        let conf = ctx.data::<Config>()?;
+        let pool = ctx.data::<PgPool>()?;
+
+        let user = match &username {
+            Some(username) => User::from_username(pool, username).await.ok(),
+            None => None,
+        };
+
+        let allowed = match &user {
+            Some(user) => descriptors(&Passkey::for_user(pool, user.id).await?),
+            None => vec![],
+        };
+
+        // Otherwise an empty list would reveal that the username has no passkey
+        let allowed = match &username {
+            Some(username) if allowed.is_empty() => {
+                vec![decoy_descriptor(conf.tokens.hash_key.as_bytes(), username)?]
+            }
+            _ => allowed,
+        };
+
+        // An unknown username gets a challenge that no passkey can satisfy
+        let ceremony = match (&username, &user) {
+            (Some(_), Some(user)) => Ceremony::Login(Some(user.id)),
+            (Some(_), None) => Ceremony::Login(Some(Uuid::nil())),
+            (None, _) => Ceremony::Login(None),
+        };
+
+        let challenge =
+            create_challenge(ctx.data::<redis::Client>()?, &conf.webauthn, ceremony).await?;
+
+        Ok(Json(json!({
+            "publicKey": {
+                "rpId": conf.webauthn.rp_id,
+                "challenge": challenge,
+                "timeout": conf.webauthn.challenge_timeout * 1000,
+                "allowCredentials": allowed,
+                "userVerification": if username.is_some() { "preferred" } else { "required" },
+            }
+        })))
+    }
+
+    /// Create a refresh token with a passkey alone. The authenticator has to verify the user,
+    /// with a PIN or biometrics. Rate limited like logging in with a password.
+    async fn finish_passkey_login(
+        &self,
+        ctx: &Context<'_>,
+        assertion: PasskeyAssertion,
+        #[graphql(default)] options: TokenOptions,
+    ) -> Result<RefreshToken> {
+        ctx.data::<RateLimiter>()?
+            .limit(
+                policy::LOGIN,
+                Identifier::Address(ctx.data::<IpAddr>()?.clone()),
+            )
+            .await
+            .map_err(RateLimited::gql_error)?;
+
+        let pool = ctx.data::<PgPool>()?;
+
+        let passkey = Passkey::authenticate(
+            pool,
+            ctx.data::<redis::Client>()?,
+            &ctx.data::<Config>()?.webauthn,
+            &assertion,
+            None,
+            true,
+        )
+        .await?;
+
+        Ok(options
+            .issue(ctx, &User::from_id(pool, passkey.user_id).await?)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        access::webauthn::{base64url, soft::SoftAuthenticator},
+        db::SqlxConn,
+        Config, CONF_FILE,
+    };
+
+    /// Register a passkey with a software authenticator and log in with it, without a password.
+    #[tokio::test]
+    async fn register_and_login() {
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+        let mut authenticator = SoftAuthenticator::new(&conf.webauthn.rp_id, &conf.webauthn.origin);
+
+        // Start without passkeys from earlier runs
+        sqlx::query!("DELETE FROM users WHERE username = 'passkey_user'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        gql_test!(
+            r#"mutation {
+                createUser(newUser: { username: "passkey_user", password: "password_of_20_characters" }) {
+                  id
+                }
+              }
+              "#
+        );
+
+        let jwt = gql_test_login!("passkey_user", "password_of_20_characters");
+
+        // The password is checked again
+        assert!(gql_test!(
+            r#"mutation { startPasskeyRegistration(password: "wrong_password_of_20_chars") }"#,
+            Some(&jwt)
+        )
+        .is_err());
+
+        let started = gql_test!(
+            r#"mutation { startPasskeyRegistration(password: "password_of_20_characters") }"#,
+            Some(&jwt)
+        );
+        let challenge = started.data.into_json().unwrap()["startPasskeyRegistration"]["publicKey"]
+            ["challenge"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let (client_data, attestation) = authenticator.register(&challenge);
+
+        let finished = gql_test!(
+            format!(
+                r#"mutation {{
+                    finishPasskeyRegistration(attestation: {{ clientDataJson: "{}", attestationObject: "{}" }}) {{
+                      passkey {{ id }}
+                      recoveryCodes
+                    }}
+                  }}
+                  "#,
+                base64url(&client_data),
+                base64url(&attestation)
+            ),
+            Some(&jwt)
+        );
+        let finished = finished.data.into_json().unwrap()["finishPasskeyRegistration"].clone();
+        let passkey_id = finished["passkey"]["id"].as_str().unwrap().to_string();
+
+        // The first passkey enables two-factor authentication
+        let recovery_codes = finished["recoveryCodes"].as_array().unwrap();
+        assert_eq!(recovery_codes.len(), 10);
+
+        // The password alone is not enough anymore
+        assert!(gql_test!(
+            r#"mutation {
+                createRefreshToken(newToken: { username: "passkey_user", password: "password_of_20_characters" }) {
+                  tokenString
+                }
+              }
+              "#
+        )
+        .is_err());
+
+        let started = gql_test!(r#"mutation { startPasskeyLogin }"#);
+        let challenge = started.data.into_json().unwrap()["startPasskeyLogin"]["publicKey"]
+            ["challenge"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let (client_data, auth_data, signature) = authenticator.assert(&challenge);
+        let login = format!(
+            r#"mutation {{
+                finishPasskeyLogin(assertion: {{ credentialId: "{}", clientDataJson: "{}", authenticatorData: "{}", signature: "{}" }}) {{
+                  tokenString
+                }}
+              }}
+              "#,
+            base64url(&authenticator.credential_id()),
+            base64url(&client_data),
+            base64url(&auth_data),
+            base64url(&signature)
+        );
+
+        assert!(gql_test!(login.clone()).is_ok());
+
+        // The challenge was consumed
+        assert!(gql_test!(login).is_err());
+
+        let delete = |otp: &str| {
+            format!(
+                r#"mutation {{
+                    deletePasskey(id: "{}", password: "password_of_20_characters"{})
+                  }}
+                  "#,
+                passkey_id, otp
+            )
+        };
+
+        // The second factor is needed too
+        assert!(gql_test!(delete(""), Some(&jwt)).is_err());
+
+        assert_eq!(
+            gql_test!(
+                delete(&format!(
+                    r#", otp: "{}""#,
+                    recovery_codes[0].as_str().unwrap()
+                )),
+                Some(&jwt)
+            )
+            .data
+            .into_json()
+            .unwrap()["deletePasskey"],
+            true
+        );
+    }
+
+    /// Unknown usernames get a credential too, the same one every time.
+    #[tokio::test]
+    async fn decoy_credentials() {
+        let allowed = |res: async_graphql::Response| {
+            res.data.into_json().unwrap()["startPasskeyLogin"]["publicKey"]["allowCredentials"]
+                .clone()
+        };
+
+        let query = r#"mutation { startPasskeyLogin(username: "nobody_with_passkeys") }"#;
+        let first = allowed(gql_test!(query));
+
+        assert_eq!(first.as_array().unwrap().len(), 1);
+        assert_eq!(first, allowed(gql_test!(query)));
+        assert_ne!(
+            first,
+            allowed(gql_test!(
+                r#"mutation { startPasskeyLogin(username: "somebody_else") }"#
+            ))
+        );
+    }
+}
@@ -1,21 +1,33 @@
 mod mutation;
 mod query;
 
-pub use mutation::RefreshTokenMutation;
+pub use mutation::{RefreshTokenMutation, TokenOptions};
 pub use query::RefreshTokenQuery;
 
+use crate::{
+    access::{jwt::Denylist, secret},
+    gql::{E, R},
+    models::security_event::{SecurityEvent, SecurityEventKind},
+};
 use async_graphql::*;
 use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres};
 use uuid::Uuid;
 
+/// Length of generated token strings.
+const TOKEN_LEN: usize = 100;
+
+/// Length of the public prefix tokens are looked up by.
+const PREFIX_LEN: usize = 12;
+
 /// A refresh token is used to generate new JWTs.
 #[derive(SimpleObject)]
 pub struct RefreshToken {
     /// Identifier used to identify a refresh token without exposing the token string.
     pub id: Uuid,
     /// Identifies the token when generating new JWTs.
-    /// Might be hidden in some queries.
-    pub token_string: String,
+    /// Tokens are stored hashed, so this is only returned when the token is created.
+    pub token_string: Option<String>,
     pub created: DateTime<Utc>,
     pub modified: DateTime<Utc>,
     pub expires: DateTime<Utc>,
@@ -24,11 +36,324 @@ pub struct RefreshToken {
     pub client_address: String,
     /// Maximum valid lifetime for signed JWTs.
     pub max_jwt_lifetime: i32,
+    /// Tokens replaced by rotation share the family of the original token.
+    pub family_id: Uuid,
+    /// Every use replaces the token with a new one in the same family.
+    pub rotate: bool,
+    /// When the token was replaced by rotation. Using a retired token revokes the family.
+    pub retired: Option<DateTime<Utc>>,
+    /// The beginning of the token string, used to find the token. Not a secret.
+    pub token_prefix: String,
+    /// Keyed hash of the full token string.
+    #[graphql(skip)]
+    pub token_hash: Option<String>,
+}
+
+/// Everything except the token string needed to create a token.
+pub struct RefreshTokenParams<'a> {
+    pub expires: DateTime<Utc>,
+    pub user_id: Uuid,
+    pub client_address: &'a str,
+    pub max_jwt_lifetime: i32,
+    pub family_id: Uuid,
+    pub rotate: bool,
 }
 
 impl RefreshToken {
     pub fn is_valid(&self) -> bool {
-        self.expires > Utc::now()
+        self.expires > Utc::now() && self.retired.is_none()
+    }
+
+    /// Create a token with a new random string, storing only it's hash.
+    /// The returned token is the only one with `token_string` set.
+    pub async fn insert<'c, X>(
+        executor: X,
+        hash_key: &[u8],
+        params: RefreshTokenParams<'_>,
+    ) -> R<RefreshToken>
+    where
+        X: sqlx::Executor<'c, Database = Postgres>,
+    {
+        let token_string = secret::random_string(TOKEN_LEN);
+
+        let mut token = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            INSERT INTO refresh_tokens
+            (token_prefix, token_hash, expires, user_id, client_address, max_jwt_lifetime, family_id, rotate)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *;
+            "#,
+            &token_string[..PREFIX_LEN],
+            secret::hash(hash_key, &token_string)?,
+            params.expires,
+            params.user_id,
+            params.client_address,
+            params.max_jwt_lifetime,
+            params.family_id,
+            params.rotate
+        )
+        .fetch_one(executor)
+        .await?;
+
+        token.token_string = Some(token_string);
+
+        Ok(token)
+    }
+
+    /// Find a non expired token by it's string, including tokens retired by rotation.
+    /// Candidates are found by the prefix and the hash is compared in constant time.
+    pub async fn from_token_string(
+        pool: &PgPool,
+        hash_key: &[u8],
+        token_string: &str,
+    ) -> R<RefreshToken> {
+        let prefix = match token_string.get(..PREFIX_LEN) {
+            Some(prefix) => prefix,
+            None => return Err(E::NotFound),
+        };
+
+        let candidates = sqlx::query_as!(
+            RefreshToken,
+            "SELECT * FROM refresh_tokens WHERE expires > NOW() AND token_prefix = $1;",
+            prefix
+        )
+        .fetch_all(pool)
+        .await?;
+
+        for token in candidates {
+            if let Some(hash) = &token.token_hash {
+                if secret::verify(hash_key, token_string, hash)? {
+                    return Ok(token);
+                }
+            }
+        }
+
+        Err(E::NotFound)
+    }
+
+    /// Find one of the user's tokens by it's id.
+    pub async fn from_id(pool: &PgPool, id: Uuid, user_id: Uuid) -> R<RefreshToken> {
+        Ok(sqlx::query_as!(
+            RefreshToken,
+            "SELECT * FROM refresh_tokens WHERE id = $1 AND user_id = $2;",
+            id,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+
+    /// All of the user's tokens, oldest first. If valid is `true`, returns only usable tokens.
+    pub async fn for_user(pool: &PgPool, user_id: Uuid, valid: bool) -> R<Vec<RefreshToken>> {
+        Ok(sqlx::query_as!(
+            RefreshToken,
+            r#"
+            SELECT * FROM refresh_tokens
+            WHERE (NOT $1 OR (expires > NOW() AND retired IS NULL))
+                AND user_id = $2 ORDER BY created;
+            "#,
+            valid,
+            user_id
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Hash tokens stored in plaintext before hashing was introduced, and clear the plaintext.
+    /// Run on startup after the migrations.
+    pub async fn hash_legacy_tokens(pool: &PgPool, hash_key: &[u8]) -> anyhow::Result<()> {
+        let legacy = sqlx::query!(
+            r#"
+            SELECT id, token_string FROM refresh_tokens
+            WHERE token_hash IS NULL AND token_string IS NOT NULL;
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        if !legacy.is_empty() {
+            info!("Hashing {} plaintext refresh tokens", legacy.len());
+        }
+
+        for row in legacy {
+            if let Some(token_string) = row.token_string {
+                sqlx::query!(
+                    r#"
+                    UPDATE refresh_tokens SET token_hash = $1, token_string = NULL
+                    WHERE id = $2;
+                    "#,
+                    secret::hash(hash_key, &token_string)?,
+                    row.id
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find a non expired token to sign a JWT of the given lifetime with.
+    /// Rotating tokens are replaced by a new token in the same family, which is returned instead.
+    /// If rotation is not allowed, rotating tokens are rejected before they are retired.
+    /// Presenting a retired token revokes the whole family and records a security event.
+    pub async fn redeem(
+        pool: &PgPool,
+        denylist: &Denylist,
+        hash_key: &[u8],
+        token_string: &str,
+        client_address: &str,
+        jwt_lifetime: i64,
+        allow_rotation: bool,
+    ) -> R<RefreshToken> {
+        let token = Self::from_token_string(pool, hash_key, token_string).await?;
+
+        if token.retired.is_some() {
+            return Err(token.reused(pool, denylist, client_address).await);
+        }
+
+        // Check that the lifetime of an allowed length before the token is rotated
+        if jwt_lifetime > token.max_jwt_lifetime as i64 {
+            return Err(E::Message(format!(
+                "Requested JWT lifetime exceeds the limit of {}.",
+                token.max_jwt_lifetime,
+            )));
+        }
+
+        if !token.rotate {
+            return Ok(token);
+        }
+
+        if !allow_rotation {
+            return Err(E::Message(
+                "Rotating refresh tokens can only be used with refreshJwt.".into(),
+            ));
+        }
+
+        let mut tx = pool.begin().await?;
+
+        // Only one concurrent use can retire the token, the others count as reuse
+        let retired = sqlx::query!(
+            r#"
+            UPDATE refresh_tokens SET retired = NOW(), modified = NOW()
+            WHERE id = $1 AND retired IS NULL RETURNING id;
+            "#,
+            token.id
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        if retired.is_none() {
+            tx.rollback().await?;
+
+            return Err(token.reused(pool, denylist, client_address).await);
+        }
+
+        let rotated = Self::insert(
+            &mut tx,
+            hash_key,
+            RefreshTokenParams {
+                expires: token.expires,
+                user_id: token.user_id,
+                client_address,
+                max_jwt_lifetime: token.max_jwt_lifetime,
+                family_id: token.family_id,
+                rotate: true,
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(rotated)
+    }
+
+    /// Handle the reuse of a retired token. Returns the error to respond with.
+    async fn reused(&self, pool: &PgPool, denylist: &Denylist, client_address: &str) -> E {
+        if let Err(error) = Self::revoke_family(pool, denylist, self.family_id).await {
+            error!(
+                "Failed to revoke refresh token family {}: {}",
+                self.family_id, error
+            );
+        }
+
+        if let Err(error) = SecurityEvent::record(
+            pool,
+            SecurityEventKind::RefreshTokenReuse,
+            Some(self.user_id),
+            Some(client_address.into()),
+            format!(
+                "Retired refresh token {} of family {} was used.",
+                self.id, self.family_id
+            ),
+        )
+        .await
+        {
+            error!("Failed to record a security event: {}", error);
+        }
+
+        E::Message(
+            "The refresh token has already been used, every token of it's session was revoked."
+                .into(),
+        )
+    }
+
+    /// Revoke every session of the user, except the family given.
+    /// Returns the amount of sessions revoked.
+    pub async fn revoke_user_sessions(
+        pool: &PgPool,
+        denylist: &Denylist,
+        user_id: Uuid,
+        except_family: Option<Uuid>,
+    ) -> R<u64> {
+        let tokens = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            UPDATE refresh_tokens SET expires = NOW(), modified = NOW()
+            WHERE user_id = $1 AND expires > NOW()
+                AND ($2::uuid IS NULL OR family_id <> $2) RETURNING *;
+            "#,
+            user_id,
+            except_family
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut families = vec![];
+
+        for token in tokens {
+            denylist
+                .revoke_parent(&token.id, token.max_jwt_lifetime as i64)
+                .await?;
+
+            if !families.contains(&token.family_id) {
+                families.push(token.family_id);
+            }
+        }
+
+        Ok(families.len() as u64)
+    }
+
+    /// Expire every token in the family and revoke the JWTs signed with them.
+    pub async fn revoke_family(pool: &PgPool, denylist: &Denylist, family_id: Uuid) -> R<()> {
+        let tokens = sqlx::query_as!(
+            RefreshToken,
+            r#"
+            UPDATE refresh_tokens SET expires = NOW(), modified = NOW()
+            WHERE family_id = $1 AND expires > NOW() RETURNING *;
+            "#,
+            family_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        for token in tokens {
+            denylist
+                .revoke_parent(&token.id, token.max_jwt_lifetime as i64)
+                .await?;
+        }
+
+        Ok(())
     }
 }
 
@@ -40,13 +365,18 @@ mod tests {
     fn test_token() -> RefreshToken {
         RefreshToken {
             id: Uuid::new_v4(),
-            token_string: "token_string".into(),
+            token_string: None,
             created: Utc::now(),
             modified: Utc::now(),
             expires: Utc::now(),
             user_id: Uuid::new_v4(),
             client_address: String::new(),
             max_jwt_lifetime: 60,
+            family_id: Uuid::new_v4(),
+            rotate: false,
+            retired: None,
+            token_prefix: "token_prefix".into(),
+            token_hash: None,
         }
     }
 
@@ -69,4 +399,14 @@ mod tests {
 
         assert!(token.is_valid())
     }
+
+    #[test]
+    fn refresh_token_retired() {
+        let mut token = test_token();
+
+        token.expires = token.expires + Duration::hours(1);
+        token.retired = Some(Utc::now());
+
+        assert!(!token.is_valid())
+    }
 }
@@ -1,15 +1,31 @@
-use super::RefreshToken;
+use super::{RefreshToken, RefreshTokenParams};
 
-use crate::{gql::E, models::user::User};
+use crate::{
+    access::{
+        authenticated,
+        guard::{Guard, Permission},
+        policy, Credentials, Identifier, Lockout, RateLimiter, JWT,
+    },
+    gql::{E, R},
+    mail::MailQueue,
+    models::{
+        one_time_token::{OneTimeToken, OneTimeTokenKind},
+        passkey::PasskeyAssertion,
+        role::permission,
+        two_factor::TwoFactor,
+        user::User,
+    },
+    Config,
+};
 
 use async_graphql::*;
 use chrono::{Duration, Utc};
-use rand::{distributions::Alphanumeric, Rng};
 use std::net::IpAddr;
+use uuid::Uuid;
 use validator::Validate;
 
 /// User credentials and lifetime for a new refresh token.
-#[derive(Validate, InputObject, Clone)]
+#[derive(Validate, InputObject)]
 struct NewRefreshToken {
     username: String,
     password: String,
@@ -24,6 +40,88 @@ struct NewRefreshToken {
     #[graphql(default = 300)]
     #[validate(range(min = 10, max = 3600))]
     max_jwt_lifetime: i32,
+    /// Replace the token on every use with `refreshJwt`.
+    /// Reusing a replaced token revokes every token of the session.
+    #[graphql(default = false)]
+    rotate: bool,
+    /// A TOTP or recovery code, required if two-factor authentication is enabled.
+    otp: Option<String>,
+    /// A passkey response for a challenge from `startPasskeyLogin`, instead of `otp`.
+    passkey: Option<PasskeyAssertion>,
+}
+
+/// Lifetime of a refresh token created by a login without a password.
+/// The same limits as with `createRefreshToken` apply.
+#[derive(Validate, InputObject)]
+pub struct TokenOptions {
+    /// Defaults to 1 week.
+    #[graphql(default = 604800)]
+    #[validate(range(min = 60, max = 2629800))]
+    expires_in_seconds: i32,
+    #[graphql(default = 300)]
+    #[validate(range(min = 10, max = 3600))]
+    max_jwt_lifetime: i32,
+    #[graphql(default = false)]
+    rotate: bool,
+}
+
+impl Default for TokenOptions {
+    fn default() -> Self {
+        TokenOptions {
+            expires_in_seconds: 604800,
+            max_jwt_lifetime: 300,
+            rotate: false,
+        }
+    }
+}
+
+/// Create a refresh token for an user who has authenticated, in a new session.
+/// Users without a verified email address are rejected if `require_verified_email` is set.
+async fn issue(
+    ctx: &Context<'_>,
+    user: &User,
+    expires_in_seconds: i32,
+    max_jwt_lifetime: i32,
+    rotate: bool,
+) -> R<RefreshToken> {
+    let conf = ctx.data::<Config>()?;
+
+    if conf.require_verified_email && !user.email_verified {
+        return Err(E::Message(
+            "The email address has to be verified first.".into(),
+        ));
+    }
+
+    // The token string is only returned here
+    RefreshToken::insert(
+        ctx.data::<sqlx::PgPool>()?,
+        conf.tokens.hash_key.as_bytes(),
+        RefreshTokenParams {
+            expires: Utc::now() + Duration::seconds(expires_in_seconds as i64),
+            user_id: user.id,
+            client_address: &ctx.data::<IpAddr>()?.to_string(),
+            max_jwt_lifetime,
+            family_id: Uuid::new_v4(),
+            rotate,
+        },
+    )
+    .await
+}
+
+impl TokenOptions {
+    /// Validate the options and create a token with them.
+    pub async fn issue(&self, ctx: &Context<'_>, user: &User) -> R<RefreshToken> {
+        self.validate()?;
+
+        issue(
+            ctx,
+            user,
+            self.expires_in_seconds,
+            self.max_jwt_lifetime,
+            self.rotate,
+        )
+        .await
+    }
 }
 
 #[derive(Default)]
@@ -32,59 +130,326 @@ pub struct RefreshTokenMutation;
 #[Object]
 impl RefreshTokenMutation {
     // Create a new refresh token with the user's credentials.
+    // Users with two-factor authentication need a TOTP code, a recovery code or a passkey.
+    // Users without a verified email address are rejected if `require_verified_email` is set.
+    // Rate limited by the `login` policy for every address, and failures in a row for the username
+    // lead to a temporary lockout.
     async fn create_refresh_token(
         &self,
         ctx: &Context<'_>,
         new_token: NewRefreshToken,
-    ) -> std::result::Result<RefreshToken, E> {
+    ) -> Result<RefreshToken> {
         new_token.validate()?;
 
-        // Find the user by the username
-        let user = User::from_credentials(
-            ctx.data::<sqlx::PgPool>()?,
-            new_token.username,
-            new_token.password,
+        #[cfg(not(test))]
+        {
+            use crate::access::RateLimited;
+
+            ctx.data::<RateLimiter>()?
+                .limit(
+                    policy::LOGIN,
+                    Identifier::Address(ctx.data::<IpAddr>()?.clone()),
+                )
+                .await
+                .map_err(RateLimited::gql_error)?;
+        }
+
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        // Both a wrong password and a wrong second factor count towards the lockout
+        let user = Lockout::from_context(ctx)?
+            .attempt(
+                pool,
+                &new_token.username,
+                Some(ctx.data::<IpAddr>()?.to_string()),
+                async {
+                    let user = match User::from_credentials(
+                        pool,
+                        &ctx.data::<Config>()?.password,
+                        new_token.username.clone(),
+                        new_token.password.clone(),
+                    )
+                    .await?
+                    {
+                        Some(user) => user,
+                        None => return Ok(Credentials::Invalid(E::InvalidCredentials)),
+                    };
+
+                    let verified = TwoFactor::verify_second_factor(
+                        ctx,
+                        &user,
+                        new_token.otp.as_deref(),
+                        new_token.passkey.as_ref(),
+                    )
+                    .await?;
+
+                    Ok(if verified {
+                        Credentials::Valid(user)
+                    } else {
+                        Credentials::Invalid(E::Message("Invalid two-factor code.".into()))
+                    })
+                },
+            )
+            .await?;
+
+        Ok(issue(
+            ctx,
+            &user,
+            new_token.expires_in_seconds,
+            new_token.max_jwt_lifetime,
+            new_token.rotate,
+        )
+        .await?)
+    }
+
+    /// Mail a single-use login link to the email address, if an user has it.
+    /// The response is the same whether the address is found or not.
+    /// Rate limited by the `magic_link` policy for every address, and for every user.
+    async fn request_magic_link(&self, ctx: &Context<'_>, email: String) -> Result<bool> {
+        #[cfg(not(test))]
+        {
+            use crate::access::RateLimited;
+
+            ctx.data::<RateLimiter>()?
+                .limit(
+                    policy::MAGIC_LINK,
+                    Identifier::Address(ctx.data::<IpAddr>()?.clone()),
+                )
+                .await
+                .map_err(RateLimited::gql_error)?;
+        }
+
+        let pool = ctx.data::<sqlx::PgPool>()?.clone();
+        let conf = ctx.data::<Config>()?;
+        let tokens = conf.tokens.clone();
+        let template = conf.mail.templates.magic_link.clone();
+        let mail_queue = ctx.data::<MailQueue>()?.clone();
+        let rate_limiter = ctx.data::<RateLimiter>()?.clone();
+
+        // In the background, so the response time doesn't reveal whether the address exists
+        tokio::spawn(async move {
+            let sent = async {
+                let user = match User::from_email(&pool, &email).await {
+                    Ok(user) => user,
+                    Err(_) => return R::Ok(()),
+                };
+
+                // Requests from many addresses could still flood the inbox.
+                // Silently dropped, a recorded denial would reveal the address exists.
+                if let Some(limiter) =
+                    rate_limiter.limiter(policy::MAGIC_LINK, Identifier::User(user.id))?
+                {
+                    if !matches!(rate_limiter.run(&limiter).await, Ok(state) if state.allowed()) {
+                        return Ok(());
+                    }
+                }
+
+                let token = OneTimeToken::create(
+                    &pool,
+                    tokens.hash_key.as_bytes(),
+                    OneTimeTokenKind::MagicLink,
+                    user.id,
+                    Duration::seconds(tokens.magic_link_lifetime),
+                    Some(&email),
+                )
+                .await?;
+
+                mail_queue.send(
+                    template.render(&email, &[("username", &user.username), ("token", &token)]),
+                )?;
+
+                Ok(())
+            };
+
+            if let Err(error) = sent.await {
+                error!("Magic link for {} failed: {}", email, error);
+            }
+        });
+
+        Ok(true)
+    }
+
+    /// Create a refresh token with a token from `requestMagicLink`.
+    /// Users with two-factor authentication still need a TOTP or recovery code, or a passkey
+    /// response for a challenge from `startPasskeyLogin`. A wrong one counts towards the lockout
+    /// of the username, and doesn't use up the link.
+    async fn redeem_magic_link(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        otp: Option<String>,
+        passkey: Option<PasskeyAssertion>,
+        #[graphql(default)] options: TokenOptions,
+    ) -> std::result::Result<RefreshToken, E> {
+        options.validate()?;
+
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let token = OneTimeToken::find(
+            pool,
+            ctx.data::<Config>()?.tokens.hash_key.as_bytes(),
+            OneTimeTokenKind::MagicLink,
+            &token,
         )
         .await?;
 
-        let token_string: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(100)
-            .map(char::from)
-            .collect();
-
-        // Create a new refresh token
-        Ok(sqlx::query_as!(
-            RefreshToken,
-            r#"
-            INSERT INTO refresh_tokens
-            (token_string, expires, user_id, client_address, max_jwt_lifetime)
-            VALUES ($1, $2, $3, $4, $5) RETURNING *;
-            "#,
-            token_string,
-            Utc::now() + Duration::seconds(new_token.expires_in_seconds as i64),
-            user.id,
-            ctx.data::<IpAddr>()?.to_string(),
-            new_token.max_jwt_lifetime
+        let user = User::from_id(pool, token.user_id).await?;
+
+        // The address might have been changed after the link was sent
+        if user.email != token.payload {
+            return Err(E::ItemNotFound("Token".into()));
+        }
+
+        Lockout::from_context(ctx)?
+            .attempt(
+                pool,
+                &user.username,
+                Some(ctx.data::<IpAddr>()?.to_string()),
+                async {
+                    let verified = TwoFactor::verify_second_factor(
+                        ctx,
+                        &user,
+                        otp.as_deref(),
+                        passkey.as_ref(),
+                    )
+                    .await?;
+
+                    Ok(if verified {
+                        Credentials::Valid(())
+                    } else {
+                        Credentials::Invalid(E::Message("Invalid two-factor code.".into()))
+                    })
+                },
+            )
+            .await?;
+
+        token.use_once(pool).await?;
+
+        options.issue(ctx, &user).await
+    }
+
+    /// Expire the refresh token and revoke every JWT signed with it.
+    /// For rotating tokens the tokens it was replaced with are revoked too.
+    async fn revoke_refresh_token(
+        &self,
+        ctx: &Context<'_>,
+        refresh_token_string: String,
+    ) -> std::result::Result<bool, E> {
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let token = RefreshToken::from_token_string(
+            pool,
+            ctx.data::<Config>()?.tokens.hash_key.as_bytes(),
+            &refresh_token_string,
         )
-        .fetch_one(ctx.data::<sqlx::PgPool>()?)
-        .await?)
+        .await?;
+
+        RefreshToken::revoke_family(pool, ctx.data::<JWT>()?.denylist()?, token.family_id).await?;
+
+        Ok(true)
+    }
+
+    /// Revoke one of the authenticated user's sessions by the refresh token's id.
+    async fn revoke_session(&self, ctx: &Context<'_>, id: Uuid) -> std::result::Result<bool, E> {
+        let claims = authenticated(ctx)?;
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let token = RefreshToken::from_id(pool, id, claims.sub).await?;
+
+        RefreshToken::revoke_family(pool, ctx.data::<JWT>()?.denylist()?, token.family_id).await?;
+
+        Ok(true)
+    }
+
+    /// Revoke every session of the authenticated user, except the one the JWT was signed with.
+    /// Returns the amount of revoked sessions.
+    async fn revoke_all_other_sessions(&self, ctx: &Context<'_>) -> std::result::Result<u64, E> {
+        let claims = authenticated(ctx)?;
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let current = RefreshToken::from_id(pool, claims.parent_token, claims.sub).await?;
+
+        RefreshToken::revoke_user_sessions(
+            pool,
+            ctx.data::<JWT>()?.denylist()?,
+            claims.sub,
+            Some(current.family_id),
+        )
+        .await
+    }
+
+    /// Revoke every session of another user. Requires the `sessions.revoke` permission.
+    /// Returns the amount of revoked sessions.
+    #[graphql(guard(Permission(name = "permission::REVOKE_SESSIONS")))]
+    async fn revoke_user_sessions(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Uuid,
+    ) -> std::result::Result<u64, E> {
+        RefreshToken::revoke_user_sessions(
+            ctx.data::<sqlx::PgPool>()?,
+            ctx.data::<JWT>()?.denylist()?,
+            user_id,
+            None,
+        )
+        .await
+    }
+
+    /// End the session the JWT was signed with. The JWT itself is revoked too.
+    async fn logout(&self, ctx: &Context<'_>) -> std::result::Result<bool, E> {
+        let claims = authenticated(ctx)?;
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let current = RefreshToken::from_id(pool, claims.parent_token, claims.sub).await?;
+
+        RefreshToken::revoke_family(pool, ctx.data::<JWT>()?.denylist()?, current.family_id)
+            .await?;
+
+        Ok(true)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::{config::FailurePolicy, macros::unique_name, Config, CONF_FILE};
     /// Create a new refresh token.
     #[tokio::test]
     async fn create() {
-        gql_test_user!();
+        let username = unique_name("token_user");
+        gql_test_user!(&username);
+
+        assert!(gql_test!(format!(
+            r#"mutation {{
+                createRefreshToken(newToken: {{ username: "{}", password: "password_of_20_characters" }}
+                  ) {{ id }}
+              }}
+              "#,
+            username
+        ))
+        .is_ok());
+    }
+
+    /// Logging in works while Redis is down, with the lockout counted in memory.
+    #[tokio::test]
+    async fn create_without_redis() {
+        let username = unique_name("token_user");
+        gql_test_user!(&username);
+
+        let mut conf = Config::from_file(CONF_FILE);
+        conf.rd.url = "redis://127.0.0.1:1".into();
+        conf.rate_limits.on_failure = FailurePolicy::Local;
 
         assert!(gql_test!(
-            r#"mutation {
-                createRefreshToken(newToken: { username: "test_user", password: "password_of_20_characters" }
-                  ) { id }
-              }
-              "#
+            format!(
+                r#"mutation {{
+                createRefreshToken(newToken: {{ username: "{}", password: "password_of_20_characters" }}
+                  ) {{ id }}
+              }}
+              "#,
+                username
+            ),
+            None::<&str>,
+            conf
         )
         .is_ok());
     }
@@ -92,30 +457,224 @@ mod tests {
     /// Try to create a token with too long of a lifetime.
     #[tokio::test]
     async fn create_too_long_lifetime() {
-        gql_test_user!();
+        let username = unique_name("token_user");
+        gql_test_user!(&username);
 
-        assert!(gql_test!(
-            r#"mutation {
-                createRefreshToken(newToken: { username: "test_user", password: "password_of_20_characters", expiresInSeconds: 2629801 }
-                  ) { id }
-              }
-              "#
-        )
+        assert!(gql_test!(format!(
+            r#"mutation {{
+                createRefreshToken(newToken: {{ username: "{}", password: "password_of_20_characters", expiresInSeconds: 2629801 }}
+                  ) {{ id }}
+              }}
+              "#,
+            username
+        ))
         .is_err());
     }
 
     /// The wrong password should not work.
     #[tokio::test]
     async fn create_wrong_password() {
-        gql_test_user!();
+        let username = unique_name("token_user");
+        gql_test_user!(&username);
+
+        assert!(gql_test!(format!(
+            r#"mutation {{
+                createRefreshToken(newToken: {{ username: "{}", password: "wrong_password" }}
+                  ) {{ id }}
+              }}
+              "#,
+            username
+        ))
+        .is_err());
+    }
+
+    /// A revoked refresh token can't be used to sign new JWTs.
+    #[tokio::test]
+    async fn revoke() {
+        let username = unique_name("token_user");
+        gql_test_user!(&username);
+
+        let created = gql_test!(format!(
+            r#"mutation {{
+                createRefreshToken(newToken: {{ username: "{}", password: "password_of_20_characters" }}
+                  ) {{ tokenString }}
+              }}
+              "#,
+            username
+        ));
+        let token_string = created.data.into_json().unwrap()["createRefreshToken"]["tokenString"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        assert!(gql_test!(format!(
+            r#"mutation {{ revokeRefreshToken(refreshTokenString: "{}") }}"#,
+            token_string
+        ))
+        .is_ok());
+
+        assert!(gql_test!(format!(
+            r#"mutation {{ signJwt(refreshTokenString: "{}") }}"#,
+            token_string
+        ))
+        .is_err());
+    }
+
+    /// A rotating token is replaced on use, and reusing the old one revokes the new one.
+    #[tokio::test]
+    async fn rotation_reuse() {
+        let username = unique_name("token_user");
+        gql_test_user!(&username);
+
+        let created = gql_test!(format!(
+            r#"mutation {{
+                createRefreshToken(newToken: {{ username: "{}", password: "password_of_20_characters", rotate: true }}
+                  ) {{ tokenString }}
+              }}
+              "#,
+            username
+        ));
+        let first = created.data.into_json().unwrap()["createRefreshToken"]["tokenString"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let refreshed = gql_test!(format!(
+            r#"mutation {{ refreshJwt(refreshTokenString: "{}") {{ refreshToken {{ tokenString }} }} }}"#,
+            first
+        ));
+        let second = refreshed.data.into_json().unwrap()["refreshJwt"]["refreshToken"]
+            ["tokenString"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        assert_ne!(first, second);
+
+        // Reusing the first token revokes the family
+        assert!(gql_test!(format!(
+            r#"mutation {{ refreshJwt(refreshTokenString: "{}") {{ jwt }} }}"#,
+            first
+        ))
+        .is_err());
+
+        assert!(gql_test!(format!(
+            r#"mutation {{ refreshJwt(refreshTokenString: "{}") {{ jwt }} }}"#,
+            second
+        ))
+        .is_err());
+    }
+
+    /// After logging out, the JWT should not be accepted anymore.
+    #[tokio::test]
+    async fn logout() {
+        let jwt = gql_test_jwt!();
+
+        assert!(gql_test!(r#"mutation { logout }"#, Some(&jwt)).is_ok());
+
+        assert!(crate::macros::TEST_JWT
+            .decode(&jwt, crate::macros::TEST_JWT.audience())
+            .await
+            .is_err());
+    }
+
+    /// Revoking other users' sessions requires a permission.
+    #[tokio::test]
+    async fn revoke_user_sessions_forbidden() {
+        let jwt = gql_test_jwt!();
 
         assert!(gql_test!(
-            r#"mutation {
-                createRefreshToken(newToken: { username: "test_user", password: "wrong_password" }
-                  ) { id }
-              }
-              "#
+            r#"mutation { revokeUserSessions(userId: "00000000-0000-0000-0000-000000000000") }"#,
+            Some(&jwt)
         )
         .is_err());
     }
+
+    /// Unknown addresses get the same response.
+    #[tokio::test]
+    async fn request_magic_link_unknown_email() {
+        let res = gql_test!(r#"mutation { requestMagicLink(email: "nobody@email.com") }"#);
+
+        assert_eq!(res.data.into_json().unwrap()["requestMagicLink"], true);
+    }
+
+    /// A magic link logs in once, and only while the address is still the user's.
+    #[tokio::test]
+    async fn redeem_magic_link() {
+        use crate::{
+            access::totp,
+            db::SqlxConn,
+            models::{
+                one_time_token::{OneTimeToken, OneTimeTokenKind},
+                two_factor::{TotpSecret, TwoFactor},
+                user::User,
+            },
+            Config, CONF_FILE,
+        };
+
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+
+        let username = unique_name("magic_user");
+        let email = format!("{}@email.com", username);
+        gql_test_user!(&username);
+
+        let user = User::from_email(&pool, &email).await.unwrap();
+
+        let create_link = |email: String| {
+            let (pool, conf, user_id) = (&pool, &conf, user.id);
+
+            async move {
+                OneTimeToken::create(
+                    pool,
+                    conf.tokens.hash_key.as_bytes(),
+                    OneTimeTokenKind::MagicLink,
+                    user_id,
+                    chrono::Duration::minutes(5),
+                    Some(&email),
+                )
+                .await
+            }
+        };
+
+        let redeem = |token: String| {
+            format!(
+                r#"mutation {{ redeemMagicLink(token: "{}") {{ tokenString }} }}"#,
+                token
+            )
+        };
+
+        let query = redeem(create_link(email.clone()).await.unwrap());
+
+        assert!(gql_test!(query.clone()).is_ok());
+
+        // Single-use
+        assert!(gql_test!(query).is_err());
+
+        // Sent to an address the user doesn't have anymore
+        assert!(gql_test!(redeem(create_link(format!("old_{}", email)).await.unwrap())).is_err());
+
+        // With two-factor authentication a wrong code doesn't use up the link
+        let secret = TotpSecret::enroll(&pool, user.id).await.unwrap();
+        let recovery_codes = TwoFactor::confirm(
+            &pool,
+            conf.tokens.hash_key.as_bytes(),
+            user.id,
+            &totp::code(secret.secret(), totp::step(chrono::Utc::now().timestamp())).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let token = create_link(email.clone()).await.unwrap();
+        let with_otp = |otp: &str| {
+            format!(
+                r#"mutation {{ redeemMagicLink(token: "{}", otp: "{}") {{ tokenString }} }}"#,
+                token, otp
+            )
+        };
+
+        assert!(gql_test!(redeem(token.clone())).is_err());
+        assert!(gql_test!(with_otp("wrong_code")).is_err());
+        assert!(gql_test!(with_otp(&recovery_codes[0])).is_ok());
+    }
 }
@@ -1,30 +1,40 @@
 use super::RefreshToken;
 
-use crate::{gql::E, models::user::User};
+use crate::{access::Lockout, gql::E, models::user::User, Config};
 
 use async_graphql::*;
+use std::net::IpAddr;
 
 #[derive(Default)]
 pub struct RefreshTokenQuery;
 
 #[Object]
 impl RefreshTokenQuery {
-    /// Get a single non expired refresh token from a string.
+    /// Get a single usable refresh token from a string. Tokens replaced by rotation are not returned.
     async fn refresh_token_from_token_string(
         &self,
         ctx: &Context<'_>,
         token_string: String,
     ) -> std::result::Result<RefreshToken, E> {
-        Ok(sqlx::query_as!(
-            RefreshToken,
-            "SELECT * FROM refresh_tokens WHERE expires > NOW() AND token_string = $1;",
-            token_string
+        let token = RefreshToken::from_token_string(
+            ctx.data::<sqlx::PgPool>()?,
+            ctx.data::<Config>()?.tokens.hash_key.as_bytes(),
+            &token_string,
         )
-        .fetch_one(ctx.data::<sqlx::PgPool>()?)
-        .await?)
+        .await?;
+
+        if token.retired.is_some() {
+            return Err(E::NotFound);
+        }
+
+        Ok(token)
     }
 
     /// Get all user's refresh tokens. If valid is `true`, returns only usable tokens.
+    /// Authenticated clients should use `me { sessions }` instead of sending the password.
+    /// Failures count towards the login lockout of the username.
+    /// Costs 50 towards the complexity, for hashing the password.
+    #[graphql(complexity = 50)]
     async fn refresh_tokens(
         &self,
         ctx: &Context<'_>,
@@ -34,19 +44,24 @@ impl RefreshTokenQuery {
     ) -> std::result::Result<Vec<RefreshToken>, E> {
         let pool = ctx.data::<sqlx::PgPool>()?;
 
-        let user = User::from_credentials(pool, username, password).await?;
-
-        Ok(sqlx::query_as!(
-            RefreshToken,
-            r#"
-            SELECT * FROM refresh_tokens
-            WHERE (CASE WHEN $1 THEN expires > NOW() END)
-                AND user_id = $2 ORDER BY created;
-            "#,
-            valid,
-            user.id
-        )
-        .fetch_all(pool)
-        .await?)
+        let user = Lockout::from_context(ctx)?
+            .attempt(
+                pool,
+                &username,
+                Some(ctx.data::<IpAddr>()?.to_string()),
+                async {
+                    Ok(User::from_credentials(
+                        pool,
+                        &ctx.data::<Config>()?.password,
+                        username.clone(),
+                        password,
+                    )
+                    .await?
+                    .into())
+                },
+            )
+            .await?;
+
+        RefreshToken::for_user(pool, user.id, valid).await
     }
 }
@@ -0,0 +1,168 @@
+mod mutation;
+mod query;
+
+pub use mutation::RoleMutation;
+pub use query::RoleQuery;
+
+use crate::gql::{E, R};
+use async_graphql::*;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Permissions known to the application. Every one of them has a row in `permissions`.
+pub mod permission {
+    /// Create roles and assign them to users.
+    pub const MANAGE_ROLES: &str = "roles.manage";
+    /// Add users to groups and remove them from groups.
+    pub const MANAGE_GROUPS: &str = "groups.manage";
+    /// Revoke the sessions of other users.
+    pub const REVOKE_SESSIONS: &str = "sessions.revoke";
+    /// End the login lockout of an username.
+    pub const UNLOCK_USERS: &str = "users.unlock";
+    /// Create users with password hashes from other systems.
+    pub const IMPORT_USERS: &str = "users.import";
+}
+
+/// A named set of permissions that can be assigned to users.
+#[derive(SimpleObject, Debug, Clone)]
+#[graphql(complex)]
+pub struct Role {
+    pub id: Uuid,
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// A permission roles can grant.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct PermissionInfo {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// A role assigned to an user, until it expires.
+#[derive(SimpleObject, Debug, Clone)]
+#[graphql(complex)]
+pub struct RoleAssignment {
+    pub user_id: Uuid,
+    pub role_id: Uuid,
+    pub created: DateTime<Utc>,
+    /// The assignment is permanent if not set.
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl Role {
+    pub async fn from_id(pool: &PgPool, id: Uuid) -> R<Role> {
+        Ok(
+            sqlx::query_as!(Role, "SELECT * FROM roles WHERE id = $1", id)
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
+    /// Names of the permissions the role grants.
+    pub async fn permission_names(pool: &PgPool, role_id: Uuid) -> R<Vec<String>> {
+        Ok(sqlx::query!(
+            "SELECT permission FROM role_permissions WHERE role_id = $1 ORDER BY permission",
+            role_id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| row.permission)
+        .collect())
+    }
+
+    /// Replace the permissions the role grants.
+    /// Errors if any of the permissions is unknown.
+    pub async fn set_permissions(pool: &PgPool, role_id: Uuid, permissions: &[String]) -> R<()> {
+        let known: Vec<String> = sqlx::query!(
+            "SELECT name FROM permissions WHERE name = ANY($1)",
+            permissions
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| row.name)
+        .collect();
+
+        if let Some(unknown) = permissions.iter().find(|p| !known.contains(p)) {
+            return Err(E::Message(format!("Unknown permission '{}'.", unknown)));
+        }
+
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!("DELETE FROM role_permissions WHERE role_id = $1", role_id)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO role_permissions (role_id, permission) SELECT $1, UNNEST($2::varchar[])",
+            role_id,
+            permissions
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!("UPDATE roles SET modified = NOW() WHERE id = $1", role_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Every permission granted to the user by roles that have not expired.
+    pub async fn effective_permissions(pool: &PgPool, user_id: Uuid) -> R<Vec<String>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT DISTINCT rp.permission AS "permission!" FROM user_roles ur
+            INNER JOIN role_permissions rp ON rp.role_id = ur.role_id
+            WHERE ur.user_id = $1 AND (ur.expires IS NULL OR ur.expires > NOW())
+            ORDER BY 1
+            "#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| row.permission)
+        .collect())
+    }
+}
+
+#[ComplexObject]
+impl Role {
+    /// Names of the permissions the role grants.
+    async fn permissions(&self, ctx: &Context<'_>) -> std::result::Result<Vec<String>, E> {
+        Role::permission_names(ctx.data::<PgPool>()?, self.id).await
+    }
+}
+
+impl RoleAssignment {
+    /// Roles of the user, including expired assignments.
+    pub async fn for_user(pool: &PgPool, user_id: Uuid) -> R<Vec<RoleAssignment>> {
+        Ok(sqlx::query_as!(
+            RoleAssignment,
+            "SELECT * FROM user_roles WHERE user_id = $1 ORDER BY created",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+}
+
+#[ComplexObject]
+impl RoleAssignment {
+    async fn role(&self, ctx: &Context<'_>) -> std::result::Result<Role, E> {
+        Role::from_id(ctx.data::<PgPool>()?, self.role_id).await
+    }
+
+    /// `false` once the assignment has expired.
+    async fn is_active(&self) -> bool {
+        !matches!(self.expires, Some(expires) if expires <= Utc::now())
+    }
+}
@@ -0,0 +1,142 @@
+use super::{permission, Role, RoleAssignment};
+use crate::{
+    access::guard::{Guard, Permission},
+    gql::E,
+};
+use async_graphql::*;
+use chrono::{DateTime, Utc};
+use sqlx::{Done, PgPool};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A new role and the permissions it grants.
+#[derive(Validate, InputObject)]
+struct NewRole {
+    #[validate(length(min = 1, max = 50))]
+    name: String,
+    description: Option<String>,
+    #[graphql(default)]
+    permissions: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct RoleMutation;
+
+/// Every mutation requires the `roles.manage` permission.
+#[Object]
+impl RoleMutation {
+    /// Create a new role granting the permissions.
+    #[graphql(guard(Permission(name = "permission::MANAGE_ROLES")))]
+    async fn create_role(
+        &self,
+        ctx: &Context<'_>,
+        new_role: NewRole,
+    ) -> std::result::Result<Role, E> {
+        new_role.validate()?;
+
+        let pool = ctx.data::<PgPool>()?;
+
+        let role = sqlx::query_as!(
+            Role,
+            "INSERT INTO roles (name, description) VALUES ($1, $2) RETURNING *;",
+            new_role.name,
+            new_role.description
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Role::set_permissions(pool, role.id, &new_role.permissions).await?;
+
+        Ok(role)
+    }
+
+    /// Delete the role, removing it from every user it was assigned to.
+    #[graphql(guard(Permission(name = "permission::MANAGE_ROLES")))]
+    async fn delete_role(&self, ctx: &Context<'_>, role_id: Uuid) -> std::result::Result<bool, E> {
+        let deleted = sqlx::query!("DELETE FROM roles WHERE id = $1", role_id)
+            .execute(ctx.data::<PgPool>()?)
+            .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    /// Replace the permissions the role grants.
+    #[graphql(guard(Permission(name = "permission::MANAGE_ROLES")))]
+    async fn set_role_permissions(
+        &self,
+        ctx: &Context<'_>,
+        role_id: Uuid,
+        permissions: Vec<String>,
+    ) -> std::result::Result<Role, E> {
+        let pool = ctx.data::<PgPool>()?;
+
+        Role::set_permissions(pool, role_id, &permissions).await?;
+
+        Role::from_id(pool, role_id).await
+    }
+
+    /// Assign the role to an user, optionally until the expiry time.
+    /// Assigning the role again replaces the expiry time.
+    #[graphql(guard(Permission(name = "permission::MANAGE_ROLES")))]
+    async fn assign_role(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Uuid,
+        role_id: Uuid,
+        expires: Option<DateTime<Utc>>,
+    ) -> std::result::Result<RoleAssignment, E> {
+        Ok(sqlx::query_as!(
+            RoleAssignment,
+            r#"
+            INSERT INTO user_roles (user_id, role_id, expires) VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, role_id) DO UPDATE SET expires = EXCLUDED.expires
+            RETURNING *;
+            "#,
+            user_id,
+            role_id,
+            expires
+        )
+        .fetch_one(ctx.data::<PgPool>()?)
+        .await?)
+    }
+
+    /// Remove the role from the user. Returns `false` if it wasn't assigned.
+    #[graphql(guard(Permission(name = "permission::MANAGE_ROLES")))]
+    async fn unassign_role(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Uuid,
+        role_id: Uuid,
+    ) -> std::result::Result<bool, E> {
+        let deleted = sqlx::query!(
+            "DELETE FROM user_roles WHERE user_id = $1 AND role_id = $2",
+            user_id,
+            role_id
+        )
+        .execute(ctx.data::<PgPool>()?)
+        .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// Users without the permission can't create roles.
+    #[tokio::test]
+    async fn create_role_forbidden() {
+        let jwt = gql_test_jwt!();
+
+        let res = gql_test!(
+            r#"mutation {
+                createRole(newRole: { name: "moderator", permissions: ["sessions.revoke"] }) {
+                  id
+                }
+              }
+              "#,
+            Some(&jwt)
+        );
+
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use super::{permission, PermissionInfo, Role, RoleAssignment};
+use crate::{
+    access::{
+        current_permissions,
+        guard::{Guard, Permission, RequireAuth},
+    },
+    gql::E,
+};
+use async_graphql::*;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub struct RoleQuery;
+
+#[Object]
+impl RoleQuery {
+    /// Every role. Requires the `roles.manage` permission.
+    #[graphql(guard(Permission(name = "permission::MANAGE_ROLES")))]
+    async fn roles(&self, ctx: &Context<'_>) -> std::result::Result<Vec<Role>, E> {
+        Ok(sqlx::query_as!(Role, "SELECT * FROM roles ORDER BY name")
+            .fetch_all(ctx.data::<PgPool>()?)
+            .await?)
+    }
+
+    /// Every permission roles can grant.
+    #[graphql(guard(RequireAuth()))]
+    async fn permissions(&self, ctx: &Context<'_>) -> std::result::Result<Vec<PermissionInfo>, E> {
+        Ok(
+            sqlx::query_as!(PermissionInfo, "SELECT * FROM permissions ORDER BY name")
+                .fetch_all(ctx.data::<PgPool>()?)
+                .await?,
+        )
+    }
+
+    /// Roles assigned to the user. Requires the `roles.manage` permission.
+    #[graphql(guard(Permission(name = "permission::MANAGE_ROLES")))]
+    async fn user_roles(
+        &self,
+        ctx: &Context<'_>,
+        user_id: Uuid,
+    ) -> std::result::Result<Vec<RoleAssignment>, E> {
+        RoleAssignment::for_user(ctx.data::<PgPool>()?, user_id).await
+    }
+
+    /// Permissions of the authenticated user.
+    async fn my_permissions(&self, ctx: &Context<'_>) -> std::result::Result<Vec<String>, E> {
+        current_permissions(ctx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// A new user has no permissions.
+    #[tokio::test]
+    async fn my_permissions_empty() {
+        let jwt = gql_test_jwt!();
+
+        let res = gql_test!(r#"query { myPermissions }"#, Some(&jwt));
+
+        assert_eq!(
+            res.data.into_json().unwrap()["myPermissions"],
+            serde_json::json!([])
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::Result;
+use async_graphql::*;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Display;
+use uuid::Uuid;
+
+/// What happened. Stored as text, so new kinds don't need a migration.
+#[derive(Display, Clone, Copy, PartialEq, Debug)]
+pub enum SecurityEventKind {
+    /// A retired refresh token was presented again, and it's family was revoked.
+    RefreshTokenReuse,
+    PasswordChanged,
+    /// The password was changed with a reset token.
+    PasswordReset,
+    /// Recorded without the user, since their events are deleted with them.
+    AccountDeleted,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    /// A two-factor recovery code was used instead of a TOTP code.
+    RecoveryCodeUsed,
+    PasskeyRegistered,
+    PasskeyRemoved,
+    /// Too many failed logins in a row for the username.
+    AccountLocked,
+    /// An admin ended a lockout.
+    AccountUnlocked,
+}
+
+/// Security relevant events, kept for auditing.
+#[derive(SimpleObject, Debug)]
+pub struct SecurityEvent {
+    pub id: Uuid,
+    pub created: DateTime<Utc>,
+    pub user_id: Option<Uuid>,
+    pub kind: String,
+    pub client_address: Option<String>,
+    pub details: Option<String>,
+}
+
+impl SecurityEvent {
+    /// Record an event. Also logged as a warning.
+    pub async fn record<S: Into<String>>(
+        pool: &PgPool,
+        kind: SecurityEventKind,
+        user_id: Option<Uuid>,
+        client_address: Option<String>,
+        details: S,
+    ) -> Result<SecurityEvent> {
+        let details = details.into();
+
+        warn!(
+            "Security event {} for user {:?} from {:?}: {}",
+            kind, user_id, client_address, details
+        );
+
+        Ok(sqlx::query_as!(
+            SecurityEvent,
+            r#"
+            INSERT INTO security_events (kind, user_id, client_address, details)
+            VALUES ($1, $2, $3, $4) RETURNING *;
+            "#,
+            kind.to_string(),
+            user_id,
+            client_address,
+            details
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+}
@@ -0,0 +1,314 @@
+mod mutation;
+
+pub use mutation::TwoFactorMutation;
+
+use crate::{
+    access::{secret, totp},
+    gql::{E, R},
+    models::{
+        passkey::{Passkey, PasskeyAssertion},
+        security_event::{SecurityEvent, SecurityEventKind},
+        user::User,
+    },
+    Config,
+};
+use async_graphql::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Done, PgPool};
+use uuid::Uuid;
+
+/// Amount of recovery codes generated at once.
+const RECOVERY_CODE_COUNT: usize = 10;
+
+const RECOVERY_CODE_LEN: usize = 12;
+
+/// An user's TOTP secret. Two-factor authentication is enabled once it's confirmed.
+#[derive(Debug)]
+pub struct TotpSecret {
+    pub user_id: Uuid,
+    pub created: DateTime<Utc>,
+    secret: Vec<u8>,
+    pub confirmed: Option<DateTime<Utc>>,
+    pub last_step: Option<i64>,
+}
+
+impl TotpSecret {
+    pub async fn for_user(pool: &PgPool, user_id: Uuid) -> R<Option<TotpSecret>> {
+        Ok(sqlx::query_as!(
+            TotpSecret,
+            "SELECT * FROM totp_secrets WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?)
+    }
+
+    /// Generate a new unconfirmed secret, replacing an earlier unconfirmed one.
+    /// Errors if two-factor authentication is enabled already.
+    pub async fn enroll(pool: &PgPool, user_id: Uuid) -> R<TotpSecret> {
+        let secret = totp::generate_secret()?;
+
+        sqlx::query_as!(
+            TotpSecret,
+            r#"
+            INSERT INTO totp_secrets (user_id, secret) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, created = NOW()
+                WHERE totp_secrets.confirmed IS NULL
+            RETURNING *;
+            "#,
+            user_id,
+            secret
+        )
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| E::Message("Two-factor authentication is already enabled.".into()))
+    }
+
+    /// The secret for authenticator apps.
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Check the code, and accept it only if no later code has been used.
+    pub async fn use_code(&self, pool: &PgPool, code: &str) -> R<bool> {
+        let step = match totp::verify(&self.secret, code, Utc::now().timestamp())? {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+
+        let updated = sqlx::query!(
+            r#"
+            UPDATE totp_secrets SET last_step = $2
+            WHERE user_id = $1 AND (last_step IS NULL OR last_step < $2);
+            "#,
+            self.user_id,
+            step
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(updated.rows_affected() == 1)
+    }
+}
+
+/// Two-factor authentication of an user.
+pub struct TwoFactor;
+
+impl TwoFactor {
+    /// `true` if the user has a confirmed TOTP secret or a passkey.
+    pub async fn is_enabled(pool: &PgPool, user_id: Uuid) -> R<bool> {
+        if Passkey::exists_for_user(pool, user_id).await? {
+            return Ok(true);
+        }
+
+        Ok(matches!(
+            TotpSecret::for_user(pool, user_id).await?,
+            Some(totp) if totp.confirmed.is_some()
+        ))
+    }
+
+    /// Check a TOTP code or an unused recovery code.
+    /// Recovery codes are marked used.
+    pub async fn verify(pool: &PgPool, hash_key: &[u8], user_id: Uuid, code: &str) -> R<bool> {
+        let code = code.trim();
+
+        if let Some(totp) = TotpSecret::for_user(pool, user_id).await? {
+            if totp.confirmed.is_some() && totp.use_code(pool, code).await? {
+                return Ok(true);
+            }
+        }
+
+        Self::use_recovery_code(pool, hash_key, user_id, code).await
+    }
+
+    /// The second step for users with two-factor authentication, a no-op for others.
+    /// Either a TOTP or recovery code, or a passkey response is accepted.
+    pub async fn check_second_factor(
+        ctx: &Context<'_>,
+        user: &User,
+        otp: Option<&str>,
+        passkey: Option<&PasskeyAssertion>,
+    ) -> R<()> {
+        if !Self::verify_second_factor(ctx, user, otp, passkey).await? {
+            return Err(E::Message("Invalid two-factor code.".into()));
+        }
+
+        Ok(())
+    }
+
+    /// Like `check_second_factor`, but a wrong code is `false` instead of an error.
+    pub async fn verify_second_factor(
+        ctx: &Context<'_>,
+        user: &User,
+        otp: Option<&str>,
+        passkey: Option<&PasskeyAssertion>,
+    ) -> R<bool> {
+        let pool = ctx.data::<PgPool>()?;
+
+        if !Self::is_enabled(pool, user.id).await? {
+            return Ok(true);
+        }
+
+        let conf = ctx.data::<Config>()?;
+
+        match (otp, passkey) {
+            (Some(code), _) => {
+                Self::verify(pool, conf.tokens.hash_key.as_bytes(), user.id, code).await
+            }
+            (None, Some(assertion)) => {
+                Passkey::authenticate(
+                    pool,
+                    ctx.data::<redis::Client>()?,
+                    &conf.webauthn,
+                    assertion,
+                    Some(user.id),
+                    false,
+                )
+                .await?;
+
+                Ok(true)
+            }
+            (None, None) => Err(E::Message(
+                "A two-factor authentication code is required.".into(),
+            )),
+        }
+    }
+
+    /// Enable two-factor authentication after checking the first code.
+    /// Returns new recovery codes.
+    pub async fn confirm(
+        pool: &PgPool,
+        hash_key: &[u8],
+        user_id: Uuid,
+        code: &str,
+    ) -> R<Vec<String>> {
+        let totp = match TotpSecret::for_user(pool, user_id).await? {
+            Some(totp) if totp.confirmed.is_none() => totp,
+            Some(_) => {
+                return Err(E::Message(
+                    "Two-factor authentication is already enabled.".into(),
+                ))
+            }
+            None => return Err(E::Message("Start by enrolling a secret.".into())),
+        };
+
+        if !totp.use_code(pool, code.trim()).await? {
+            return Err(E::Message("Invalid two-factor code.".into()));
+        }
+
+        sqlx::query!(
+            "UPDATE totp_secrets SET confirmed = NOW() WHERE user_id = $1",
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+        SecurityEvent::record(
+            pool,
+            SecurityEventKind::TwoFactorEnabled,
+            Some(user_id),
+            None,
+            "TOTP",
+        )
+        .await?;
+
+        Self::regenerate_recovery_codes(pool, hash_key, user_id).await
+    }
+
+    /// Remove the secret and recovery codes.
+    pub async fn disable(pool: &PgPool, user_id: Uuid) -> R<()> {
+        sqlx::query!("DELETE FROM totp_secrets WHERE user_id = $1", user_id)
+            .execute(pool)
+            .await?;
+
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(pool)
+            .await?;
+
+        SecurityEvent::record(
+            pool,
+            SecurityEventKind::TwoFactorDisabled,
+            Some(user_id),
+            None,
+            "TOTP",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Replace every recovery code with new ones. The codes are only returned here.
+    pub async fn regenerate_recovery_codes(
+        pool: &PgPool,
+        hash_key: &[u8],
+        user_id: Uuid,
+    ) -> R<Vec<String>> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| secret::random_string(RECOVERY_CODE_LEN))
+            .collect();
+
+        let hashes = codes
+            .iter()
+            .map(|code| secret::hash(hash_key, code))
+            .collect::<anyhow::Result<Vec<String>>>()?;
+
+        let mut tx = pool.begin().await?;
+
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::text[])",
+            user_id,
+            &hashes
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(codes)
+    }
+
+    async fn use_recovery_code(
+        pool: &PgPool,
+        hash_key: &[u8],
+        user_id: Uuid,
+        code: &str,
+    ) -> R<bool> {
+        let candidates = sqlx::query!(
+            "SELECT id, code_hash FROM recovery_codes WHERE user_id = $1 AND used IS NULL",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        for candidate in candidates {
+            if secret::verify(hash_key, code, &candidate.code_hash)? {
+                // Concurrent requests can use the code only once
+                let updated = sqlx::query!(
+                    "UPDATE recovery_codes SET used = NOW() WHERE id = $1 AND used IS NULL",
+                    candidate.id
+                )
+                .execute(pool)
+                .await?;
+
+                if updated.rows_affected() == 1 {
+                    SecurityEvent::record(
+                        pool,
+                        SecurityEventKind::RecoveryCodeUsed,
+                        Some(user_id),
+                        None,
+                        "",
+                    )
+                    .await?;
+                }
+
+                return Ok(updated.rows_affected() == 1);
+            }
+        }
+
+        Ok(false)
+    }
+}
@@ -0,0 +1,202 @@
+use super::{TotpSecret, TwoFactor};
+use crate::{
+    access::{current_user, totp, Lockout},
+    gql::E,
+    Config,
+};
+use async_graphql::*;
+use sqlx::PgPool;
+
+/// A new TOTP secret to add to an authenticator app.
+#[derive(SimpleObject)]
+struct TotpEnrollment {
+    /// Base32 encoded, for entering by hand.
+    secret: String,
+    /// `otpauth://` URI to show as a QR code.
+    uri: String,
+}
+
+#[derive(Default)]
+pub struct TwoFactorMutation;
+
+/// Every mutation is for the authenticated user.
+#[Object]
+impl TwoFactorMutation {
+    /// Generate a TOTP secret. Two-factor authentication is enabled with `confirmTotp`.
+    async fn enroll_totp(
+        &self,
+        ctx: &Context<'_>,
+        password: String,
+    ) -> std::result::Result<TotpEnrollment, E> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let user = current_user(ctx).await?;
+        Lockout::from_context(ctx)?
+            .check_password(ctx, &user, password)
+            .await?;
+
+        let totp = TotpSecret::enroll(pool, user.id).await?;
+
+        Ok(TotpEnrollment {
+            secret: totp::base32(totp.secret()),
+            uri: totp::uri(
+                &ctx.data::<Config>()?.jwt.issuer,
+                &user.username,
+                totp.secret(),
+            ),
+        })
+    }
+
+    /// Enable two-factor authentication with the first code from the authenticator app.
+    /// Returns single-use recovery codes, which are not shown again.
+    async fn confirm_totp(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+    ) -> std::result::Result<Vec<String>, E> {
+        TwoFactor::confirm(
+            ctx.data::<PgPool>()?,
+            ctx.data::<Config>()?.tokens.hash_key.as_bytes(),
+            current_user(ctx).await?.id,
+            &code,
+        )
+        .await
+    }
+
+    /// Disable two-factor authentication. Requires the password and a TOTP or recovery code.
+    async fn disable_totp(
+        &self,
+        ctx: &Context<'_>,
+        password: String,
+        code: String,
+    ) -> std::result::Result<bool, E> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let user = current_user(ctx).await?;
+        Lockout::from_context(ctx)?
+            .check_password(ctx, &user, password)
+            .await?;
+
+        if !TwoFactor::verify(
+            pool,
+            ctx.data::<Config>()?.tokens.hash_key.as_bytes(),
+            user.id,
+            &code,
+        )
+        .await?
+        {
+            return Err(E::Message("Invalid two-factor code.".into()));
+        }
+
+        TwoFactor::disable(pool, user.id).await?;
+
+        Ok(true)
+    }
+
+    /// Replace the recovery codes. Requires a TOTP code.
+    async fn regenerate_recovery_codes(
+        &self,
+        ctx: &Context<'_>,
+        code: String,
+    ) -> std::result::Result<Vec<String>, E> {
+        let pool = ctx.data::<PgPool>()?;
+        let hash_key = ctx.data::<Config>()?.tokens.hash_key.as_bytes();
+
+        let user = current_user(ctx).await?;
+
+        let valid = match TotpSecret::for_user(pool, user.id).await? {
+            Some(totp) if totp.confirmed.is_some() => totp.use_code(pool, code.trim()).await?,
+            _ => {
+                return Err(E::Message(
+                    "Two-factor authentication is not enabled.".into(),
+                ))
+            }
+        };
+
+        if !valid {
+            return Err(E::Message("Invalid two-factor code.".into()));
+        }
+
+        TwoFactor::regenerate_recovery_codes(pool, hash_key, user.id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        access::totp,
+        db::SqlxConn,
+        models::{two_factor::TotpSecret, user::User},
+        Config, CONF_FILE,
+    };
+    use chrono::Utc;
+
+    /// Enable TOTP, after which refresh tokens need a code. Recovery codes work once.
+    #[tokio::test]
+    async fn enroll_and_login() {
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+
+        // Start without 2FA from earlier runs
+        sqlx::query!("DELETE FROM users WHERE username = 'totp_user'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        gql_test!(
+            r#"mutation {
+                createUser(newUser: { username: "totp_user", password: "password_of_20_characters" }) {
+                  id
+                }
+              }
+              "#
+        );
+
+        let create_token = |otp: &str| {
+            format!(
+                r#"mutation {{
+                    createRefreshToken(newToken: {{ username: "totp_user", password: "password_of_20_characters"{} }}) {{
+                      tokenString
+                    }}
+                  }}
+                  "#,
+                otp
+            )
+        };
+
+        let jwt = gql_test_login!("totp_user", "password_of_20_characters");
+
+        let enrolled = gql_test!(
+            r#"mutation { enrollTotp(password: "password_of_20_characters") { uri } }"#,
+            Some(&jwt)
+        );
+        assert!(enrolled.data.into_json().unwrap()["enrollTotp"]["uri"]
+            .as_str()
+            .unwrap()
+            .starts_with("otpauth://totp/"));
+
+        let user = sqlx::query_as!(User, "SELECT * FROM users WHERE username = 'totp_user'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let secret = TotpSecret::for_user(&pool, user.id).await.unwrap().unwrap();
+        let code = totp::code(secret.secret(), totp::step(Utc::now().timestamp())).unwrap();
+
+        let confirmed = gql_test!(
+            format!(r#"mutation {{ confirmTotp(code: "{}") }}"#, code),
+            Some(&jwt)
+        );
+        let recovery_code = confirmed.data.into_json().unwrap()["confirmTotp"][0]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // The password alone is not enough anymore
+        assert!(gql_test!(create_token("")).is_err());
+
+        let with_recovery = create_token(&format!(r#", otp: "{}""#, recovery_code));
+
+        assert!(gql_test!(with_recovery.clone()).is_ok());
+        assert!(gql_test!(with_recovery).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use super::regex;
+use crate::{
+    access::password::{self, ForeignAlgorithm},
+    gql::{E, R},
+};
+use async_graphql::{Enum, SimpleObject};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use validator::Validate;
+
+/// Files users can be imported from. Both have the fields of `ImportRow`.
+/// CSV files start with a header row, JSONL files have an object per line.
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ImportFormat {
+    /// Guess the format by a file's extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path.rsplit('.').next()? {
+            "csv" => Some(ImportFormat::Csv),
+            "jsonl" => Some(ImportFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// An user from another system. The password hash is kept as it is,
+/// and replaced with an argon2id hash on the first successful login.
+#[derive(Deserialize, Validate, Debug)]
+struct ImportRow {
+    #[validate(regex(
+        path = "regex::USERNAME",
+        message = "should be 4 to 20 alphanumeric characters"
+    ))]
+    username: String,
+    #[validate(email)]
+    email: Option<String>,
+    display_name: Option<String>,
+    /// Trusted as verified by the other system. Defaults to `false`.
+    email_verified: Option<bool>,
+    /// `bcrypt`, `pbkdf2-sha1`, `pbkdf2-sha256` or `pbkdf2-sha512`.
+    hash_algorithm: ForeignAlgorithm,
+    password_hash: String,
+}
+
+/// A row that was not imported.
+#[derive(SimpleObject, Debug)]
+pub struct ImportFailure {
+    /// Line of the row, starting from 1. The CSV header is line 1.
+    pub line: i32,
+    pub username: Option<String>,
+    pub message: String,
+}
+
+/// The outcome of an import. In a dry run nothing is written,
+/// and `imported` is the amount of rows that would have been.
+#[derive(SimpleObject, Debug)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub imported: i32,
+    pub failures: Vec<ImportFailure>,
+}
+
+/// Parse every row, keeping the line number. A row that can't be parsed is a failure.
+fn parse(format: ImportFormat, data: &str) -> Vec<(i32, Result<ImportRow, String>)> {
+    match format {
+        ImportFormat::Csv => csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data.as_bytes())
+            .deserialize()
+            .enumerate()
+            // Line 1 is the header
+            .map(|(i, row)| (i as i32 + 2, row.map_err(|error| error.to_string())))
+            .collect(),
+        ImportFormat::Jsonl => data
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                (
+                    i as i32 + 1,
+                    serde_json::from_str(line).map_err(|error| error.to_string()),
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Check a row and insert it, unless it's a dry run.
+async fn import_row(
+    pool: &PgPool,
+    row: &ImportRow,
+    seen: &mut HashSet<String>,
+    dry_run: bool,
+) -> R<()> {
+    row.validate()?;
+
+    let password_hash = password::foreign(row.hash_algorithm, &row.password_hash)?;
+
+    // Usernames are unique regardless of case in the other rows
+    if !seen.insert(row.username.to_lowercase()) {
+        return Err(E::Message("Duplicate username in the import.".into()));
+    }
+
+    let existing = sqlx::query!(
+        "SELECT id FROM users WHERE username = $1 OR email = $2",
+        &row.username,
+        row.email.as_deref()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if existing.is_some() {
+        return Err(E::Message(
+            "An user with the username or email exists already.".into(),
+        ));
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO users (username, email, display_name, password_hash, email_verified)
+        VALUES ($1, $2, $3, $4, $5);
+        "#,
+        &row.username,
+        row.email.as_deref(),
+        row.display_name.as_deref(),
+        password_hash,
+        row.email_verified.unwrap_or(false) && row.email.is_some()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Import users with password hashes from another system.
+/// Rows are independent, a failing one is reported and the rest are still imported.
+pub async fn import_users(
+    pool: &PgPool,
+    format: ImportFormat,
+    data: &str,
+    dry_run: bool,
+) -> ImportReport {
+    let mut report = ImportReport {
+        dry_run,
+        imported: 0,
+        failures: vec![],
+    };
+
+    let mut seen = HashSet::new();
+
+    for (line, row) in parse(format, data) {
+        let result = match &row {
+            Ok(row) => import_row(pool, row, &mut seen, dry_run)
+                .await
+                .map_err(|error| error.to_string()),
+            Err(error) => Err(error.clone()),
+        };
+
+        match result {
+            Ok(()) => report.imported += 1,
+            Err(message) => report.failures.push(ImportFailure {
+                line,
+                username: row.ok().map(|row| row.username),
+                message,
+            }),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::SqlxConn, models::user::User, Config, CONF_FILE};
+
+    #[test]
+    fn parse_csv() {
+        let rows = parse(
+            ImportFormat::Csv,
+            "username,email,display_name,email_verified,hash_algorithm,password_hash\n\
+             csv_user,csv@email.com,,true,bcrypt,$2b$04$abc\n\
+             broken_user,,,,md5,abc\n",
+        );
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, 2);
+
+        let row = rows[0].1.as_ref().unwrap();
+        assert_eq!(row.email.as_deref(), Some("csv@email.com"));
+        assert_eq!(row.display_name, None);
+        assert_eq!(row.hash_algorithm, ForeignAlgorithm::Bcrypt);
+
+        // Unknown algorithm
+        assert!(rows[1].1.is_err());
+    }
+
+    #[test]
+    fn parse_jsonl() {
+        let rows = parse(
+            ImportFormat::Jsonl,
+            "{\"username\": \"jsonl_user\", \"hash_algorithm\": \"pbkdf2-sha256\", \"password_hash\": \"1$c2FsdA==$a2V5\"}\n\
+             \n\
+             not json\n",
+        );
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0].1.as_ref().unwrap().hash_algorithm,
+            ForeignAlgorithm::Pbkdf2Sha256
+        );
+        assert_eq!(rows[1].0, 3);
+        assert!(rows[1].1.is_err());
+    }
+
+    /// Import a bcrypt user, which is upgraded to argon2id when they log in.
+    #[tokio::test]
+    async fn import_and_upgrade() {
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+
+        sqlx::query!("DELETE FROM users WHERE username = 'imported_user'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let data = format!(
+            "{{\"username\": \"imported_user\", \"hash_algorithm\": \"bcrypt\", \"password_hash\": \"{}\"}}\n\
+             {{\"username\": \"imported_user\", \"hash_algorithm\": \"bcrypt\", \"password_hash\": \"invalid\"}}\n",
+            bcrypt::hash("imported_password", 4).unwrap()
+        );
+
+        let report = import_users(&pool, ImportFormat::Jsonl, &data, true).await;
+        assert_eq!(report.imported, 1);
+        assert_eq!(report.failures[0].line, 2);
+        assert!(User::from_username(&pool, "imported_user").await.is_err());
+
+        let report = import_users(&pool, ImportFormat::Jsonl, &data, false).await;
+        assert_eq!(report.imported, 1);
+
+        let user = User::from_credentials(
+            &pool,
+            &conf.password,
+            "imported_user".into(),
+            "imported_password".into(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(user.password_hash.starts_with("$argon2id$"));
+
+        // Already exists
+        let report = import_users(&pool, ImportFormat::Jsonl, &data, true).await;
+        assert_eq!(report.imported, 0);
+    }
+}
@@ -0,0 +1,45 @@
+use super::User;
+use crate::{
+    access::jwt::JwtClaims,
+    gql::E,
+    models::{passkey::Passkey, refresh_token::RefreshToken, two_factor::TwoFactor},
+};
+use async_graphql::*;
+use uuid::Uuid;
+
+/// The authenticated caller. Everything is loaded from the database when requested.
+pub struct Me {
+    pub claims: JwtClaims,
+}
+
+#[Object]
+impl Me {
+    /// The current state of the user the JWT was issued to.
+    async fn user(&self, ctx: &Context<'_>) -> std::result::Result<User, E> {
+        Ok(self.claims.load_user(ctx.data::<sqlx::PgPool>()?).await?)
+    }
+
+    /// The user's refresh tokens. If valid is `true`, returns only usable tokens.
+    async fn sessions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = true)] valid: bool,
+    ) -> std::result::Result<Vec<RefreshToken>, E> {
+        RefreshToken::for_user(ctx.data::<sqlx::PgPool>()?, self.claims.sub, valid).await
+    }
+
+    /// The user's registered WebAuthn credentials.
+    async fn passkeys(&self, ctx: &Context<'_>) -> std::result::Result<Vec<Passkey>, E> {
+        Passkey::for_user(ctx.data::<sqlx::PgPool>()?, self.claims.sub).await
+    }
+
+    /// `true` if refresh tokens require a second factor: a passkey, a TOTP or a recovery code.
+    async fn two_factor_enabled(&self, ctx: &Context<'_>) -> std::result::Result<bool, E> {
+        TwoFactor::is_enabled(ctx.data::<sqlx::PgPool>()?, self.claims.sub).await
+    }
+
+    /// The refresh token the JWT of this request was signed with.
+    async fn current_session(&self) -> Uuid {
+        self.claims.parent_token
+    }
+}
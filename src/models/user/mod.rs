@@ -1,16 +1,23 @@
+mod import;
+mod me;
 mod mutation;
 mod query;
 mod regex;
 
+pub use import::{import_users, ImportFormat, ImportReport};
+pub use me::Me;
 pub use mutation::UserMutation;
 pub use query::UserQuery;
 
+use crate::{
+    access::password::{self, Verification},
+    config::PasswordConfig,
+};
 use anyhow::Result;
 use async_graphql::*;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sodiumoxide::crypto::pwhash::argon2id13;
-use sqlx::PgPool;
+use sqlx::{Done, PgPool};
 use tokio::task::spawn_blocking;
 use uuid::Uuid;
 
@@ -23,84 +30,171 @@ pub struct User {
     pub email: Option<String>,
     pub display_name: Option<String>,
     #[graphql(skip)]
+    #[serde(skip)]
     pub password_hash: String,
     pub groups: Vec<String>,
+    /// The user has confirmed they own `email`.
+    pub email_verified: bool,
+    /// A new address waiting for confirmation. Replaces `email` once verified.
+    pub pending_email: Option<String>,
 }
 
 impl User {
-    /// Hash the given password with argon2id13.
+    /// Hash the given password with the configured argon2id parameters.
     /// If used in an asyncronous context, `spawn_blocking` should be used,
     /// since task is CPU intensive.
-    pub fn hash_password<S: Into<String>>(new_password: S) -> Result<String> {
-        sodiumoxide::init().unwrap();
-
-        let hashed = match argon2id13::pwhash(
-            new_password.into().as_bytes(),
-            argon2id13::OPSLIMIT_INTERACTIVE,
-            argon2id13::MEMLIMIT_INTERACTIVE,
-        ) {
-            Ok(h) => h,
-            Err(()) => bail!("Failed to hash password."),
-        };
-
-        Ok(std::str::from_utf8(&hashed.0)?
-            .trim_end_matches('\u{0}')
-            .to_string())
+    pub fn hash_password<S: Into<String>>(
+        conf: &PasswordConfig,
+        new_password: S,
+    ) -> Result<String> {
+        password::hash(conf, &new_password.into())
     }
 
     /// Tests the password against current users hash.
-    /// Returns `true` on a match and `false` if the password is wrong.
+    /// Returns an error if the password is wrong, and whether the hash is outdated if not.
     /// Usage with `spawn_blocking` is preferred.
-    pub fn validate_password<S: Into<String>>(&self, password: S) -> Result<()> {
-        sodiumoxide::init().unwrap();
-
-        let mut hash_padded = [0u8; 128];
-        self.password_hash
-            .as_bytes()
-            .iter()
-            .enumerate()
-            .for_each(|(i, val)| {
-                hash_padded[i] = val.clone();
-            });
-
-        match argon2id13::HashedPassword::from_slice(&hash_padded) {
-            Some(hp) => {
-                // If password is incorrect, return an error
-                if !argon2id13::pwhash_verify(&hp, password.into().as_bytes()) {
-                    bail!("Wrong password")
-                }
-                Ok(())
-            }
-            _ => bail!("Password "),
+    pub fn validate_password<S: Into<String>>(
+        &self,
+        conf: &PasswordConfig,
+        password: S,
+    ) -> Result<Verification> {
+        match password::verify(conf, &self.password_hash, &password.into())? {
+            Verification::Mismatch => bail!("Wrong password"),
+            verification => Ok(verification),
         }
     }
 
+    /// Hash the new password and replace the user's current one.
+    pub async fn set_password(
+        pool: &PgPool,
+        conf: &PasswordConfig,
+        id: Uuid,
+        new_password: String,
+    ) -> Result<()> {
+        let conf = conf.clone();
+        let hashed_password =
+            spawn_blocking(move || User::hash_password(&conf, new_password)).await??;
+
+        sqlx::query!(
+            "UPDATE users SET password_hash = $1, modified = NOW() WHERE id = $2",
+            hashed_password,
+            id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Find an user by their id.
+    pub async fn from_id(pool: &PgPool, id: Uuid) -> Result<User> {
+        Ok(
+            sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", id)
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
+    /// Find an user by their username.
+    pub async fn from_username(pool: &PgPool, username: &str) -> Result<User> {
+        Ok(
+            sqlx::query_as!(User, "SELECT * FROM users WHERE username = $1", username)
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
+    /// Find an user by their email address.
+    pub async fn from_email(pool: &PgPool, email: &str) -> Result<User> {
+        Ok(
+            sqlx::query_as!(User, "SELECT * FROM users WHERE email = $1", email)
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
     /// Find an user by their username and validate that their password is correct.
+    /// `None` if there's no such user or the password is wrong.
+    /// A hash made with outdated parameters is replaced, since the password is known now.
     pub async fn from_credentials(
         pool: &PgPool,
+        conf: &PasswordConfig,
         username: String,
         password: String,
-    ) -> Result<User> {
+    ) -> Result<Option<User>> {
         // Find by username
-        let user = sqlx::query_as!(User, "SELECT * FROM users WHERE username = $1", &username)
-            .fetch_one(pool)
-            .await?;
+        let mut user =
+            match sqlx::query_as!(User, "SELECT * FROM users WHERE username = $1", username)
+                .fetch_optional(pool)
+                .await?
+            {
+                Some(user) => user,
+                None => return Ok(None),
+            };
 
         // Validate the password is correct
-        let c = user.clone();
-        let password = password.clone();
+        let hash_conf = conf.clone();
+        let hash = user.password_hash.clone();
+        let hash_password = password.clone();
 
         // Send to an another thread
-        spawn_blocking(move || return c.validate_password(password)).await??;
+        let verification =
+            spawn_blocking(move || password::verify(&hash_conf, &hash, &hash_password)).await??;
+
+        match verification {
+            Verification::Mismatch => return Ok(None),
+            Verification::Outdated => {
+                user.password_hash = Self::rehash_password(pool, conf, &user, password).await?;
+            }
+            Verification::Current => {}
+        }
 
-        // If there is no error, password is correct
-        Ok(user)
+        Ok(Some(user))
+    }
+
+    /// Replace the user's hash with one made with the current parameters.
+    /// Skipped if the password was changed meanwhile. Returns the hash now stored.
+    async fn rehash_password(
+        pool: &PgPool,
+        conf: &PasswordConfig,
+        user: &User,
+        password: String,
+    ) -> Result<String> {
+        let conf = conf.clone();
+        let hashed_password =
+            spawn_blocking(move || User::hash_password(&conf, password)).await??;
+
+        let updated = sqlx::query!(
+            "UPDATE users SET password_hash = $1 WHERE id = $2 AND password_hash = $3",
+            hashed_password,
+            user.id,
+            user.password_hash
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        if updated == 0 {
+            return Ok(user.password_hash.clone());
+        }
+
+        info!(
+            "Rehashed the password of user {} with current parameters.",
+            user.id
+        );
+
+        Ok(hashed_password)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Config, CONF_FILE};
+
+    fn test_conf() -> PasswordConfig {
+        Config::from_file(CONF_FILE).password
+    }
 
     fn test_user() -> User {
         User {
@@ -110,8 +204,10 @@ mod tests {
             username: "".to_string(),
             email: None,
             display_name: None,
-            password_hash: User::hash_password("a_password").unwrap(),
+            password_hash: User::hash_password(&test_conf(), "a_password").unwrap(),
             groups: vec![],
+            email_verified: false,
+            pending_email: None,
         }
     }
 
@@ -119,13 +215,15 @@ mod tests {
     fn user_password_valid() {
         let user = test_user();
 
-        assert!(user.validate_password("a_password").is_ok());
+        assert!(user.validate_password(&test_conf(), "a_password").is_ok());
     }
 
     #[test]
     fn user_password_invalid() {
         let user = test_user();
 
-        assert!(user.validate_password("wrong_password").is_err());
+        assert!(user
+            .validate_password(&test_conf(), "wrong_password")
+            .is_err());
     }
 }
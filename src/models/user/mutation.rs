@@ -1,10 +1,26 @@
-use super::{regex, User};
+use super::{import_users, regex, ImportFormat, ImportReport, User};
 use crate::{
-    gql::E,
+    access::{
+        authenticated, current_user,
+        guard::{Guard, Permission},
+        password_policy::{self, PasswordOwner},
+        Lockout, JWT,
+    },
+    gql::{E, R},
+    mail::MailQueue,
+    models::{
+        one_time_token::{OneTimeToken, OneTimeTokenKind},
+        refresh_token::RefreshToken,
+        role::permission,
+        security_event::{SecurityEvent, SecurityEventKind},
+    },
     Config,
 };
 use async_graphql::*;
+use chrono::Duration;
+use std::net::IpAddr;
 use tokio::task::spawn_blocking;
+use uuid::Uuid;
 use validator::Validate;
 
 /// A new user with an optional email address.
@@ -20,21 +36,86 @@ struct NewUser {
     username: String,
     #[validate(email)]
     email: Option<String>,
-    #[validate(regex(path = "regex::PASSWORD", message = "should be 20 to 50 characters"))]
+    /// Checked against the password policy.
     password: String,
 }
 
+/// A group to add an user to or remove them from.
+#[derive(Validate, InputObject)]
+struct GroupMembership {
+    user_id: Uuid,
+    #[validate(regex(
+        path = "regex::GROUP",
+        message = "should be 1 to 10 lowercase alphanumeric characters"
+    ))]
+    group: String,
+}
+
+/// The current password and a new one for the authenticated user.
+#[derive(Validate, InputObject)]
+struct PasswordChange {
+    current_password: String,
+    /// Checked against the password policy.
+    new_password: String,
+    /// Revoke every other session, keeping only the one the JWT was signed with.
+    #[graphql(default = false)]
+    revoke_other_sessions: bool,
+}
+
+/// A token from `requestPasswordReset` and the new password.
+#[derive(Validate, InputObject)]
+struct PasswordReset {
+    token: String,
+    /// Checked against the password policy.
+    new_password: String,
+}
+
+/// A new email address for the authenticated user. The password is required.
+#[derive(Validate, InputObject)]
+struct EmailChange {
+    #[validate(email)]
+    new_email: String,
+    password: String,
+}
+
+/// Create a token confirming the address belongs to the user, and send it to the address.
+/// Earlier verification tokens stop working.
+async fn send_email_verification(ctx: &Context<'_>, user: &User, email: &str) -> R<()> {
+    let pool = ctx.data::<sqlx::PgPool>()?;
+    let conf = ctx.data::<Config>()?;
+
+    OneTimeToken::invalidate(pool, OneTimeTokenKind::EmailVerification, user.id).await?;
+
+    let token = OneTimeToken::create(
+        pool,
+        conf.tokens.hash_key.as_bytes(),
+        OneTimeTokenKind::EmailVerification,
+        user.id,
+        Duration::seconds(conf.tokens.email_verification_lifetime),
+        Some(email),
+    )
+    .await?;
+
+    ctx.data::<MailQueue>()?
+        .send(conf.mail.templates.email_verification.render(
+            email,
+            &[
+                ("username", &user.username),
+                ("email", email),
+                ("token", &token),
+            ],
+        ))?;
+
+    Ok(())
+}
+
 #[derive(Default)]
 pub struct UserMutation;
 
 #[Object]
 impl UserMutation {
-    /// Create a new user if registerations are allowed. Rate limited to 5 tries per hour.
-    async fn create_user(
-        &self,
-        ctx: &Context<'_>,
-        new_user: NewUser,
-    ) -> std::result::Result<User, E> {
+    /// Create a new user if registerations are allowed. Rate limited by the `register` policy.
+    async fn create_user(&self, ctx: &Context<'_>, new_user: NewUser) -> Result<User> {
         if !ctx.data::<Config>()?.allow_registerations {
             return Err(Error::new("Registerations not allowed"))?;
         }
@@ -43,27 +124,38 @@ impl UserMutation {
         // Rate limiting only in release builds.
         #[cfg(not(test))]
         {
-            use crate::access::{Identifier, Limiter, RateLimiter};
-            use std::net::IpAddr;
+            use crate::access::{policy, Identifier, RateLimited, RateLimiter};
 
             ctx.data::<RateLimiter>()?
-                .run(
-                    &Limiter::default(Identifier::Address(ctx.data::<IpAddr>()?.clone()))
-                        .register()
-                        .lifetime_seconds(60 * 60)
-                        .full_count(5),
+                .limit(
+                    policy::REGISTER,
+                    Identifier::Address(ctx.data::<IpAddr>()?.clone()),
                 )
-                .await?;
+                .await
+                .map_err(RateLimited::gql_error)?;
         }
 
         new_user.validate()?;
 
+        password_policy::validate(
+            &ctx.data::<Config>()?.password_policy,
+            "password",
+            &new_user.password,
+            PasswordOwner {
+                username: Some(new_user.username.clone()),
+                email: new_user.email.clone(),
+            },
+        )
+        .await?;
+
         let sqlx = ctx.data::<sqlx::PgPool>()?;
 
         let c = new_user.clone();
-        let hashed_password = spawn_blocking(|| User::hash_password(c.password)).await??;
+        let conf = ctx.data::<Config>()?.password.clone();
+        let hashed_password =
+            spawn_blocking(move || User::hash_password(&conf, c.password)).await??;
 
-        Ok(sqlx::query_as!(
+        let user = sqlx::query_as!(
             User,
             "INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3) RETURNING *;",
             new_user.username,
@@ -71,12 +163,429 @@ impl UserMutation {
             hashed_password
         )
         .fetch_one(sqlx)
+        .await?;
+
+        if let Some(email) = &user.email {
+            send_email_verification(ctx, &user, email).await?;
+        }
+
+        Ok(user)
+    }
+
+    /// Confirm an email address with a token sent to it.
+    /// A pending address replaces the old one only now.
+    async fn verify_email(&self, ctx: &Context<'_>, token: String) -> std::result::Result<User, E> {
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let token = OneTimeToken::redeem(
+            pool,
+            ctx.data::<Config>()?.tokens.hash_key.as_bytes(),
+            OneTimeTokenKind::EmailVerification,
+            &token,
+        )
+        .await?;
+
+        let email = token.payload.ok_or(E::InvalidInput)?;
+
+        // Another user might have taken the address after the token was sent
+        let taken = sqlx::query!(
+            "SELECT id FROM users WHERE email = $1 AND id <> $2",
+            email,
+            token.user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        if taken.is_some() {
+            return Err(E::Message("The email address is already in use.".into()));
+        }
+
+        Ok(sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users SET email = $2::VARCHAR, email_verified = TRUE, modified = NOW(),
+                pending_email = CASE WHEN pending_email = $2::VARCHAR
+                    THEN NULL ELSE pending_email END
+            WHERE id = $1 RETURNING *;
+            "#,
+            token.user_id,
+            email
+        )
+        .fetch_one(pool)
+        .await?)
+    }
+
+    /// Change the authenticated user's email address.
+    /// The new address is pending until it's verified, and the current one stays in use.
+    async fn change_email(
+        &self,
+        ctx: &Context<'_>,
+        change: EmailChange,
+    ) -> std::result::Result<User, E> {
+        change.validate()?;
+
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let user = authenticated(ctx)?.load_user(pool).await?;
+        Lockout::from_context(ctx)?
+            .check_password(ctx, &user, change.password)
+            .await?;
+
+        let user = sqlx::query_as!(
+            User,
+            "UPDATE users SET pending_email = $2, modified = NOW() WHERE id = $1 RETURNING *;",
+            user.id,
+            change.new_email
+        )
+        .fetch_one(pool)
+        .await?;
+
+        send_email_verification(ctx, &user, &change.new_email).await?;
+
+        Ok(user)
+    }
+
+    /// Send a new verification token for the pending address,
+    /// or the current one if it's not verified yet.
+    async fn resend_email_verification(&self, ctx: &Context<'_>) -> std::result::Result<bool, E> {
+        let user = current_user(ctx).await?;
+
+        let email = match (&user.pending_email, &user.email) {
+            (Some(pending), _) => pending,
+            (None, Some(email)) if !user.email_verified => email,
+            _ => return Err(E::Message("Nothing to verify.".into())),
+        };
+
+        send_email_verification(ctx, &user, email).await?;
+
+        Ok(true)
+    }
+
+    /// Add an user to a group. Requires the `groups.manage` permission.
+    #[graphql(guard(Permission(name = "permission::MANAGE_GROUPS")))]
+    async fn add_user_to_group(
+        &self,
+        ctx: &Context<'_>,
+        membership: GroupMembership,
+    ) -> std::result::Result<User, E> {
+        membership.validate()?;
+
+        Ok(sqlx::query_as!(
+            User,
+            "UPDATE users
+            SET groups = CASE WHEN $2 = ANY(groups) THEN groups ELSE array_append(groups, $2) END
+            WHERE id = $1
+            RETURNING *;",
+            membership.user_id,
+            membership.group
+        )
+        .fetch_one(ctx.data::<sqlx::PgPool>()?)
+        .await?)
+    }
+
+    /// Remove an user from a group. Requires the `groups.manage` permission.
+    #[graphql(guard(Permission(name = "permission::MANAGE_GROUPS")))]
+    async fn remove_user_from_group(
+        &self,
+        ctx: &Context<'_>,
+        membership: GroupMembership,
+    ) -> std::result::Result<User, E> {
+        membership.validate()?;
+
+        Ok(sqlx::query_as!(
+            User,
+            "UPDATE users SET groups = array_remove(groups, $2) WHERE id = $1 RETURNING *;",
+            membership.user_id,
+            membership.group
+        )
+        .fetch_one(ctx.data::<sqlx::PgPool>()?)
         .await?)
     }
+
+    /// Change the authenticated user's password. The current password is required.
+    async fn change_password(
+        &self,
+        ctx: &Context<'_>,
+        change: PasswordChange,
+    ) -> std::result::Result<bool, E> {
+        change.validate()?;
+
+        let claims = authenticated(ctx)?;
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let user = claims.load_user(pool).await?;
+        Lockout::from_context(ctx)?
+            .check_password(ctx, &user, change.current_password)
+            .await?;
+
+        password_policy::validate(
+            &ctx.data::<Config>()?.password_policy,
+            "new_password",
+            &change.new_password,
+            PasswordOwner {
+                username: Some(user.username.clone()),
+                email: user.email.clone(),
+            },
+        )
+        .await?;
+
+        User::set_password(
+            pool,
+            &ctx.data::<Config>()?.password,
+            user.id,
+            change.new_password,
+        )
+        .await?;
+
+        if change.revoke_other_sessions {
+            let current = RefreshToken::from_id(pool, claims.parent_token, user.id).await?;
+
+            RefreshToken::revoke_user_sessions(
+                pool,
+                ctx.data::<JWT>()?.denylist()?,
+                user.id,
+                Some(current.family_id),
+            )
+            .await?;
+        }
+
+        SecurityEvent::record(
+            pool,
+            SecurityEventKind::PasswordChanged,
+            Some(user.id),
+            Some(ctx.data::<IpAddr>()?.to_string()),
+            format!("Other sessions revoked: {}", change.revoke_other_sessions),
+        )
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Send a single-use password reset token to the email address, if an user has it.
+    /// The response is the same whether the address is found or not.
+    /// Rate limited by the `password_reset` policy.
+    async fn request_password_reset(&self, ctx: &Context<'_>, email: String) -> Result<bool> {
+        #[cfg(not(test))]
+        {
+            use crate::access::{policy, Identifier, RateLimited, RateLimiter};
+
+            ctx.data::<RateLimiter>()?
+                .limit(
+                    policy::PASSWORD_RESET,
+                    Identifier::Address(ctx.data::<IpAddr>()?.clone()),
+                )
+                .await
+                .map_err(RateLimited::gql_error)?;
+        }
+
+        let pool = ctx.data::<sqlx::PgPool>()?.clone();
+        let conf = ctx.data::<Config>()?;
+        let tokens = conf.tokens.clone();
+        let template = conf.mail.templates.password_reset.clone();
+        let mail_queue = ctx.data::<MailQueue>()?.clone();
+
+        // In the background, so the response time doesn't reveal whether the address exists
+        tokio::spawn(async move {
+            let sent = async {
+                let user = match User::from_email(&pool, &email).await {
+                    Ok(user) => user,
+                    Err(_) => return R::Ok(()),
+                };
+
+                let token = OneTimeToken::create(
+                    &pool,
+                    tokens.hash_key.as_bytes(),
+                    OneTimeTokenKind::PasswordReset,
+                    user.id,
+                    Duration::seconds(tokens.password_reset_lifetime),
+                    None,
+                )
+                .await?;
+
+                mail_queue.send(
+                    template.render(&email, &[("username", &user.username), ("token", &token)]),
+                )?;
+
+                Ok(())
+            };
+
+            if let Err(error) = sent.await {
+                error!("Password reset for {} failed: {}", email, error);
+            }
+        });
+
+        Ok(true)
+    }
+
+    /// Set a new password with a token from `requestPasswordReset`.
+    /// Every session of the user is revoked.
+    async fn reset_password(
+        &self,
+        ctx: &Context<'_>,
+        reset: PasswordReset,
+    ) -> std::result::Result<bool, E> {
+        reset.validate()?;
+
+        let pool = ctx.data::<sqlx::PgPool>()?;
+        let policy = &ctx.data::<Config>()?.password_policy;
+
+        // Checked before redeeming too, so a rejected password doesn't use up the token
+        password_policy::validate(
+            policy,
+            "new_password",
+            &reset.new_password,
+            PasswordOwner::default(),
+        )
+        .await?;
+
+        // Used only after the full check, which needs the token's user
+        let token = OneTimeToken::find(
+            pool,
+            ctx.data::<Config>()?.tokens.hash_key.as_bytes(),
+            OneTimeTokenKind::PasswordReset,
+            &reset.token,
+        )
+        .await?;
+
+        let user = User::from_id(pool, token.user_id).await?;
+
+        password_policy::validate(
+            policy,
+            "new_password",
+            &reset.new_password,
+            PasswordOwner {
+                username: Some(user.username),
+                email: user.email,
+            },
+        )
+        .await?;
+
+        let token = token.use_once(pool).await?;
+
+        User::set_password(
+            pool,
+            &ctx.data::<Config>()?.password,
+            token.user_id,
+            reset.new_password,
+        )
+        .await?;
+
+        // Other links sent before this one shouldn't work anymore
+        OneTimeToken::invalidate(pool, OneTimeTokenKind::PasswordReset, token.user_id).await?;
+
+        RefreshToken::revoke_user_sessions(
+            pool,
+            ctx.data::<JWT>()?.denylist()?,
+            token.user_id,
+            None,
+        )
+        .await?;
+
+        SecurityEvent::record(
+            pool,
+            SecurityEventKind::PasswordReset,
+            Some(token.user_id),
+            Some(ctx.data::<IpAddr>()?.to_string()),
+            "Every session revoked",
+        )
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Delete the authenticated user and everything they own. The password is required.
+    /// Sessions are revoked first, so signed JWTs stop working too.
+    async fn delete_account(
+        &self,
+        ctx: &Context<'_>,
+        password: String,
+    ) -> std::result::Result<bool, E> {
+        let claims = authenticated(ctx)?;
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        let user = claims.load_user(pool).await?;
+        Lockout::from_context(ctx)?
+            .check_password(ctx, &user, password)
+            .await?;
+
+        RefreshToken::revoke_user_sessions(pool, ctx.data::<JWT>()?.denylist()?, user.id, None)
+            .await?;
+
+        // Refresh tokens and other owned rows are removed by `ON DELETE CASCADE`
+        sqlx::query!("DELETE FROM users WHERE id = $1", user.id)
+            .execute(pool)
+            .await?;
+
+        SecurityEvent::record(
+            pool,
+            SecurityEventKind::AccountDeleted,
+            None,
+            Some(ctx.data::<IpAddr>()?.to_string()),
+            format!("User {} ({})", user.id, user.username),
+        )
+        .await?;
+
+        Ok(true)
+    }
+
+    /// End the login lockout and back-off of an username. Requires the `users.unlock` permission.
+    /// Returns `false` if the username had no failed attempts.
+    #[graphql(guard(Permission(name = "permission::UNLOCK_USERS")))]
+    async fn unlock_user(
+        &self,
+        ctx: &Context<'_>,
+        username: String,
+    ) -> std::result::Result<bool, E> {
+        let unlocked = Lockout::from_context(ctx)?.unlock(&username).await?;
+
+        if unlocked {
+            let pool = ctx.data::<sqlx::PgPool>()?;
+
+            SecurityEvent::record(
+                pool,
+                SecurityEventKind::AccountUnlocked,
+                User::from_username(pool, &username)
+                    .await
+                    .ok()
+                    .map(|user| user.id),
+                Some(ctx.data::<IpAddr>()?.to_string()),
+                format!("{} unlocked by {}", username, authenticated(ctx)?.sub),
+            )
+            .await?;
+        }
+
+        Ok(unlocked)
+    }
+
+    /// Create users from a CSV or JSONL export of another system, with their password hashes.
+    /// Failing rows are reported, the others are imported. Requires the `users.import` permission.
+    /// Large imports should use the `import-users` command instead.
+    #[graphql(guard(Permission(name = "permission::IMPORT_USERS")))]
+    async fn import_users(
+        &self,
+        ctx: &Context<'_>,
+        format: ImportFormat,
+        data: String,
+        #[graphql(default = false)] dry_run: bool,
+    ) -> std::result::Result<ImportReport, E> {
+        let report = import_users(ctx.data::<sqlx::PgPool>()?, format, &data, dry_run).await;
+
+        info!(
+            "User {} imported {} users, {} failed{}.",
+            authenticated(ctx)?.sub,
+            report.imported,
+            report.failures.len(),
+            if dry_run { " (dry run)" } else { "" }
+        );
+
+        Ok(report)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::macros::unique_name;
+
     /// Creating users should be impossible when `allow_registerations = true` in `config.toml`.
     #[tokio::test]
     async fn disable_registerations() {
@@ -97,13 +606,46 @@ mod tests {
               "#,
         );
 
+        let schema = build_schema(&conf.graphql);
         req = req.data(conf);
 
-        let res = build_schema().execute(req).await;
+        let res = schema.execute(req).await;
 
         assert!(res.is_err());
     }
 
+    /// Only admins can manage group memberships.
+    #[tokio::test]
+    async fn add_to_group_forbidden() {
+        let jwt = gql_test_jwt!();
+
+        let res = gql_test!(
+            r#"mutation {
+                addUserToGroup(membership: { userId: "00000000-0000-0000-0000-000000000000", group: "admin" }) {
+                  id
+                }
+              }
+              "#,
+            Some(&jwt)
+        );
+
+        assert!(res.is_err());
+    }
+
+    /// Guards should reject requests without a JWT.
+    #[tokio::test]
+    async fn add_to_group_unauthenticated() {
+        assert!(gql_test!(
+            r#"mutation {
+                addUserToGroup(membership: { userId: "00000000-0000-0000-0000-000000000000", group: "admin" }) {
+                  id
+                }
+              }
+              "#
+        )
+        .is_err());
+    }
+
     /// Successfully create a new user. Might fail if not using a clean database instance.
     #[tokio::test]
     async fn create_user() {
@@ -117,4 +659,176 @@ mod tests {
         )
         .is_ok());
     }
+
+    /// The current password has to be correct.
+    #[tokio::test]
+    async fn change_password_wrong_current() {
+        let jwt = gql_test_jwt!();
+
+        assert!(gql_test!(
+            r#"mutation {
+                changePassword(change: { currentPassword: "wrong_password", newPassword: "another_password_of_20_characters" })
+              }
+              "#,
+            Some(&jwt)
+        )
+        .is_err());
+    }
+
+    /// Unknown addresses get the same response.
+    #[tokio::test]
+    async fn request_password_reset_unknown_email() {
+        let res = gql_test!(r#"mutation { requestPasswordReset(email: "nobody@email.com") }"#);
+
+        assert_eq!(res.data.into_json().unwrap()["requestPasswordReset"], true);
+    }
+
+    /// Reset the password with a token, which only works once, and delete the account.
+    #[tokio::test]
+    async fn reset_password_and_delete_account() {
+        use super::super::User;
+        use crate::{
+            db::SqlxConn,
+            models::one_time_token::{OneTimeToken, OneTimeTokenKind},
+            Config, CONF_FILE,
+        };
+
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+
+        let username = unique_name("reset_user");
+        let email = format!("{}@email.com", username);
+        gql_test_user!(&username);
+
+        let user = User::from_email(&pool, &email).await.unwrap();
+
+        let token = OneTimeToken::create(
+            &pool,
+            conf.tokens.hash_key.as_bytes(),
+            OneTimeTokenKind::PasswordReset,
+            user.id,
+            chrono::Duration::minutes(5),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let reset = format!(
+            r#"mutation {{
+                resetPassword(reset: {{ token: "{}", newPassword: "new_password_of_20_characters" }})
+              }}
+              "#,
+            token
+        );
+
+        assert!(gql_test!(reset.clone()).is_ok());
+
+        // Single-use
+        assert!(gql_test!(reset).is_err());
+
+        let jwt = gql_test_login!(&username, "new_password_of_20_characters");
+
+        assert!(gql_test!(
+            r#"mutation { deleteAccount(password: "new_password_of_20_characters") }"#,
+            Some(&jwt)
+        )
+        .is_ok());
+
+        assert!(User::from_id(&pool, user.id).await.is_err());
+    }
+
+    /// A verification token confirms the address it was sent to.
+    #[tokio::test]
+    async fn verify_email() {
+        use super::super::User;
+        use crate::{
+            db::SqlxConn,
+            models::one_time_token::{OneTimeToken, OneTimeTokenKind},
+            Config, CONF_FILE,
+        };
+
+        let conf = Config::from_file(CONF_FILE);
+        let pool = SqlxConn::new(&conf).await.into_inner();
+
+        let username = unique_name("verify_user");
+        let email = format!("{}@email.com", username);
+        gql_test_user!(&username);
+
+        let user = User::from_email(&pool, &email).await.unwrap();
+
+        let token = OneTimeToken::create(
+            &pool,
+            conf.tokens.hash_key.as_bytes(),
+            OneTimeTokenKind::EmailVerification,
+            user.id,
+            chrono::Duration::minutes(5),
+            Some(&email),
+        )
+        .await
+        .unwrap();
+
+        let res = gql_test!(format!(
+            r#"mutation {{ verifyEmail(token: "{}") {{ emailVerified }} }}"#,
+            token
+        ));
+
+        assert_eq!(
+            res.data.into_json().unwrap()["verifyEmail"]["emailVerified"],
+            true
+        );
+    }
+
+    /// Changing the address requires the password.
+    #[tokio::test]
+    async fn change_email_wrong_password() {
+        let jwt = gql_test_jwt!();
+
+        assert!(gql_test!(
+            r#"mutation {
+                changeEmail(change: { newEmail: "changed@email.com", password: "wrong_password" }) { id }
+              }
+              "#,
+            Some(&jwt)
+        )
+        .is_err());
+    }
+
+    /// Ending lockouts requires a permission.
+    #[tokio::test]
+    async fn unlock_user_forbidden() {
+        let jwt = gql_test_jwt!();
+
+        assert!(gql_test!(
+            r#"mutation { unlockUser(username: "test_user") }"#,
+            Some(&jwt)
+        )
+        .is_err());
+    }
+
+    /// Importing requires a permission.
+    #[tokio::test]
+    async fn import_users_forbidden() {
+        let jwt = gql_test_jwt!();
+
+        assert!(gql_test!(
+            r#"mutation { importUsers(format: JSONL, data: "", dryRun: true) { imported } }"#,
+            Some(&jwt)
+        )
+        .is_err());
+    }
+
+    /// Twenty spaces are long enough, but not a password.
+    #[tokio::test]
+    async fn create_user_weak_password() {
+        let res = gql_test!(
+            r#"mutation {
+                createUser(newUser: { username: "weak_user", password: "                    " }) {
+                  id
+                }
+              }
+              "#
+        );
+
+        assert!(res.errors[0].message.contains("too easy to guess"));
+    }
 }
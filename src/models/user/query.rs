@@ -1,7 +1,7 @@
-use super::User;
+use super::{Me, User};
 
 use crate::{
-    access::{Identifier, Limiter, RateLimiter},
+    access::{authenticated, policy, Identifier, Lockout, RateLimited, RateLimiter},
     gql::E,
     Config,
 };
@@ -14,25 +14,46 @@ pub struct UserQuery;
 
 #[Object]
 impl UserQuery {
-    /// Get the user with the correct credentials. Rate limited to 10 tried per hour.
-    async fn user(
-        &self,
-        ctx: &Context<'_>,
-        username: String,
-        password: String,
-    ) -> std::result::Result<User, E> {
-        // Limit rate to 10 per hour for every address.
+    /// Get the user with the correct credentials. Rate limited by the `login` policy,
+    /// and failures in a row for the username lead to a temporary lockout.
+    /// Costs 50 towards the complexity, for hashing the password.
+    #[graphql(complexity = 50)]
+    async fn user(&self, ctx: &Context<'_>, username: String, password: String) -> Result<User> {
+        // Limited for every address.
         ctx.data::<RateLimiter>()?
-            .run(
-                &Limiter::default(Identifier::Address(ctx.data::<IpAddr>()?.clone()))
-                    .login()
-                    .lifetime_seconds(60 * 60)
-                    .full_count(10),
+            .limit(
+                policy::LOGIN,
+                Identifier::Address(ctx.data::<IpAddr>()?.clone()),
             )
-            .await?;
+            .await
+            .map_err(RateLimited::gql_error)?;
 
-        // Convert from one result type to another
-        Ok(User::from_credentials(ctx.data::<sqlx::PgPool>()?, username, password).await?)
+        let pool = ctx.data::<sqlx::PgPool>()?;
+
+        Ok(Lockout::from_context(ctx)?
+            .attempt(
+                pool,
+                &username,
+                Some(ctx.data::<IpAddr>()?.to_string()),
+                async {
+                    Ok(User::from_credentials(
+                        pool,
+                        &ctx.data::<Config>()?.password,
+                        username.clone(),
+                        password,
+                    )
+                    .await?
+                    .into())
+                },
+            )
+            .await?)
+    }
+
+    /// The user authenticated with the JWT in the `Authorization` header, and their sessions.
+    async fn me(&self, ctx: &Context<'_>) -> std::result::Result<Me, E> {
+        Ok(Me {
+            claims: authenticated(ctx)?.clone(),
+        })
     }
 
     /// `true` if the creation of new users is enabled.
@@ -70,4 +91,29 @@ mod tests {
         )
         .is_ok());
     }
+
+    /// The authenticated user should be returned without sending the password.
+    #[tokio::test]
+    async fn me() {
+        let username = crate::macros::unique_name("me_user");
+        gql_test_user!(&username);
+        let jwt = gql_test_login!(&username, "password_of_20_characters");
+
+        let res = gql_test!(
+            r#"query { me { user { username } sessions { id } currentSession } }"#,
+            Some(&jwt)
+        );
+
+        assert!(res.is_ok());
+        assert_eq!(
+            res.data.into_json().unwrap()["me"]["user"]["username"],
+            username
+        );
+    }
+
+    /// Without a JWT there is no current user.
+    #[tokio::test]
+    async fn me_unauthenticated() {
+        assert!(gql_test!(r#"query { me { user { id } } }"#).is_err());
+    }
 }
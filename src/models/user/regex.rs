@@ -2,7 +2,7 @@ use regex::Regex;
 
 lazy_static! {
     pub static ref USERNAME: Regex = Regex::new(r"^[A-Za-z0-9_-]{4,20}$").unwrap();
-    pub static ref PASSWORD: Regex = Regex::new(r"^.{20,50}$").unwrap();
+    pub static ref GROUP: Regex = Regex::new(r"^[a-z0-9_-]{1,10}$").unwrap();
 }
 
 #[cfg(test)]
@@ -24,14 +24,19 @@ mod tests {
     }
 
     #[test]
-    fn password_valid() {
-        assert!(PASSWORD.is_match("WDDsKtbvkZK3UjbYwboiV72cVXQ2c8"));
+    fn group_valid() {
+        assert!(GROUP.is_match("admin"));
+
+        assert!(GROUP.is_match("group_2"));
     }
 
     #[test]
-    fn password_invalid() {
-        assert!(!PASSWORD.is_match("too short"));
+    fn group_invalid() {
+        assert!(!GROUP.is_match("Admin"));
+
+        assert!(!GROUP.is_match(""));
 
-        assert!(!PASSWORD.is_match("too long Vo3Qt32cahHt6aTa3urxAwM32L2JrSVo3Qt32cahHt6aTa3urxAwM32L2JrSVo3Qt32cahHt6aTa3urxAwM32L2JrS"));
+        // Longer than the database column
+        assert!(!GROUP.is_match("eleven_char"));
     }
 }
@@ -1,19 +1,29 @@
 use crate::{
-    access::{ClientIP, RateLimiter, UserFromJWT, JWT},
+    access::{
+        ClientIP, Identifier, JwtAudience, Lockout, RateLimitState, RateLimited, RateLimiter,
+        UserCache, UserFromJWT, JWT,
+    },
     db::{RedisConn, SqlxConn},
-    gql::DiaSchema,
+    gql::{CostBudget, DiaSchema, QueryCost},
+    mail::MailQueue,
     Config,
 };
-use actix_web::{guard, web, HttpRequest, HttpResponse, Result, Scope};
+use actix_web::{
+    guard,
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    web, FromRequest, HttpRequest, HttpResponse, Responder, Result, Scope,
+};
 use async_graphql::{
     http::{playground_source, GraphQLPlaygroundConfig},
-    Data, Schema,
+    Data, ErrorExtensions, Schema, ServerError,
 };
 use async_graphql_actix_web::{Request, Response, WSSubscription};
 
 /// Build GQL routes, currently POST for queries and WS, GET for the playground.
-pub fn build() -> Scope {
+/// Tokens are accepted only for the given audience.
+pub fn build(audience: &str) -> Scope {
     web::scope("/gql")
+        .app_data(JwtAudience::new(audience))
         .route("", web::post().to(index))
         .route(
             "",
@@ -25,9 +35,12 @@ pub fn build() -> Scope {
         .route("/sdl", web::get().to(sdl))
 }
 
-/// Normal GraphQL queries as POST requests.
+/// Normal GraphQL queries as POST requests. Each one counts towards the global rate limit,
+/// which is reported in the `RateLimit-*` headers, and its complexity is charged from the
+/// client's cost budget.
 async fn index(
     schema: web::Data<DiaSchema>,
+    http_req: HttpRequest,
     req: Request,
     pg: SqlxConn,
     rd: RedisConn,
@@ -36,29 +49,118 @@ async fn index(
     rl: RateLimiter,
     jwt: JWT,
     user_jwt: UserFromJWT,
-) -> Response {
+) -> Result<HttpResponse> {
+    // Read from the request, actix-web handlers take at most 10 extractors
+    let mail = MailQueue::extract(&http_req).await?;
+    let lockout = Lockout::extract(&http_req).await?;
+    let ip = ip.into_inner();
+
+    let global = match rl.limit_global(ip).await {
+        Ok(global) => global,
+        Err(error) => {
+            let response =
+                async_graphql::Response::from_errors(vec![ServerError::new(error.to_string())]);
+
+            return respond(&http_req, response, None, vec![], false).await;
+        }
+    };
+
+    // Rejected before running anything
+    if let Some(state) = global.as_ref().filter(|state| !state.allowed()) {
+        let response = async_graphql::Response::from_errors(vec![RateLimited(state.clone())
+            .extend()
+            .into_server_error()]);
+
+        return respond(
+            &http_req,
+            response,
+            global.clone(),
+            vec![state.clone()],
+            true,
+        )
+        .await;
+    }
+
     let mut request = req.into_inner();
 
+    // The cost budget is per user when logged in
+    let user = user_jwt.0.as_ref().map(|claims| claims.sub);
+    let client = || user.map_or(Identifier::Address(ip), Identifier::User);
+
+    if let Err(error) = rl.check_cost(client()).await {
+        let rejected = error.is::<RateLimited>();
+        let response = async_graphql::Response::from_errors(vec![
+            RateLimited::gql_error(error).into_server_error()
+        ]);
+
+        return respond(&http_req, response, global, rl.denied(), rejected).await;
+    }
+
+    let cost = QueryCost::default();
+
     let mut data = Data::default();
 
     data.insert(pg.into_inner());
     data.insert(rd.into_inner());
-    data.insert(ip.into_inner());
+    data.insert(ip);
     data.insert(cfg);
-    data.insert(rl);
+    data.insert(rl.clone());
     data.insert(jwt);
+    data.insert(mail);
+    data.insert(lockout);
 
-    // Convert UserFromJWT to User, since context will error out if it doesn't exist
-    if let Some(user) = user_jwt.0 {
-        data.insert(user);
+    // Insert only existing claims, since context will error out if they don't exist.
+    // Resolvers load the live user by `sub` when they need it.
+    if let Some(claims) = user_jwt.0 {
+        data.insert(claims);
     }
 
+    data.insert(UserCache::default());
+    data.insert(cost.clone());
+
     request.data = data;
 
-    schema.execute(request).await.into()
+    let response = schema.execute(request).await;
+
+    if let Err(error) = rl.charge_cost(client(), cost.get() as u64).await {
+        error!("Failed to charge the query cost: {}", error);
+    }
+
+    respond(&http_req, response, global, rl.denied(), false).await
 }
 
-/// Websocket queries and subscriptions.
+/// Add the rate limit headers of the global policy to the response, and `Retry-After` with
+/// the longest wait of any denied request. Requests `rejected` by a rate limit before running
+/// get a `429` status. Errors of denied requests have the extensions of `RateLimited` already.
+async fn respond(
+    req: &HttpRequest,
+    response: async_graphql::Response,
+    global: Option<RateLimitState>,
+    denied: Vec<RateLimitState>,
+    rejected: bool,
+) -> Result<HttpResponse> {
+    let mut http_response = Response::from(response).respond_to(req).await?;
+
+    if rejected {
+        *http_response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    }
+
+    if let Some(state) = &global {
+        state.set_headers(http_response.headers_mut());
+    }
+
+    if let Some(retry_after) = denied.iter().map(RateLimitState::retry_after_seconds).max() {
+        http_response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+
+    Ok(http_response)
+}
+
+/// Websocket queries and subscriptions. Opening the connection counts towards the global
+/// rate limit. Operations are limited like POST queries, and charged from the cost budget
+/// when they're validated.
 async fn ws(
     schema: web::Data<DiaSchema>,
     req: HttpRequest,
@@ -71,21 +173,50 @@ async fn ws(
     jwt: JWT,
     user_jwt: UserFromJWT,
 ) -> Result<HttpResponse> {
+    // Read from the request, actix-web handlers take at most 10 extractors
+    let mail = MailQueue::extract(&req).await?;
+    let lockout = Lockout::extract(&req).await?;
+
     WSSubscription::start_with_initializer(Schema::clone(&*schema), &req, payload, |_| async {
+        let ip = ip.into_inner();
+
+        let global = rl
+            .limit_global(ip)
+            .await
+            .map_err(|error| async_graphql::Error::new(error.to_string()))?;
+
+        if let Some(state) = global.filter(|state| !state.allowed()) {
+            return Err(RateLimited(state).extend());
+        }
+
         let mut data = Data::default();
 
         data.insert(pg.into_inner());
         data.insert(rd.into_inner());
-        data.insert(ip.into_inner());
+        data.insert(ip);
         data.insert(cfg);
+        data.insert(CostBudget {
+            rate_limiter: rl.clone(),
+            client: user_jwt
+                .0
+                .as_ref()
+                .map_or(Identifier::Address(ip), |claims| {
+                    Identifier::User(claims.sub)
+                }),
+        });
         data.insert(rl);
         data.insert(jwt);
+        data.insert(mail);
+        data.insert(lockout);
 
-        // Convert UserFromJWT to User, since context will error out if it doesn't exist
-        if let Some(user) = user_jwt.0 {
-            data.insert(user);
+        // Insert only existing claims, since context will error out if they don't exist.
+        // Resolvers load the live user by `sub` when they need it.
+        if let Some(claims) = user_jwt.0 {
+            data.insert(claims);
         }
 
+        data.insert(UserCache::default());
+
         Ok(data)
     })
 }
@@ -106,12 +237,13 @@ async fn sdl(schema: web::Data<DiaSchema>) -> HttpResponse {
 
 #[cfg(test)]
 mod tests {
-    use crate::routes::build;
+    use crate::{routes::build, Config, CONF_FILE};
     use actix_web::{http, test, App};
 
     #[actix_rt::test]
     async fn gql_load_playground() {
-        let mut app = test::init_service(App::new().service(build())).await;
+        let conf = Config::from_file(CONF_FILE);
+        let mut app = test::init_service(App::new().service(build(&conf))).await;
 
         let req = test::TestRequest::get().uri("/api/gql").to_request();
 
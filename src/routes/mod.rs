@@ -1,10 +1,17 @@
 mod gql;
 mod ping;
+mod well_known;
 
+use crate::Config;
 use actix_web::Scope;
 
-pub fn build() -> Scope {
+pub fn build(conf: &Config) -> Scope {
     Scope::new("/api")
-        .service(gql::build())
+        .service(gql::build(&conf.jwt.audience))
         .service(ping::build())
 }
+
+/// Routes under `/.well-known`, which can't be nested in `/api`.
+pub fn well_known() -> Scope {
+    well_known::build()
+}
@@ -1,10 +1,81 @@
-use crate::res::Res;
-use actix_web::{web, Scope};
+use crate::{
+    access::{ClientIP, RateLimited, RateLimiter},
+    res::Res,
+};
+use actix_web::{http::StatusCode, web, HttpResponse, Scope};
 
 pub fn build() -> Scope {
     Scope::new("/ping").route("", web::get().to(ping))
 }
 
-async fn ping() -> Res<()> {
-    Res::info("pong", None)
+/// Counts towards the global rate limit, so clients can check their limits.
+async fn ping(ip: ClientIP, rl: RateLimiter) -> HttpResponse {
+    let global = match rl.limit_global(ip.into_inner()).await {
+        Ok(global) => global,
+        Err(error) => {
+            error!("Failed to check the rate limit: {}", error);
+
+            return Res::<()>::error("Failed to check the rate limit.")
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .to_response();
+        }
+    };
+
+    let mut response = match &global {
+        Some(state) if !state.allowed() => Res::<()>::error(RateLimited(state.clone()).to_string())
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .to_response(),
+        _ => Res::<()>::info("pong", None).to_response(),
+    };
+
+    if let Some(state) = &global {
+        state.set_headers(response.headers_mut());
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::RedisConn, Config, CONF_FILE};
+    use actix_web::{test, App};
+
+    /// The global policy is reported in the headers.
+    #[actix_rt::test]
+    async fn rate_limit_headers() {
+        let conf = Config::from_file(CONF_FILE);
+        let rl = RateLimiter::new(RedisConn::new(&conf), &conf.rate_limits);
+
+        let mut app = test::init_service(App::new().app_data(rl).service(build())).await;
+
+        let req = test::TestRequest::get()
+            .uri("/ping")
+            .header("X-Forwarded-For", "192.0.2.1")
+            .to_request();
+        let response = test::call_service(&mut app, req).await;
+
+        let headers = response.headers();
+        let limit: u64 = headers
+            .get("RateLimit-Limit")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let remaining: u64 = headers
+            .get("RateLimit-Remaining")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            limit,
+            conf.rate_limits.policies[&conf.rate_limits.global].capacity
+        );
+        assert!(remaining < limit);
+        assert!(headers.contains_key("RateLimit-Reset"));
+    }
 }
@@ -0,0 +1,48 @@
+use crate::{access::JWT, res::Res};
+use actix_web::{http::StatusCode, web, HttpResponse, Scope};
+
+/// Discovery documents served outside of `/api`, at the paths other services expect.
+pub fn build() -> Scope {
+    Scope::new("/.well-known").route("/jwks.json", web::get().to(jwks))
+}
+
+/// The public keys accepted for JWT validation, so other services can verify tokens locally.
+async fn jwks(jwt: JWT) -> HttpResponse {
+    match jwt.jwks() {
+        Ok(jwks) => HttpResponse::Ok()
+            .header("Cache-Control", "public, max-age=60")
+            .json(jwks),
+        Err(error) => {
+            error!("Failed to build the JWKS: {}", error);
+
+            Res::<()>::error("Failed to load the JWT keys.")
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .to_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{access::JWT, routes::well_known};
+    use actix_web::{http, test, App};
+
+    #[actix_rt::test]
+    async fn jwks_lists_keys() {
+        let jwt = JWT::generate().unwrap();
+
+        let mut app = test::init_service(App::new().app_data(jwt).service(well_known())).await;
+
+        let req = test::TestRequest::get()
+            .uri("/.well-known/jwks.json")
+            .to_request();
+
+        let response = test::call_service(&mut app, req).await;
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+
+        let body: serde_json::Value = test::read_body_json(response).await;
+
+        assert_eq!(body["keys"].as_array().unwrap().len(), 1);
+    }
+}